@@ -5,21 +5,106 @@ import { markdown } from "@codemirror/lang-markdown"
 let textarea = document.querySelector('textarea');
 textarea.style.display = "none";
 
+let form = textarea.form;
+let title = form.querySelector('input[name=title]');
+let csrf = form.querySelector('input[name=csrf]')?.value ?? "";
+
+// A preview of the body as it'll look on the item's page, rendered by the
+// server (so it's exactly the same) and kept up to date while typing
+let preview = document.createElement('div');
+preview.className = "item-body preview";
+preview.hidden = true;
+
+let rendering = null;
+
+async function renderPreview() {
+    let response = await fetch('/render', {
+        method: 'POST',
+        body: new URLSearchParams({ csrf, body: view.state.doc.toString() }),
+    });
+    if (response.ok) {
+        preview.innerHTML = await response.text();
+    } else {
+        preview.textContent = `Couldn't render the preview: ${await response.text()}`;
+    }
+}
+
+function schedulePreview() {
+    clearTimeout(rendering);
+    rendering = setTimeout(renderPreview, 300);
+}
+
+function togglePreview() {
+    preview.hidden = !preview.hidden;
+    toggle.textContent = preview.hidden ? "Preview" : "Hide preview";
+    if (!preview.hidden) {
+        renderPreview();
+    }
+}
+
+// Drafts are saved to the server every few seconds while there are changes,
+// and when the tab is hidden or closed, so they survive crashes. Saving the
+// item discards its draft.
+let unsaved = false;
+
+async function saveDraft() {
+    if (!unsaved || !form.dataset.draft) {
+        return;
+    }
+    unsaved = false;
+    try {
+        let response = await fetch(form.dataset.draft, {
+            method: 'POST',
+            body: new URLSearchParams({ csrf, title: title.value, body: view.state.doc.toString() }),
+            keepalive: true,
+        });
+        unsaved ||= !response.ok;
+    } catch (err) {
+        console.warn("Couldn't save draft", err);
+        unsaved = true;
+    }
+}
+
+title.addEventListener('input', () => unsaved = true);
+setInterval(saveDraft, 5000);
+document.addEventListener('visibilitychange', () => {
+    if (document.visibilityState === 'hidden') {
+        saveDraft();
+    }
+});
+
 // Create a CodeMirror editor with the body's contents
 let view = new EditorView({
     doc: textarea.value,
     extensions: [
         EditorView.lineWrapping,
         basicSetup,
-        markdown({})
+        markdown({}),
+        EditorView.updateListener.of((update) => {
+            if (update.docChanged) {
+                unsaved = true;
+                if (!preview.hidden) {
+                    schedulePreview();
+                }
+            }
+        }),
     ],
 });
 textarea.insertAdjacentElement("afterend", view.dom);
+view.dom.insertAdjacentElement("afterend", preview);
+
+let toggle = document.createElement('button');
+toggle.type = "button";
+toggle.className = "preview-toggle";
+toggle.textContent = "Preview";
+toggle.onclick = togglePreview;
+view.dom.insertAdjacentElement("beforebegin", toggle);
+
+hotkey('p', togglePreview);
 
 
 // When submitting the form, update the original body with the new contents
-textarea.parentElement.onsubmit = function () {
+form.onsubmit = function () {
+    unsaved = false;
     textarea.value = view.state.doc;
 }
-
-
@@ -0,0 +1,459 @@
+//! Optional built-in authentication with local user accounts.
+//!
+//! Authentication is off until a user is created with `wannado add-user
+//! NAME`. After that, every page except `/login` needs a session: logging in
+//! sets an HTTP-only session cookie that lasts 30 days, and each session has a
+//! CSRF token that must accompany every request that changes something. Forms
+//! (including file uploads) send it in a hidden `csrf` field and scripts in an
+//! `X-CSRF-Token` header.
+//!
+//! Scripts use API tokens instead, sent as `Authorization: Bearer TOKEN`.
+//! Tokens are created and revoked at `/admin/tokens` or with `wannado token`,
+//! belong to a user or are global, and are either read-only or read-write.
+//! They only work for the endpoints that don't return web pages (see
+//! [`is_api_path`]) and don't need CSRF tokens.
+//!
+//! Passwords are hashed with Argon2, and session and API tokens with SHA-256.
+
+use crate::handlers::MAX_UPLOAD_SIZE;
+use crate::repo::{ApiToken, Repo, Session};
+use crate::{AppError, StatusCode};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::body::{Body, Bytes};
+use axum::extract::{FromRequest, OriginalUri, RequestParts};
+use axum::http::{header, HeaderMap, Method, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect, Response};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+pub const SESSION_COOKIE: &str = "wannado_session";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+const SESSION_LENGTH_IN_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+/// Pages that can be seen without logging in.
+const PUBLIC_PATHS: [&str; 2] = ["/login", "/manifest.webmanifest"];
+
+/// API paths that need a read-write token even to read: backups hold every
+/// user's data and password hash, and quick captures create items.
+const WRITE_ONLY_PATHS: [&str; 2] = ["/admin/backups/", "/capture"];
+
+/// What an API token may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Read,
+    Write,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+        }
+    }
+
+    fn allows(&self, method: &Method, path: &str) -> bool {
+        *self == Scope::Write
+            || (matches!(*method, Method::GET | Method::HEAD)
+                && !WRITE_ONLY_PATHS.iter().any(|p| path.starts_with(p)))
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "write" => Ok(Scope::Write),
+            _ => Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown scope '{}', expected read or write", s),
+            )),
+        }
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Couldn't hash password: {}", e),
+            )
+        })
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// A random 256-bit token, hex-encoded.
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Create an API token. Returns the token itself, which isn't stored and
+/// can't be shown again, along with its record.
+pub fn create_api_token(
+    repo: &mut Repo,
+    name: &str,
+    user: Option<u32>,
+    scope: Scope,
+) -> Result<(String, ApiToken), AppError> {
+    if name.trim().is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("API tokens need a name"),
+        ));
+    }
+    let token = format!("wannado_{}", random_token());
+    let record = repo.add_api_token(name.trim(), user, &hash_token(&token), scope.as_str())?;
+    Ok((token, record))
+}
+
+/// How session and API tokens are stored, so that the database doesn't hold
+/// anything that can be used to log in.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Whether API tokens can be used for `path`: the JSON API, feeds, exports,
+/// downloads and quick capture.
+pub fn is_api_path(path: &str) -> bool {
+    ["/api/", "/attachment/", "/admin/backups/"]
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+        || ["/calendar.ics", "/feed.atom", "/export", "/capture"].contains(&path)
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Check a user's password and start a session for them. Returns the
+/// session's token. Checking the password is slow, so the repo isn't kept
+/// locked while it's done.
+pub fn log_in(repomux: &Mutex<Repo>, name: &str, password: &str) -> Result<String, AppError> {
+    let found = lock(repomux)?.user_password_hash(name)?;
+    match found {
+        Some((user, hash)) if verify_password(password, &hash) => {
+            let token = random_token();
+            let expires = chrono::Utc::now().timestamp() + SESSION_LENGTH_IN_SECONDS;
+            lock(repomux)?.add_session(&hash_token(&token), user, &random_token(), expires)?;
+            Ok(token)
+        }
+        found => {
+            // Take as long for unknown users as for wrong passwords
+            if found.is_none() {
+                hash_password(password)?;
+            }
+            Err((
+                StatusCode::UNAUTHORIZED,
+                String::from("Wrong user name or password"),
+            ))
+        }
+    }
+}
+
+/// A `Set-Cookie` value for the session cookie. An empty token clears it.
+pub fn session_cookie(token: &str) -> String {
+    let max_age = if token.is_empty() {
+        0
+    } else {
+        SESSION_LENGTH_IN_SECONDS
+    };
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        SESSION_COOKIE, token, max_age
+    )
+}
+
+pub fn session_token(headers: &HeaderMap) -> Option<String> {
+    cookie(headers, SESSION_COOKIE)
+}
+
+/// The value of the cookie called `name`, if it was sent.
+pub fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| value.to_owned())
+}
+
+/// Where to go after logging in: `next` if it's a path on this site.
+pub fn safe_redirect(next: Option<&str>) -> String {
+    match next {
+        Some(path) if path.starts_with('/') && !path.starts_with("//") && !path.contains('\\') => {
+            path.to_owned()
+        }
+        _ => String::from("/"),
+    }
+}
+
+/// Middleware that turns away requests without a session (once there are
+/// users) and changes without the session's CSRF token.
+pub async fn require_login(req: Request<Body>, next: Next<Body>) -> Response {
+    match authenticate(req).await {
+        Ok(req) => next.run(req).await,
+        Err(response) => response,
+    }
+}
+
+async fn authenticate(mut req: Request<Body>) -> Result<Request<Body>, Response> {
+    if PUBLIC_PATHS.contains(&req.uri().path()) {
+        return Ok(req);
+    }
+    let repomux = req
+        .extensions()
+        .get::<Arc<Mutex<Repo>>>()
+        .cloned()
+        .expect("The item repo should be added before authentication");
+    let session = {
+        let mut repo = lock(&repomux).map_err(IntoResponse::into_response)?;
+        if !repo.has_users().map_err(IntoResponse::into_response)? {
+            return Ok(req);
+        }
+        if let Some(token) = bearer_token(req.headers()) {
+            let token = repo
+                .use_api_token(&hash_token(token))
+                .map_err(IntoResponse::into_response)?;
+            let token = check_api_token(token, &req).map_err(IntoResponse::into_response)?;
+            req.extensions_mut().insert(token);
+            return Ok(req);
+        }
+        match session_token(req.headers()) {
+            Some(token) => repo
+                .session(&hash_token(&token))
+                .map_err(IntoResponse::into_response)?,
+            None => None,
+        }
+    };
+    let session = match session {
+        Some(session) => session,
+        None => return Err(unauthenticated(&req)),
+    };
+
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        let (token, checked) = csrf_token(req).await?;
+        req = checked;
+        if !token.is_some_and(|t| constant_time_eq(t.as_bytes(), session.csrf.as_bytes())) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Missing or invalid CSRF token; try reloading the page",
+            )
+                .into_response());
+        }
+    }
+    req.extensions_mut().insert(session);
+    Ok(req)
+}
+
+fn lock(repomux: &Mutex<Repo>) -> Result<MutexGuard<'_, Repo>, AppError> {
+    repomux.lock().map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Couldn't lock the item repo: {:?}", e),
+        )
+    })
+}
+
+fn check_api_token(token: Option<ApiToken>, req: &Request<Body>) -> Result<ApiToken, AppError> {
+    let token = token.ok_or((StatusCode::UNAUTHORIZED, String::from("Invalid API token")))?;
+    if !is_api_path(req.uri().path()) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            String::from("API tokens can't be used for web pages"),
+        ));
+    }
+    let scope: Scope = token.scope.parse()?;
+    if !scope.allows(req.method(), req.uri().path()) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("The API token '{}' is read-only", token.name),
+        ));
+    }
+    Ok(token)
+}
+
+fn unauthenticated(req: &Request<Body>) -> Response {
+    if req.method() == Method::GET && !req.uri().path().starts_with("/api/") {
+        // Come back to the list the request was for (see `lists`)
+        let uri = match req.extensions().get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri,
+            None => req.uri(),
+        };
+        let next = serde_urlencoded::to_string([("next", uri.to_string())]).unwrap_or_default();
+        Redirect::to(&format!("/login?{}", next)).into_response()
+    } else {
+        (StatusCode::UNAUTHORIZED, "Log in first").into_response()
+    }
+}
+
+/// The CSRF token sent with a request, from its header or form body, along
+/// with the request to pass on (with its body restored if it had to be read).
+async fn csrf_token(req: Request<Body>) -> Result<(Option<String>, Request<Body>), Response> {
+    fn form_field(src: &[u8]) -> Option<String> {
+        serde_urlencoded::from_bytes::<Vec<(String, String)>>(src)
+            .ok()?
+            .into_iter()
+            .find(|(name, _)| name == "csrf")
+            .map(|(_, value)| value)
+    }
+
+    async fn multipart_field(body: Bytes, boundary: String) -> Option<String> {
+        let mut multipart = multer::Multipart::new(Body::from(body), boundary);
+        while let Some(field) = multipart.next_field().await.ok()? {
+            if field.name() == Some("csrf") {
+                return field.text().await.ok();
+            }
+        }
+        None
+    }
+
+    if let Some(token) = req.headers().get(CSRF_HEADER).and_then(|h| h.to_str().ok()) {
+        return Ok((Some(token.to_owned()), req));
+    }
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    let boundary = multer::parse_boundary(content_type).ok();
+    if boundary.is_none() && !content_type.starts_with("application/x-www-form-urlencoded") {
+        return Ok((None, req));
+    }
+    if boundary.is_some() {
+        // Uploads are read into memory here, so hold them to the same limit
+        // as their handlers
+        let length = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|h| h.to_str().ok()?.parse::<u64>().ok());
+        match length {
+            None => return Err(StatusCode::LENGTH_REQUIRED.into_response()),
+            Some(length) if length > MAX_UPLOAD_SIZE => {
+                return Err(StatusCode::PAYLOAD_TOO_LARGE.into_response())
+            }
+            Some(_) => {}
+        }
+    }
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await.map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Couldn't read request: {}", e),
+        )
+            .into_response()
+    })?;
+    let token = match boundary {
+        Some(boundary) => multipart_field(body.clone(), boundary).await,
+        None => form_field(&body),
+    };
+    Ok((token, Request::from_parts(parts, Body::from(body))))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// The CSRF token for the current session, to put in forms. It's empty if
+/// authentication is off.
+pub struct Csrf(pub String);
+
+#[axum::async_trait]
+impl<B: Send> FromRequest<B> for Csrf {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let session = req.extensions().get::<Session>();
+        Ok(Csrf(session.map(|s| s.csrf.clone()).unwrap_or_default()))
+    }
+}
+
+/// The logged-in user, or the user an API token belongs to. It's `None` if
+/// authentication is off or the token is global.
+pub struct CurrentUser(pub Option<u32>);
+
+#[axum::async_trait]
+impl<B: Send> FromRequest<B> for CurrentUser {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let session = req.extensions().get::<Session>().map(|s| s.user);
+        let token = req.extensions().get::<ApiToken>().and_then(|t| t.user);
+        Ok(CurrentUser(session.or(token)))
+    }
+}
+
+#[test]
+fn test_passwords_and_tokens() -> Result<(), AppError> {
+    let hash = hash_password("correct horse")?;
+    assert!(hash.starts_with("$argon2"));
+    assert!(verify_password("correct horse", &hash));
+    assert!(!verify_password("battery staple", &hash));
+    assert!(!verify_password("correct horse", "not a hash"));
+
+    assert_eq!(random_token().len(), 64);
+    assert_ne!(random_token(), random_token());
+    assert!(constant_time_eq(b"abc", b"abc"));
+    assert!(!constant_time_eq(b"abc", b"abd"));
+    assert!(!constant_time_eq(b"abc", b"ab"));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::COOKIE,
+        format!("theme=dark; {}=s3cret", SESSION_COOKIE)
+            .parse()
+            .unwrap(),
+    );
+    assert_eq!(session_token(&headers).as_deref(), Some("s3cret"));
+
+    assert_eq!(safe_redirect(Some("/item/3?x=1")), "/item/3?x=1");
+    assert_eq!(safe_redirect(Some("//evil.example")), "/");
+    assert_eq!(safe_redirect(Some("https://evil.example")), "/");
+    assert_eq!(safe_redirect(None), "/");
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::AUTHORIZATION, "Bearer wannado_abc".parse().unwrap());
+    assert_eq!(bearer_token(&headers), Some("wannado_abc"));
+    assert_eq!(hash_token("wannado_abc").len(), 64);
+    assert!(is_api_path("/api/items/3"));
+    assert!(is_api_path("/calendar.ics"));
+    // The stats page is HTML, even though it can give JSON too
+    assert!(is_api_path("/api/stats"));
+    assert!(!is_api_path("/stats"));
+    assert!(!is_api_path("/item/3"));
+    assert!(!is_api_path("/admin/tokens"));
+    assert!(Scope::Read.allows(&Method::GET, "/api/items"));
+    assert!(!Scope::Read.allows(&Method::POST, "/api/items"));
+    assert!(!Scope::Read.allows(&Method::GET, "/admin/backups/snapshot.sqlite3"));
+    assert!(!Scope::Read.allows(&Method::GET, "/capture"));
+    assert!(Scope::Write.allows(&Method::DELETE, "/api/items/3"));
+    assert!(Scope::Write.allows(&Method::GET, "/admin/backups/snapshot.sqlite3"));
+    Ok(())
+}
@@ -0,0 +1,266 @@
+//! Take snapshots of the database while the server is running, using
+//! SQLite's online backup API so a snapshot is never caught half-written.
+//!
+//! Snapshots are saved in `WANNADO_BACKUP_DIR` (default `./backups`) every
+//! hour, or every `WANNADO_BACKUP_INTERVAL_IN_SECONDS` (where 0 turns them
+//! off). They're copied through a connection of their own a few pages at a
+//! time, so the server carries on while they're taken. Older snapshots are
+//! thinned out so that one per hour is kept for a day, one per day for a
+//! week, and one per week for four weeks.
+//!
+//! `wannado restore SNAPSHOT` checks that every item in a snapshot can be
+//! read before swapping it in for the current database (which is backed up
+//! first). The server should be stopped while restoring.
+
+use crate::repo::Repo;
+use crate::{AppError, StatusCode};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, TimeZone, Utc};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_FORMAT: &str = "items-%Y%m%dT%H%M%S%.3fZ.sqlite3";
+/// How many pages of the database to copy at a time, and how long to let
+/// other connections at it in between.
+const PAGES_PER_STEP: std::os::raw::c_int = 64;
+const PAUSE_BETWEEN_STEPS: std::time::Duration = std::time::Duration::from_millis(5);
+
+pub struct Snapshot {
+    pub name: String,
+    pub taken: DateTime<Utc>,
+    pub size: i64,
+}
+
+impl Snapshot {
+    pub fn taken_local(&self) -> DateTime<Local> {
+        self.taken.into()
+    }
+}
+
+pub fn backup_dir() -> PathBuf {
+    std::env::var("WANNADO_BACKUP_DIR")
+        .unwrap_or_else(|_| String::from("./backups"))
+        .into()
+}
+
+/// Take snapshots of the database at `db` every so often, unless the
+/// interval is 0.
+pub fn start_scheduled_backups(db: PathBuf) {
+    let interval = if let Ok(src) = std::env::var("WANNADO_BACKUP_INTERVAL_IN_SECONDS") {
+        let seconds = src.parse::<u64>().expect(
+            "Invalid backup interval, expected WANNADO_BACKUP_INTERVAL_IN_SECONDS to be an integer",
+        );
+        std::time::Duration::from_secs(seconds)
+    } else {
+        std::time::Duration::from_secs(60 * 60)
+    };
+    if interval.is_zero() {
+        println!("Scheduled backups are off");
+        return;
+    }
+    let dir = backup_dir();
+    println!("Backing up to {} every {:?}", dir.display(), interval);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        let result = snapshot_database(&db, &dir).and_then(|_| prune(&dir, Utc::now()));
+        if let Err((_, msg)) = result {
+            eprintln!("Error in scheduled backup: {}", msg);
+        }
+    });
+}
+
+/// Take a snapshot of the database at `db` through a connection of its own,
+/// without holding up the server's.
+pub fn snapshot_database(db: &Path, dir: &Path) -> Result<Snapshot, AppError> {
+    use rusqlite::{Connection, OpenFlags};
+
+    let source = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(convert_db_error)?;
+    new_snapshot(dir, |path| {
+        let mut target = Connection::open(path).map_err(convert_db_error)?;
+        rusqlite::backup::Backup::new(&source, &mut target)
+            .and_then(|backup| backup.run_to_completion(PAGES_PER_STEP, PAUSE_BETWEEN_STEPS, None))
+            .map_err(convert_db_error)
+    })
+}
+
+pub fn take_snapshot(repo: &Repo, dir: &Path) -> Result<Snapshot, AppError> {
+    new_snapshot(dir, |path| repo.backup_to(path))
+}
+
+/// Make a new snapshot in `dir` by writing the database to the path given to
+/// `write`.
+fn new_snapshot(
+    dir: &Path,
+    write: impl FnOnce(&Path) -> Result<(), AppError>,
+) -> Result<Snapshot, AppError> {
+    std::fs::create_dir_all(dir).map_err(io_error)?;
+    let taken = Utc::now();
+    let name = taken.format(SNAPSHOT_FORMAT).to_string();
+    let path = dir.join(&name);
+    write(&path)?;
+    let size = std::fs::metadata(&path).map_err(io_error)?.len() as i64;
+    Ok(Snapshot { name, taken, size })
+}
+
+/// Snapshots in `dir`, newest first.
+pub fn snapshots(dir: &Path) -> Result<Vec<Snapshot>, AppError> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut snapshots = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(io_error)? {
+        let entry = entry.map_err(io_error)?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(taken) = parse_snapshot_name(&name) {
+            let size = entry.metadata().map_err(io_error)?.len() as i64;
+            snapshots.push(Snapshot { name, taken, size });
+        }
+    }
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.taken));
+    Ok(snapshots)
+}
+
+/// The path of a snapshot in `dir`, if `name` is a snapshot's name.
+pub fn snapshot_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    parse_snapshot_name(name).map(|_| dir.join(name))
+}
+
+fn parse_snapshot_name(name: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(name, SNAPSHOT_FORMAT)
+        .ok()
+        .map(|naive| Utc.from_utc_datetime(&naive))
+}
+
+/// Delete snapshots that fall outside the retention schedule.
+pub fn prune(dir: &Path, now: DateTime<Utc>) -> Result<(), AppError> {
+    let snapshots = snapshots(dir)?;
+    let taken: Vec<DateTime<Utc>> = snapshots.iter().map(|s| s.taken).collect();
+    let keep = retained(&taken, now);
+    for snapshot in snapshots.iter().filter(|s| !keep.contains(&s.taken)) {
+        std::fs::remove_file(dir.join(&snapshot.name)).map_err(io_error)?;
+    }
+    Ok(())
+}
+
+/// Which of the snapshot times (sorted newest first) to keep: the newest
+/// snapshot, plus the newest in each of the last 24 hours, 7 days and 4
+/// weeks.
+fn retained(taken: &[DateTime<Utc>], now: DateTime<Utc>) -> HashSet<DateTime<Utc>> {
+    // How far back a schedule goes, and which period a snapshot falls in
+    type Schedule = (Duration, fn(&DateTime<Utc>) -> i64);
+
+    let mut keep: HashSet<DateTime<Utc>> = taken.first().copied().into_iter().collect();
+    let schedules: [Schedule; 3] = [
+        (Duration::hours(24), |t| t.timestamp() / 3600),
+        (Duration::days(7), |t| t.num_days_from_ce() as i64),
+        (Duration::weeks(4), |t| {
+            let week = t.iso_week();
+            week.year() as i64 * 100 + week.week() as i64
+        }),
+    ];
+    for (window, period) in schedules {
+        let mut seen = HashSet::new();
+        for t in taken.iter().filter(|t| now - **t < window) {
+            if seen.insert(period(t)) {
+                keep.insert(*t);
+            }
+        }
+    }
+    keep
+}
+
+/// Check a snapshot and swap it in for the database at `db`. Returns the
+/// number of items in the snapshot.
+pub fn restore(snapshot: &Path, db: &Path, dir: &Path) -> Result<usize, AppError> {
+    use rusqlite::{Connection, OpenFlags};
+
+    let cxn = Connection::open_with_flags(snapshot, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| invalid_snapshot(format!("{:?}", e)))?;
+    let integrity: String = cxn
+        .query_row("PRAGMA integrity_check", [], |r| r.get(0))
+        .map_err(|e| invalid_snapshot(format!("{:?}", e)))?;
+    if integrity != "ok" {
+        return Err(invalid_snapshot(integrity));
+    }
+    let count = Repo::new(cxn).active_and_deleted()?.len();
+
+    let staged = db.with_extension("sqlite3.restoring");
+    std::fs::copy(snapshot, &staged).map_err(io_error)?;
+    if db.exists() {
+        let current = Connection::open(db).map_err(|e| invalid_snapshot(format!("{:?}", e)))?;
+        let safety = take_snapshot(&Repo::new(current), dir)?;
+        eprintln!("Backed up the current database to {}", safety.name);
+    }
+    std::fs::rename(&staged, db).map_err(io_error)?;
+    Ok(count)
+}
+
+fn invalid_snapshot(msg: String) -> AppError {
+    (
+        StatusCode::BAD_REQUEST,
+        format!("Invalid snapshot: {}", msg),
+    )
+}
+
+fn convert_db_error(err: rusqlite::Error) -> AppError {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Backup error: {:?}", err),
+    )
+}
+
+fn io_error(err: std::io::Error) -> AppError {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Backup error: {:?}", err),
+    )
+}
+
+#[test]
+fn test_retention() {
+    let now = Utc.timestamp_opt(1_669_809_600, 0).unwrap(); // 2022-11-30 12:00
+    // Every half hour for six weeks
+    let taken: Vec<DateTime<Utc>> = (0..6 * 7 * 48)
+        .map(|i| now - Duration::minutes(30 * i))
+        .collect();
+    let keep = retained(&taken, now);
+    assert!(keep.contains(&now));
+    assert!(keep.len() <= 24 + 7 + 5);
+    assert!(keep.len() >= 24 + 5);
+    assert!(keep.iter().all(|t| now - *t < Duration::weeks(4)));
+    assert!(keep.iter().any(|t| now - *t > Duration::weeks(3)));
+
+    // A lone old snapshot is always kept
+    let old = now - Duration::weeks(10);
+    assert!(retained(&[old], now).contains(&old));
+}
+
+#[test]
+fn test_snapshot_and_restore() -> Result<(), AppError> {
+    let dir = std::env::temp_dir().join(format!("wannado-backup-test-{}", std::process::id()));
+    let db = dir.join("items.sqlite3");
+    std::fs::create_dir_all(&dir).map_err(io_error)?;
+
+    let mut repo = Repo::new(rusqlite::Connection::open(&db).unwrap());
+    repo.init().unwrap();
+    repo.add(crate::repo::DEFAULT_LIST, "Before the snapshot", "", false, false, None)?;
+    let snapshot = take_snapshot(&repo, &dir.join("backups"))?;
+    repo.add(crate::repo::DEFAULT_LIST, "After the snapshot", "", false, false, None)?;
+    // Taken while the server's connection is still open
+    let later = snapshot_database(&db, &dir.join("later"))?;
+    assert!(later.size > 0);
+    drop(repo);
+
+    assert_eq!(snapshots(&dir.join("backups"))?.len(), 1);
+    let path = snapshot_path(&dir.join("backups"), &snapshot.name).unwrap();
+    assert_eq!(restore(&path, &db, &dir.join("backups"))?, 1);
+    let mut restored = Repo::new(rusqlite::Connection::open(&db).unwrap());
+    assert_eq!(restored.all_in(crate::repo::DEFAULT_LIST)?.len(), 1);
+
+    std::fs::write(dir.join("garbage.sqlite3"), b"not a database").map_err(io_error)?;
+    assert!(restore(&dir.join("garbage.sqlite3"), &db, &dir.join("backups")).is_err());
+    assert!(snapshot_path(&dir, "../items.sqlite3").is_none());
+
+    std::fs::remove_dir_all(&dir).map_err(io_error)?;
+    Ok(())
+}
@@ -0,0 +1,163 @@
+//! An [iCalendar] feed of items' deadlines and snoozes, so they show up in
+//! calendar apps.
+//!
+//! Items with a `due: <date>` line become all-day to-dos (`VTODO`); deleted
+//! items with a `restore-on <date>` line become all-day events (`VEVENT`) on
+//! the day they'll come back. UIDs are derived from item ids (and not from the
+//! server's address) so calendar apps can track entries as items change.
+//!
+//! [iCalendar]: https://www.rfc-editor.org/rfc/rfc5545
+
+use crate::markdown;
+use crate::repo::Item;
+use chrono::{NaiveDate, TimeZone, Utc};
+
+/// `base_url` is where the server can be reached, like `https://example.com`,
+/// for links back to items.
+pub fn to_ical(items: &[Item], base_url: &str) -> String {
+    let mut lines = vec![
+        String::from("BEGIN:VCALENDAR"),
+        String::from("VERSION:2.0"),
+        String::from("PRODID:-//Wannado//Wannado//EN"),
+        String::from("X-WR-CALNAME:Wannado"),
+    ];
+    let now = timestamp(Utc::now().timestamp());
+    for item in items {
+        match (
+            item.deleted,
+            markdown::due_date(&item.body),
+            markdown::snoozed_until(&item.body),
+        ) {
+            (None, Some(due), _) => lines.extend(todo(item, due, base_url, &now)),
+            (Some(_), _, Some(until)) => lines.extend(snooze(item, until, base_url, &now)),
+            _ => {}
+        }
+    }
+    lines.push(String::from("END:VCALENDAR"));
+    lines.iter().map(|l| fold(l)).collect()
+}
+
+fn todo(item: &Item, due: NaiveDate, base_url: &str, now: &str) -> Vec<String> {
+    let mut lines = vec![
+        String::from("BEGIN:VTODO"),
+        format!("UID:item-{}@wannado", item.id),
+        format!("DTSTAMP:{}", now),
+        format!("CREATED:{}", timestamp(item.created)),
+        format!(
+            "LAST-MODIFIED:{}",
+            timestamp(item.modified.unwrap_or(item.created))
+        ),
+        format!("DUE;VALUE=DATE:{}", due.format("%Y%m%d")),
+        format!("SUMMARY:{}", escape(&item.title)),
+        format!("DESCRIPTION:{}", escape(&item.body)),
+        format!("URL:{}/item/{}", base_url, item.id),
+        String::from("STATUS:NEEDS-ACTION"),
+    ];
+    if item.important {
+        lines.push(String::from("PRIORITY:1"));
+    }
+    let tags = markdown::tags(&item.body);
+    if !tags.is_empty() {
+        let tags: Vec<String> = tags.iter().map(|t| escape(t)).collect();
+        lines.push(format!("CATEGORIES:{}", tags.join(",")));
+    }
+    lines.push(String::from("END:VTODO"));
+    lines
+}
+
+fn snooze(item: &Item, until: NaiveDate, base_url: &str, now: &str) -> Vec<String> {
+    vec![
+        String::from("BEGIN:VEVENT"),
+        format!("UID:snooze-{}@wannado", item.id),
+        format!("DTSTAMP:{}", now),
+        format!("DTSTART;VALUE=DATE:{}", until.format("%Y%m%d")),
+        format!(
+            "DTEND;VALUE=DATE:{}",
+            until.succ_opt().unwrap_or(until).format("%Y%m%d")
+        ),
+        format!("SUMMARY:{}", escape(&format!("Snoozed: {}", item.title))),
+        format!("DESCRIPTION:{}", escape(&item.body)),
+        format!("URL:{}/deleted/{}", base_url, item.id),
+        String::from("TRANSP:TRANSPARENT"),
+        String::from("END:VEVENT"),
+    ]
+}
+
+fn timestamp(ts: i64) -> String {
+    Utc.timestamp_opt(ts, 0)
+        .single()
+        .map(|dt| dt.format("%Y%m%dT%H%M%SZ").to_string())
+        .unwrap_or_default()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Lines longer than 75 bytes are folded onto continuation lines that start
+/// with a space.
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+#[test]
+fn test_ical() {
+    let item = |id, body: &str, deleted| Item {
+        id,
+        list: String::from(crate::repo::DEFAULT_LIST),
+        title: String::from("Renew passport, finally"),
+        body: String::from(body),
+        important: true,
+        urgent: false,
+        created: 1_666_000_000,
+        modified: None,
+        deleted,
+        restored: None,
+        owner: None,
+        assignees: Vec::new(),
+        created_by: None,
+        modified_by: None,
+        deleted_by: None,
+        restored_by: None,
+    };
+    let items = vec![
+        item(1, "due: 2022-11-03\n\n#travel", None),
+        item(2, "restore-on 2022-12-01", Some(1_666_100_000)),
+        item(3, "No dates here", None),
+        item(4, "due: 2022-11-03", Some(1_666_100_000)),
+    ];
+    let ical = to_ical(&items, "https://todo.example.com");
+    assert!(ical.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(ical.ends_with("END:VCALENDAR\r\n"));
+    assert_eq!(ical.matches("BEGIN:VTODO").count(), 1);
+    assert_eq!(ical.matches("BEGIN:VEVENT").count(), 1);
+    assert!(ical.contains("UID:item-1@wannado\r\n"));
+    assert!(ical.contains("URL:https://todo.example.com/item/1\r\n"));
+    assert!(ical.contains("DUE;VALUE=DATE:20221103\r\n"));
+    assert!(ical.contains("SUMMARY:Renew passport\\, finally\r\n"));
+    assert!(ical.contains("CATEGORIES:travel\r\n"));
+    assert!(ical.contains("UID:snooze-2@wannado\r\n"));
+    assert!(ical.contains("DTSTART;VALUE=DATE:20221201\r\n"));
+    assert!(ical.lines().all(|l| l.len() <= 76));
+
+    let long = fold(&"x".repeat(200));
+    assert_eq!(
+        long.replace("\r\n ", ""),
+        format!("{}\r\n", "x".repeat(200))
+    );
+}
@@ -0,0 +1,113 @@
+//! Quick capture: create an item in one request from a page's title, URL and
+//! selected text.
+//!
+//! `/capture?title=&body=&url=` is the target of the bookmarklet (see
+//! [`bookmarklet`]) and of the Web Share Target in the app manifest (see
+//! [`manifest`]), so phones can share links straight into Wannado. The
+//! selection is quoted in the item's body under a link to the page. A `GET`
+//! shows the new item form filled in, to be confirmed; a `POST` creates the
+//! item.
+
+/// The item to create from a capture.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Capture {
+    pub title: String,
+    pub body: String,
+}
+
+pub fn capture(title: Option<&str>, body: Option<&str>, url: Option<&str>) -> Capture {
+    fn nonempty(s: Option<&str>) -> Option<&str> {
+        s.map(str::trim).filter(|s| !s.is_empty())
+    }
+
+    let mut text = nonempty(body);
+    let mut url = nonempty(url);
+    // Some share sheets put the link in the text rather than the URL.
+    if url.is_none() && text.is_some_and(is_url) {
+        url = text.take();
+    }
+
+    let mut parts = Vec::new();
+    if let Some(url) = url {
+        parts.push(format!("<{}>", url));
+    }
+    if let Some(text) = text {
+        let quoted: Vec<String> = text
+            .lines()
+            .map(|l| format!("> {}", l).trim_end().to_owned())
+            .collect();
+        parts.push(quoted.join("\n"));
+    }
+
+    let title = nonempty(title)
+        .or(url)
+        .or_else(|| text.and_then(|t| t.lines().next()))
+        .unwrap_or("Captured");
+    Capture {
+        title: title.to_owned(),
+        body: parts.join("\n\n"),
+    }
+}
+
+fn is_url(text: &str) -> bool {
+    (text.starts_with("http://") || text.starts_with("https://"))
+        && !text.contains(char::is_whitespace)
+}
+
+/// A `javascript:` URL that sends the current page to `origin`'s capture
+/// endpoint.
+pub fn bookmarklet(origin: &str) -> String {
+    format!(
+        "javascript:(function(){{\
+         var q=function(s){{return encodeURIComponent(s)}};\
+         location.href='{}/capture?title='+q(document.title)\
+         +'&url='+q(location.href)\
+         +'&body='+q(String(window.getSelection()))}})()",
+        origin
+    )
+}
+
+/// The web app manifest, with a share target pointing at the capture
+/// endpoint.
+pub fn manifest() -> serde_json::Value {
+    serde_json::json!({
+        "name": "Wannado",
+        "short_name": "Wannado",
+        "start_url": "/",
+        "display": "standalone",
+        "icons": [
+            {"src": "/static/wannado-favicon.png", "sizes": "32x32", "type": "image/png"}
+        ],
+        "share_target": {
+            "action": "/capture",
+            "method": "GET",
+            "params": {"title": "title", "text": "body", "url": "url"}
+        }
+    })
+}
+
+#[test]
+fn test_capture() {
+    assert_eq!(
+        capture(
+            Some("Rust Book"),
+            Some("Ownership is\nRust's most unique feature. "),
+            Some("https://doc.rust-lang.org/book/")
+        ),
+        Capture {
+            title: String::from("Rust Book"),
+            body: String::from(
+                "<https://doc.rust-lang.org/book/>\n\n> Ownership is\n> Rust's most unique feature."
+            ),
+        }
+    );
+    assert_eq!(
+        capture(None, Some("https://example.com/a"), Some("")),
+        Capture {
+            title: String::from("https://example.com/a"),
+            body: String::from("<https://example.com/a>"),
+        }
+    );
+    assert_eq!(capture(None, None, None).title, "Captured");
+    assert!(bookmarklet("http://localhost:3000").starts_with("javascript:(function(){var q="));
+}
@@ -0,0 +1,414 @@
+//! Sub-commands for working with the database from the command line.
+//!
+//! ```text
+//! wannado export FORMAT [PATH]
+//! wannado import FORMAT PATH [--append]
+//! wannado restore SNAPSHOT
+//! wannado add TITLE [--body BODY] [--important] [--urgent] [--list LIST]
+//! wannado ls [--important] [--urgent] [--query QUERY] [--list LIST]
+//! wannado done ID
+//! wannado edit ID
+//! wannado tui [--list LIST]
+//! wannado add-user NAME
+//! wannado token create NAME [--scope read|write] [--user NAME]
+//! wannado token ls
+//! wannado token revoke ID
+//! ```
+//!
+//! `FORMAT` is one of `json`, `csv` or `markdown`. JSON and CSV exports are
+//! written to standard output if no path is given; Markdown exports and
+//! imports use a directory with one file per item. Imports replace items with
+//! the same id unless `--append` is given, in which case every imported item
+//! is added with a new id.
+//!
+//! `SNAPSHOT` is either the path of a database file or the name of a snapshot
+//! in the backup directory (see the `backup` module).
+//!
+//! The item commands (`add`, `ls`, `done` and `edit`) work on the database
+//! directly, or on a running server if given `--server URL` (see the `client`
+//! module). `edit` opens the item's body in `$VISUAL` or `$EDITOR`, and
+//! `--json` prints items as JSON instead of one line each. `add`, `ls` and
+//! `tui` use the default list unless given `--list` (see the `lists` module).
+//! `ls --query` lists the items matching a query, like
+//! `"urgent AND tag:ops"` (see the `query` module).
+//! `tui` starts the terminal UI (see the `tui` module).
+//!
+//! `add-user` asks for a password and creates a user; once there are users the
+//! web app asks them to log in (see the `auth` module). `token` manages API
+//! tokens; new tokens are read-only and global unless told otherwise. The
+//! item commands send the token in `--token` or `WANNADO_TOKEN` to the server.
+
+use crate::auth;
+use crate::backup;
+use crate::client::Client;
+use crate::handlers::ItemFields;
+use crate::repo::{Item, DEFAULT_LIST};
+use crate::transfer::{self, Format, Mode};
+use crate::{AppError, StatusCode};
+use chrono::TimeZone;
+use std::path::Path;
+
+/// Run the sub-command named in `args` (not including the program name).
+/// Returns `None` if `args` isn't a sub-command.
+pub fn run(args: &[String]) -> Option<Result<(), AppError>> {
+    let (command, rest) = args.split_first()?;
+    match command.as_str() {
+        "export" => Some(export(rest)),
+        "import" => Some(import(rest)),
+        "restore" => Some(restore(rest)),
+        "add" => Some(add(rest)),
+        "ls" => Some(ls(rest)),
+        "done" => Some(done(rest)),
+        "edit" => Some(edit(rest)),
+        "tui" => Some(tui(rest)),
+        "add-user" => Some(add_user(rest)),
+        "token" => Some(token(rest)),
+        _ => None,
+    }
+}
+
+fn export(args: &[String]) -> Result<(), AppError> {
+    let (format, path) = match args {
+        [format] => (format.parse()?, None),
+        [format, path] => (format.parse()?, Some(Path::new(path))),
+        _ => return Err(usage("wannado export FORMAT [PATH]")),
+    };
+    let mut repo = crate::open_repo();
+    let items = repo.active_and_deleted()?;
+    let output = match (format, path) {
+        (Format::Json, _) => transfer::to_json(&items)?,
+        (Format::Csv, _) => transfer::to_csv(&items)?,
+        (Format::Markdown, Some(dir)) => {
+            transfer::write_markdown_dir(&items, dir)?;
+            eprintln!("Exported {} items to {}", items.len(), dir.display());
+            return Ok(());
+        }
+        (Format::Markdown, None) => {
+            return Err(usage("wannado export markdown DIRECTORY"));
+        }
+    };
+    match path {
+        Some(path) => std::fs::write(path, output).map_err(file_error)?,
+        None => println!("{}", output),
+    }
+    Ok(())
+}
+
+fn import(args: &[String]) -> Result<(), AppError> {
+    let (format, path, mode): (Format, _, _) = match args {
+        [format, path] => (format.parse()?, Path::new(path), Mode::Merge),
+        [format, path, flag] if flag == "--append" => {
+            (format.parse()?, Path::new(path), Mode::Append)
+        }
+        _ => return Err(usage("wannado import FORMAT PATH [--append]")),
+    };
+    let items = match format {
+        Format::Json => transfer::from_json(&std::fs::read_to_string(path).map_err(file_error)?)?,
+        Format::Csv => transfer::from_csv(&std::fs::read_to_string(path).map_err(file_error)?)?,
+        Format::Markdown => transfer::read_markdown_dir(path)?,
+    };
+    let mut repo = crate::open_repo();
+    let count = transfer::import(&mut repo, &items, mode)?;
+    eprintln!("Imported {} items", count);
+    Ok(())
+}
+
+fn restore(args: &[String]) -> Result<(), AppError> {
+    let [snapshot] = args else {
+        return Err(usage("wannado restore SNAPSHOT"));
+    };
+    let dir = backup::backup_dir();
+    let path = match backup::snapshot_path(&dir, snapshot) {
+        Some(path) if path.exists() => path,
+        _ => Path::new(snapshot).to_owned(),
+    };
+    let count = backup::restore(&path, Path::new(crate::DATABASE), &dir)?;
+    eprintln!("Restored {} items from {}", count, path.display());
+    Ok(())
+}
+
+fn add(args: &[String]) -> Result<(), AppError> {
+    let opts = Options::parse(args)?;
+    if opts.positional.is_empty() {
+        return Err(usage(
+            "wannado add TITLE [--body BODY] [--important] [--urgent] [--list LIST]",
+        ));
+    }
+    let mut client = opts.client();
+    let item = client.add(&ItemFields {
+        title: opts.positional.join(" "),
+        body: opts.body.clone().unwrap_or_default(),
+        important: opts.important,
+        urgent: opts.urgent,
+    })?;
+    opts.print(&item, "Added")
+}
+
+fn ls(args: &[String]) -> Result<(), AppError> {
+    let opts = Options::parse(args)?;
+    if !opts.positional.is_empty() {
+        return Err(usage(
+            "wannado ls [--important] [--urgent] [--query QUERY] [--list LIST]",
+        ));
+    }
+    let mut client = opts.client();
+    let mut items: Vec<Item> = client
+        .list(opts.query.as_deref())?
+        .into_iter()
+        .filter(|i| (i.important || !opts.important) && (i.urgent || !opts.urgent))
+        .collect();
+    items.sort_by_key(|i| i.id);
+    if opts.json {
+        println!("{}", to_json(&items)?);
+    } else {
+        for item in &items {
+            println!("{}", summary(item));
+        }
+    }
+    Ok(())
+}
+
+fn done(args: &[String]) -> Result<(), AppError> {
+    let opts = Options::parse(args)?;
+    let id = opts.id("wannado done ID")?;
+    let mut client = opts.client();
+    let item = client.done(id)?;
+    opts.print(&item, "Done")
+}
+
+fn edit(args: &[String]) -> Result<(), AppError> {
+    let opts = Options::parse(args)?;
+    let id = opts.id("wannado edit ID")?;
+    let mut client = opts.client();
+    let mut item = client.get(id)?;
+    let body = edit_in_editor(&item.body, id)?;
+    if body == item.body {
+        return opts.print(&item, "No changes to");
+    }
+    item.body = body;
+    client.update(&mut item)?;
+    opts.print(&item, "Updated")
+}
+
+fn tui(args: &[String]) -> Result<(), AppError> {
+    let opts = Options::parse(args)?;
+    if !opts.positional.is_empty() {
+        return Err(usage("wannado tui [--list LIST]"));
+    }
+    crate::tui::run(opts.list())
+}
+
+/// Flags and positional arguments for the item commands.
+#[derive(Default)]
+struct Options {
+    positional: Vec<String>,
+    body: Option<String>,
+    server: Option<String>,
+    token: Option<String>,
+    list: Option<String>,
+    query: Option<String>,
+    important: bool,
+    urgent: bool,
+    json: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, AppError> {
+        let mut opts = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| usage(&format!("{} needs a value", name)))
+            };
+            match arg.as_str() {
+                "--body" => opts.body = Some(value("--body")?),
+                "--server" => opts.server = Some(value("--server")?),
+                "--token" => opts.token = Some(value("--token")?),
+                "--list" => opts.list = Some(value("--list")?),
+                "--query" | "-q" => opts.query = Some(value("--query")?),
+                "--important" | "-i" => opts.important = true,
+                "--urgent" | "-u" => opts.urgent = true,
+                "--json" => opts.json = true,
+                flag if flag.starts_with('-') => {
+                    return Err(usage(&format!("unknown option {}", flag)));
+                }
+                _ => opts.positional.push(arg.clone()),
+            }
+        }
+        Ok(opts)
+    }
+
+    fn client(&self) -> Client {
+        Client::connect(self.server.as_deref(), self.token.as_deref(), self.list())
+    }
+
+    fn list(&self) -> &str {
+        self.list.as_deref().unwrap_or(DEFAULT_LIST)
+    }
+
+    fn id(&self, usage_msg: &str) -> Result<u32, AppError> {
+        match &self.positional[..] {
+            [id] => id.trim_start_matches('#').parse().map_err(|_| usage(usage_msg)),
+            _ => Err(usage(usage_msg)),
+        }
+    }
+
+    fn print(&self, item: &Item, action: &str) -> Result<(), AppError> {
+        if self.json {
+            println!("{}", to_json(item)?);
+        } else {
+            println!("{} {}", action, summary(item));
+        }
+        Ok(())
+    }
+}
+
+/// One line describing an item, like `#42 I- Renew passport`.
+fn summary(item: &Item) -> String {
+    format!(
+        "#{} {}{} {}",
+        item.id,
+        if item.important { 'I' } else { '-' },
+        if item.urgent { 'U' } else { '-' },
+        item.title
+    )
+}
+
+fn to_json<T: serde::Serialize + ?Sized>(value: &T) -> Result<String, AppError> {
+    serde_json::to_string_pretty(value).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Couldn't serialize items: {:?}", e),
+        )
+    })
+}
+
+/// Let the user edit `text` in their editor, returning the edited text.
+pub fn edit_in_editor(text: &str, id: u32) -> Result<String, AppError> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    let path = std::env::temp_dir().join(format!("wannado-{}-{}.md", id, std::process::id()));
+    std::fs::write(&path, text).map_err(file_error)?;
+    // Run the editor through the shell so EDITOR can include arguments
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .status()
+        .map_err(file_error)?;
+    let edited = std::fs::read_to_string(&path).map_err(file_error);
+    let _ = std::fs::remove_file(&path);
+    if !status.success() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Editor exited with {}, leaving the item unchanged", status),
+        ));
+    }
+    edited
+}
+
+fn add_user(args: &[String]) -> Result<(), AppError> {
+    let [name] = args else {
+        return Err(usage("wannado add-user NAME"));
+    };
+    let password = rpassword::prompt_password("Password: ").map_err(file_error)?;
+    if password.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, String::from("Empty password")));
+    }
+    let confirmation = rpassword::prompt_password("Password again: ").map_err(file_error)?;
+    if password != confirmation {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("Passwords didn't match"),
+        ));
+    }
+    let mut repo = crate::open_repo();
+    repo.add_user(name, &auth::hash_password(&password)?)?;
+    eprintln!("Added user {}", name);
+    Ok(())
+}
+
+fn token(args: &[String]) -> Result<(), AppError> {
+    const USAGE: &str = "wannado token create NAME [--scope read|write] [--user NAME]
+       wannado token ls
+       wannado token revoke ID";
+    let mut repo = crate::open_repo();
+    match args {
+        [command, name, options @ ..] if command == "create" => {
+            let mut scope = auth::Scope::Read;
+            let mut user = None;
+            for option in options.chunks(2) {
+                match option {
+                    [flag, value] if flag == "--scope" => scope = value.parse()?,
+                    [flag, value] if flag == "--user" => {
+                        let (id, _) = repo.user_password_hash(value)?.ok_or((
+                            StatusCode::NOT_FOUND,
+                            format!("No user called '{}'", value),
+                        ))?;
+                        user = Some(id);
+                    }
+                    _ => return Err(usage(USAGE)),
+                }
+            }
+            let (token, _) = auth::create_api_token(&mut repo, name, user, scope)?;
+            eprintln!("Created API token '{}'. It won't be shown again:", name);
+            println!("{}", token);
+        }
+        [command] if command == "ls" => {
+            for token in repo.api_tokens()? {
+                println!(
+                    "{}\t{}\t{}\t{}\tlast used {}",
+                    token.id,
+                    token.name,
+                    token.scope,
+                    token.user_name.as_deref().unwrap_or("(global)"),
+                    token
+                        .last_used
+                        .and_then(|ts| chrono::Utc.timestamp_opt(ts, 0).single())
+                        .map(|dt| dt.to_rfc3339())
+                        .unwrap_or_else(|| String::from("never"))
+                );
+            }
+        }
+        [command, id] if command == "revoke" => {
+            let id = id.parse().map_err(|_| usage(USAGE))?;
+            repo.revoke_api_token(id, None)?;
+            eprintln!("Revoked API token {}", id);
+        }
+        _ => return Err(usage(USAGE)),
+    }
+    Ok(())
+}
+
+fn usage(msg: &str) -> AppError {
+    (StatusCode::BAD_REQUEST, format!("usage: {}", msg))
+}
+
+fn file_error(err: std::io::Error) -> AppError {
+    (StatusCode::BAD_REQUEST, format!("File error: {}", err))
+}
+
+#[test]
+fn test_options() {
+    let args: Vec<String> = ["buy", "milk", "-i", "--body", "2 litres", "--json"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let opts = Options::parse(&args).unwrap();
+    assert_eq!(opts.positional, ["buy", "milk"]);
+    assert_eq!(opts.body.as_deref(), Some("2 litres"));
+    assert!(opts.important && !opts.urgent && opts.json);
+    assert!(Options::parse(&[String::from("--body")]).is_err());
+    assert!(Options::parse(&[String::from("--bogus")]).is_err());
+
+    let args = [String::from("-q"), String::from("urgent AND #ops")];
+    let opts = Options::parse(&args).unwrap();
+    assert_eq!(opts.query.as_deref(), Some("urgent AND #ops"));
+
+    let opts = Options::parse(&[String::from("#42")]).unwrap();
+    assert_eq!(opts.id("").unwrap(), 42);
+}
@@ -0,0 +1,141 @@
+//! Access to items for the command-line client, either directly through the
+//! database or through a running server's JSON API (`/api/items`).
+//!
+//! The server is used if `--server URL` is given or `WANNADO_SERVER` is set;
+//! otherwise the client opens the database itself. If the server needs
+//! logging in, pass an API token with `--token` or `WANNADO_TOKEN`. Items are
+//! in the default list unless another is given with `--list`.
+
+use crate::handlers::ItemFields;
+use crate::lists;
+use crate::repo::{Item, Repo};
+use crate::{AppError, StatusCode};
+
+pub enum Client {
+    Local { repo: Repo, list: String },
+    Remote { url: String, token: Option<String> },
+}
+
+impl Client {
+    pub fn connect(server: Option<&str>, token: Option<&str>, list: &str) -> Client {
+        match server
+            .map(String::from)
+            .or_else(|| std::env::var("WANNADO_SERVER").ok())
+        {
+            Some(url) => Client::Remote {
+                url: format!("{}{}", url.trim_end_matches('/'), lists::base(list)),
+                token: token
+                    .map(String::from)
+                    .or_else(|| std::env::var("WANNADO_TOKEN").ok()),
+            },
+            None => Client::Local {
+                repo: crate::open_repo(),
+                list: list.to_owned(),
+            },
+        }
+    }
+
+    /// Every un-deleted item in the list, or the ones matching a query (see
+    /// the `query` module).
+    pub fn list(&mut self, query: Option<&str>) -> Result<Vec<Item>, AppError> {
+        match (self, query) {
+            (Client::Local { repo, list }, None) => repo.all_in(list),
+            (Client::Local { repo, list }, Some(query)) => repo.search(list, &query.parse()?),
+            (client, None) => receive(client.request("GET", "/api/items").call()),
+            (client, Some(query)) => {
+                receive(client.request("GET", "/api/items").query("q", query).call())
+            }
+        }
+    }
+
+    pub fn get(&mut self, id: u32) -> Result<Item, AppError> {
+        match self {
+            Client::Local { repo, .. } => repo.get(id),
+            Client::Remote { .. } => {
+                receive(self.request("GET", &format!("/api/items/{}", id)).call())
+            }
+        }
+    }
+
+    pub fn add(&mut self, fields: &ItemFields) -> Result<Item, AppError> {
+        match self {
+            Client::Local { repo, list } => {
+                if !repo.has_list(list)? {
+                    return Err((StatusCode::NOT_FOUND, format!("No list called '{}'", list)));
+                }
+                repo.add(list, &fields.title, &fields.body, fields.important, fields.urgent, None)
+            }
+            Client::Remote { .. } => receive(self.request("POST", "/api/items").send_json(fields)),
+        }
+    }
+
+    pub fn update(&mut self, item: &mut Item) -> Result<(), AppError> {
+        match self {
+            Client::Local { repo, .. } => repo.update(item, None),
+            Client::Remote { .. } => {
+                let fields = ItemFields {
+                    title: item.title.clone(),
+                    body: item.body.clone(),
+                    important: item.important,
+                    urgent: item.urgent,
+                };
+                let path = format!("/api/items/{}", item.id);
+                *item = receive(self.request("PUT", &path).send_json(fields))?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Mark an item as done, which deletes it (it can still be restored).
+    pub fn done(&mut self, id: u32) -> Result<Item, AppError> {
+        let item = self.get(id)?;
+        match self {
+            Client::Local { repo, .. } => repo.delete(&id, None)?,
+            Client::Remote { .. } => {
+                self.request("DELETE", &format!("/api/items/{}", id))
+                    .call()
+                    .map_err(server_error)?;
+            }
+        }
+        Ok(item)
+    }
+
+    /// A request to the server, with the API token if there is one.
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let (url, token) = match self {
+            Client::Remote { url, token } => (url.as_str(), token.as_deref()),
+            Client::Local { .. } => unreachable!("Local clients don't make requests"),
+        };
+        let request = ureq::request(method, &format!("{}{}", url, path));
+        match token {
+            Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+}
+
+fn receive<T: serde::de::DeserializeOwned>(
+    response: Result<ureq::Response, ureq::Error>,
+) -> Result<T, AppError> {
+    response.map_err(server_error)?.into_json().map_err(|e| {
+        (
+            StatusCode::BAD_GATEWAY,
+            format!("Unexpected response from server: {}", e),
+        )
+    })
+}
+
+fn server_error(err: ureq::Error) -> AppError {
+    match err {
+        ureq::Error::Status(code, response) => (
+            StatusCode::from_u16(code).unwrap_or(StatusCode::BAD_GATEWAY),
+            response
+                .into_string()
+                .unwrap_or_else(|_| format!("Server responded with {}", code)),
+        ),
+        ureq::Error::Transport(e) => (
+            StatusCode::BAD_GATEWAY,
+            format!("Couldn't reach server: {}", e),
+        ),
+    }
+}
@@ -0,0 +1,121 @@
+//! Recent activity on items, for the Atom feed.
+//!
+//! Items only record when they were last created, modified, deleted and
+//! restored, so the feed shows the most recent of each of those for every
+//! item rather than a complete history.
+
+use crate::repo::Item;
+
+/// How many entries the feed shows.
+pub const FEED_LENGTH: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Created,
+    Edited,
+    Deleted,
+    Restored,
+}
+
+impl Activity {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Activity::Created => "Created",
+            Activity::Edited => "Edited",
+            Activity::Deleted => "Deleted",
+            Activity::Restored => "Restored",
+        }
+    }
+
+    pub fn slug(&self) -> &'static str {
+        match self {
+            Activity::Created => "created",
+            Activity::Edited => "edited",
+            Activity::Deleted => "deleted",
+            Activity::Restored => "restored",
+        }
+    }
+}
+
+pub struct Event {
+    pub activity: Activity,
+    pub at: i64,
+    pub item: Item,
+}
+
+/// The most recent activity on `items`, newest first.
+pub fn recent_activity(items: Vec<Item>) -> Vec<Event> {
+    // Deleting and restoring an item also updates its modified time, so
+    // only count a modification as an edit if it happened at another time.
+    fn same_time(a: i64, b: Option<i64>) -> bool {
+        b.is_some_and(|b| (a - b).abs() <= 1)
+    }
+
+    let mut events = Vec::new();
+    for item in items {
+        let mut activity = vec![(Activity::Created, item.created)];
+        if let Some(modified) = item.modified {
+            if !same_time(modified, item.deleted) && !same_time(modified, item.restored) {
+                activity.push((Activity::Edited, modified));
+            }
+        }
+        if let Some(deleted) = item.deleted {
+            activity.push((Activity::Deleted, deleted));
+        }
+        if let Some(restored) = item.restored {
+            activity.push((Activity::Restored, restored));
+        }
+        events.extend(activity.into_iter().map(|(activity, at)| Event {
+            activity,
+            at,
+            item: item.clone(),
+        }));
+    }
+    events.sort_by_key(|e| std::cmp::Reverse(e.at));
+    events.truncate(FEED_LENGTH);
+    events
+}
+
+#[test]
+fn test_recent_activity() {
+    let item = |id, modified, deleted, restored| Item {
+        id,
+        list: String::from(crate::repo::DEFAULT_LIST),
+        title: format!("Item {}", id),
+        body: String::new(),
+        important: false,
+        urgent: false,
+        created: 100,
+        modified,
+        deleted,
+        restored,
+        owner: None,
+        assignees: Vec::new(),
+        created_by: None,
+        modified_by: None,
+        deleted_by: None,
+        restored_by: None,
+    };
+    let items = vec![
+        item(1, None, None, None),
+        item(2, Some(200), None, None),
+        item(3, Some(300), Some(300), None),
+        item(4, Some(401), None, Some(400)),
+        item(5, Some(600), None, Some(500)),
+    ];
+    let activity: Vec<(u32, Activity)> = recent_activity(items)
+        .iter()
+        .map(|e| (e.item.id, e.activity))
+        .collect();
+    assert_eq!(
+        &activity[..5],
+        &[
+            (5, Activity::Edited),
+            (5, Activity::Restored),
+            (4, Activity::Restored),
+            (3, Activity::Deleted),
+            (2, Activity::Edited),
+        ]
+    );
+    assert_eq!(activity.len(), 10);
+}
@@ -1,92 +1,373 @@
 use axum::{
-    extract::{Extension, Form, Path},
-    http::StatusCode,
-    response::{Html, Redirect},
+    extract::{ContentLengthLimit, Extension, Form, Multipart, OriginalUri, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Json,
 };
+use chrono::Local;
 use std::sync::{Arc, Mutex, MutexGuard};
 
+use crate::auth::{self, Csrf, CurrentUser};
+use crate::lists::{self, List};
 use crate::AppError;
-use crate::{repo, template};
+use crate::{backup, calendar, capture, feed, importers, item_templates, markdown, query, repo, stats, template, transfer, view_options};
 
+/// The home page. View options given in the query are kept in a cookie, and
+/// used when there aren't any (see `view_options`).
 pub(crate) async fn get_items(
     Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
-) -> Result<Html<String>, AppError> {
-    let mut repo = lock_repo(&repomux)?;
-    let mut items = repo.all()?;
-    items.sort_by_key(|i| (i.modified, i.created));
-    let viewmodel = template::ItemsList::from_items(&items)?;
+    CurrentUser(me): CurrentUser,
+    list: List,
+    Csrf(csrf): Csrf,
+    Query(params): Query<ItemsParams>,
+    Query(view): Query<view_options::ViewParams>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let users = repo.users()?;
+    let who = params.who.as_deref().unwrap_or("everyone");
+    let shown = match who {
+        "everyone" => None,
+        // Without a user, "mine" would quietly show everyone's items
+        "mine" => Some(me.ok_or((
+            StatusCode::BAD_REQUEST,
+            String::from("Log in to see your own items"),
+        ))?),
+        name => Some(user_id(&users, name)?),
+    };
+    let (options, cookie) = if view.is_empty() {
+        let options = auth::cookie(&headers, view_options::VIEW_COOKIE)
+            .and_then(|query| view_options::ViewOptions::from_query(&query))
+            .unwrap_or_default();
+        (options, None)
+    } else {
+        let options = view_options::ViewOptions::from_params(&view)?;
+        let cookie = view_cookie(&options.query());
+        (options, Some(cookie))
+    };
+    let after = params.after.as_deref().map(view_options::parse_cursor).transpose()?;
+    let mut page = repo.view_page(&list.name, &options, shown, after.as_deref(), PAGE_SIZE + 1)?;
+    let more = page.len() > PAGE_SIZE;
+    page.truncate(PAGE_SIZE);
+    let next_page = page.last().filter(|_| more).map(|(keys, _)| {
+        let mut pairs = vec![("after", view_options::cursor(keys))];
+        if let Some(who) = &params.who {
+            pairs.push(("who", who.clone()));
+        }
+        let mut query = options.query();
+        if !query.is_empty() {
+            query.push('&');
+        }
+        query.push_str(&serde_urlencoded::to_string(pairs).unwrap_or_default());
+        format!("{}/?{}", list.base(), query)
+    });
+    let views = views(&users, me, who, &list.base());
+    let viewmodel = template::ItemsList::from_groups(options.group_page(&page))?
+        .with_views(views)
+        .with_options(options.clone(), params.who.clone())
+        .with_next_page(next_page)
+        .with_list(list, csrf);
     let body = viewmodel.to_string();
-    Ok(Html(body))
+    match cookie {
+        Some(cookie) => Ok(([(header::SET_COOKIE, cookie)], Html(body)).into_response()),
+        None => Ok(Html(body).into_response()),
+    }
+}
+
+/// Save the home page's current options under a name.
+pub(crate) async fn post_saved_view(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    list: List,
+    Form(params): Form<SavedViewParams>,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    let query = params.query.unwrap_or_default();
+    // Only save options that can be read back
+    let options = view_options::ViewOptions::from_query(&query).ok_or((
+        StatusCode::BAD_REQUEST,
+        String::from("Invalid view options"),
+    ))?;
+    repo.save_view(&params.name, &options.query())?;
+    Ok(Redirect::to(&format!("{}/?{}", list.base(), options.query())))
+}
+
+pub(crate) async fn post_delete_saved_view(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    list: List,
+    Form(params): Form<SavedViewParams>,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.delete_saved_view(&params.name)?;
+    Ok(Redirect::to(&format!("{}/", list.base())))
+}
+
+/// Put items in a new manual order, from the home page's `J`/`K` hotkeys.
+pub(crate) async fn post_item_order(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Form(params): Form<ItemOrderParams>,
+) -> Result<StatusCode, AppError> {
+    let ids = params
+        .ids
+        .split(',')
+        .map(|id| id.trim().parse())
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid item ids '{}'", params.ids)))?;
+    let mut repo = lock_repo(&repomux)?;
+    repo.reorder(&ids)?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
 pub(crate) async fn get_item(
     Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
     Path(item_id): Path<u32>,
-) -> Result<Html<String>, AppError> {
+    list: List,
+    Csrf(csrf): Csrf,
+) -> Result<Response, AppError> {
     let repo = lock_repo(&repomux)?;
     let item = repo.get(item_id)?;
-    let viewmodel: template::Item = item.try_into()?;
+    if let Some(redirect) = redirect_to_list(&item, &list, &format!("/item/{}", item.id)) {
+        return Ok(redirect);
+    }
+    let links = repo.resolve_links(&item.body)?;
+    let backlinks = repo.backlinks(item.id)?;
+    let attachments = repo.attachments(item.id)?;
+    let users = repo.users()?;
+    let viewmodel = template::Item::new(item, links, backlinks, attachments, &users, list, csrf)?;
     let body = viewmodel.to_string();
-    Ok(Html(body))
+    Ok(Html(body).into_response())
 }
 
 pub(crate) async fn get_edit_item(
     Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
     Path(item_id): Path<u32>,
-) -> Result<Html<String>, AppError> {
+    CurrentUser(me): CurrentUser,
+    list: List,
+    Csrf(csrf): Csrf,
+    Query(params): Query<RestoreDraftParams>,
+) -> Result<Response, AppError> {
     let repo = lock_repo(&repomux)?;
     let item = repo.get(item_id)?;
-    let viewmodel: template::EditItem = item.try_into()?;
+    if let Some(redirect) = redirect_to_list(&item, &list, &format!("/item/{}/edit", item.id)) {
+        return Ok(redirect);
+    }
+    let attachments = repo.attachments(item.id)?;
+    let draft = repo.draft(&repo::DraftOf::Item(item.id), me)?;
+    let viewmodel = template::EditItem::new(item, attachments, repo.users()?, list, csrf)?
+        .with_draft(draft, params.restore());
     let body = viewmodel.to_string();
-    Ok(Html(body))
+    Ok(Html(body).into_response())
 }
 
 pub(crate) async fn post_edit_item(
     Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
     Path(item_id): Path<u32>,
+    CurrentUser(me): CurrentUser,
     Form(edits): Form<EditParams>,
 ) -> Result<Redirect, AppError> {
     let mut repo = lock_repo(&repomux)?;
     let mut item = repo.get(item_id)?;
-    item.apply(&edits);
-    let goto = Redirect::to(&format!("/item/{}", item.id));
-    repo.update(&mut item)?;
+    item.apply(&edits, &repo.users()?, &repo.lists()?)?;
+    // The item may have been moved to another list
+    let goto = Redirect::to(&format!("{}/item/{}", lists::base(&item.list), item.id));
+    repo.update(&mut item, me)?;
+    repo.discard_draft(&repo::DraftOf::Item(item.id), me)?;
     Ok(goto)
 }
 
 pub(crate) async fn post_delete_item(
     Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
     Path(item_id): Path<u32>,
+    CurrentUser(me): CurrentUser,
+    list: List,
 ) -> Result<Redirect, AppError> {
     let mut repo = lock_repo(&repomux)?;
-    repo.delete(&item_id)?;
-    Ok(Redirect::to("/"))
+    repo.delete(&item_id, me)?;
+    Ok(Redirect::to(&format!("{}/", list.base())))
 }
 
-pub(crate) async fn get_new_item() -> Html<String> {
-    Html(template::NewItem::default().to_string())
+pub(crate) async fn post_toggle_task(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(item_id): Path<u32>,
+    CurrentUser(me): CurrentUser,
+    Form(params): Form<TaskParams>,
+) -> Result<StatusCode, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    let mut item = repo.get(item_id)?;
+    item.body = markdown::toggle_task(&item.body, params.offset).ok_or((
+        StatusCode::BAD_REQUEST,
+        format!("No task at offset {}", params.offset),
+    ))?;
+    repo.update(&mut item, me)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn get_new_item(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    CurrentUser(me): CurrentUser,
+    list: List,
+    Csrf(csrf): Csrf,
+    Query(params): Query<RestoreDraftParams>,
+    Query(picker): Query<TemplateParams>,
+) -> Result<Html<String>, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let draft = repo.draft(&repo::DraftOf::NewItem(list.name.clone()), me)?;
+    let picked = picker.template.map(|id| repo.item_template(id)).transpose()?;
+    let body = picked.as_ref().map(item_templates::body_with_tags);
+    let mut viewmodel = template::NewItem::new(list, csrf).with_templates(repo.item_templates()?);
+    if let (Some(picked), Some(body)) = (&picked, &body) {
+        viewmodel = viewmodel.with_template(picked, body);
+    }
+    let viewmodel = viewmodel.with_draft(draft.as_ref(), params.restore());
+    Ok(Html(viewmodel.to_string()))
 }
 
 pub(crate) async fn post_new_item(
     Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
-    Form(edits): Form<EditParams>,
+    CurrentUser(me): CurrentUser,
+    list: List,
+    Form(mut edits): Form<EditParams>,
 ) -> Result<Redirect, AppError> {
     let mut repo = lock_repo(&repomux)?;
+    if edits.template.is_some() {
+        let placeholders = item_templates::Placeholders {
+            now: Local::now(),
+            user: repo.users()?.into_iter().find(|u| Some(u.id) == me).map(|u| u.name),
+            list: list.name.clone(),
+        };
+        edits.title = placeholders.expand(&edits.title);
+        edits.body = placeholders.expand(&edits.body);
+    }
     let item = repo.add(
+        &list.name,
         &edits.title,
         &edits.body,
         edits.important.is_some(),
         edits.urgent.is_some(),
+        me,
     )?;
-    Ok(Redirect::to(&format!("/item/{}", item.id)))
+    repo.discard_draft(&repo::DraftOf::NewItem(list.name.clone()), me)?;
+    Ok(Redirect::to(&format!("{}/item/{}", list.base(), item.id)))
 }
 
-pub(crate) async fn get_deleted_items(
+/// Autosave the editor's contents for an item.
+pub(crate) async fn post_item_draft(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(item_id): Path<u32>,
+    CurrentUser(me): CurrentUser,
+    Form(params): Form<DraftParams>,
+) -> Result<StatusCode, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.get(item_id)?;
+    repo.save_draft(&repo::DraftOf::Item(item_id), me, &params.title, &params.body)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn post_discard_item_draft(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(item_id): Path<u32>,
+    CurrentUser(me): CurrentUser,
+    list: List,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.discard_draft(&repo::DraftOf::Item(item_id), me)?;
+    Ok(Redirect::to(&format!("{}/item/{}/edit", list.base(), item_id)))
+}
+
+/// Autosave the editor's contents for a new item.
+pub(crate) async fn post_new_item_draft(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    CurrentUser(me): CurrentUser,
+    list: List,
+    Form(params): Form<DraftParams>,
+) -> Result<StatusCode, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    let of = repo::DraftOf::NewItem(list.name);
+    repo.save_draft(&of, me, &params.title, &params.body)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn post_discard_new_item_draft(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    CurrentUser(me): CurrentUser,
+    list: List,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.discard_draft(&repo::DraftOf::NewItem(list.name.clone()), me)?;
+    Ok(Redirect::to(&format!("{}/item/new", list.base())))
+}
+
+/// A body rendered as it would be on its item's page, for the editor's
+/// preview.
+pub(crate) async fn post_render(
     Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Form(params): Form<RenderParams>,
 ) -> Result<Html<String>, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let links = repo.resolve_links(&params.body)?;
+    Ok(Html(template::Preview::new(params.body, links).to_string()))
+}
+
+/// The new item form filled in from a capture, so that following a link
+/// can't create an item without the user confirming it.
+pub(crate) async fn get_capture(
+    list: List,
+    Csrf(csrf): Csrf,
+    Query(params): Query<CaptureParams>,
+) -> Html<String> {
+    let capture = capture::capture(
+        params.title.as_deref(),
+        params.body.as_deref(),
+        params.url.as_deref(),
+    );
+    Html(template::NewItem::new(list, csrf).with_capture(&capture).to_string())
+}
+
+pub(crate) async fn post_capture(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    CurrentUser(me): CurrentUser,
+    list: List,
+    Form(params): Form<CaptureParams>,
+) -> Result<Redirect, AppError> {
+    let capture = capture::capture(
+        params.title.as_deref(),
+        params.body.as_deref(),
+        params.url.as_deref(),
+    );
     let mut repo = lock_repo(&repomux)?;
-    let items = repo.deleted()?;
-    let viewmodel = template::DeletedItems::try_from(items)?;
+    let item = repo.add(&list.name, &capture.title, &capture.body, false, false, me)?;
+    Ok(Redirect::to(&format!("{}/item/{}", list.base(), item.id)))
+}
+
+pub(crate) async fn get_bookmarklet(list: List, headers: HeaderMap) -> Html<String> {
+    let link = capture::bookmarklet(&format!("{}{}", base_url(&headers), list.base()));
+    Html(template::Bookmarklet::new(link, list).to_string())
+}
+
+pub(crate) async fn get_manifest() -> impl IntoResponse {
+    let headers = [(header::CONTENT_TYPE, "application/manifest+json")];
+    (headers, capture::manifest().to_string())
+}
+
+/// The most recently deleted items, a page at a time. `?before=` is where the
+/// page starts, from the previous page's "More items" link.
+pub(crate) async fn get_deleted_items(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    list: List,
+    Query(params): Query<DeletedPageParams>,
+) -> Result<Html<String>, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let before = params.before()?;
+    let mut items = repo.deleted_page(&list.name, before, PAGE_SIZE + 1)?;
+    let next_page = if items.len() > PAGE_SIZE {
+        items.truncate(PAGE_SIZE);
+        items.last().map(|last| {
+            let cursor = format!("{}-{}", last.deleted.unwrap_or_default(), last.id);
+            format!("{}/deleted?before={}", list.base(), cursor)
+        })
+    } else {
+        None
+    };
+    let viewmodel = template::DeletedItems::new(items, list)?.with_next_page(next_page);
     let body = viewmodel.to_string();
     Ok(Html(body))
 }
@@ -94,25 +375,555 @@ pub(crate) async fn get_deleted_items(
 pub(crate) async fn get_deleted_item(
     Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
     Path(item_id): Path<u32>,
-) -> Result<Html<String>, AppError> {
+    list: List,
+    Csrf(csrf): Csrf,
+) -> Result<Response, AppError> {
     let repo = lock_repo(&repomux)?;
     let item = repo.get_deleted(item_id)?;
-    let viewmodel: template::DeletedItem = item.try_into()?;
+    if let Some(redirect) = redirect_to_list(&item, &list, &format!("/deleted/{}", item.id)) {
+        return Ok(redirect);
+    }
+    let links = repo.resolve_links(&item.body)?;
+    let attachments = repo.attachments(item.id)?;
+    let users = repo.users()?;
+    let viewmodel = template::DeletedItem::new(item, links, attachments, &users, list, csrf)?;
     let body = viewmodel.to_string();
-    Ok(Html(body))
+    Ok(Html(body).into_response())
 }
 
 pub(crate) async fn restore_item(
     Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
     Path(item_id): Path<u32>,
+    CurrentUser(me): CurrentUser,
+    list: List,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.restore(&item_id, me)?;
+    Ok(Redirect::to(&format!("{}/item/{}", list.base(), item_id)))
+}
+
+pub(crate) async fn post_purge_item(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(item_id): Path<u32>,
+    list: List,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.purge(&item_id)?;
+    Ok(Redirect::to(&format!("{}/deleted", list.base())))
+}
+
+pub(crate) async fn post_attachments(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(item_id): Path<u32>,
+    list: List,
+    ContentLengthLimit(mut multipart): ContentLengthLimit<Multipart, MAX_UPLOAD_SIZE>,
+) -> Result<Redirect, AppError> {
+    // Read the whole upload before locking the repo
+    let mut uploads = Vec::new();
+    while let Some(field) = multipart.next_field().await.map_err(bad_upload)? {
+        let name = match field.file_name() {
+            Some(name) if !name.is_empty() => sanitize_file_name(name),
+            _ => continue,
+        };
+        let content_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_owned();
+        let data = field.bytes().await.map_err(bad_upload)?;
+        uploads.push((name, content_type, data));
+    }
+    let mut repo = lock_repo(&repomux)?;
+    for (name, content_type, data) in uploads {
+        repo.add_attachment(item_id, &name, &content_type, &data)?;
+    }
+    Ok(Redirect::to(&format!("{}/item/{}/edit", list.base(), item_id)))
+}
+
+pub(crate) async fn get_attachment(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(attachment_id): Path<u32>,
+) -> Result<impl IntoResponse, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let attachment = repo.get_attachment(attachment_id)?;
+    let data = repo.attachment_data(attachment_id)?;
+    // Anything that could run scripts (e.g. HTML or SVG) is downloaded
+    // rather than shown, so uploads can't run scripts on the app's pages
+    let (content_type, disposition) = if shows_inline(&attachment.content_type) {
+        (attachment.content_type, "inline")
+    } else {
+        (String::from("application/octet-stream"), "attachment")
+    };
+    let headers = [
+        (header::CONTENT_TYPE, content_type),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("{}; filename=\"{}\"", disposition, attachment.name),
+        ),
+        (header::X_CONTENT_TYPE_OPTIONS, String::from("nosniff")),
+    ];
+    Ok((headers, data))
+}
+
+pub(crate) async fn post_delete_attachment(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(attachment_id): Path<u32>,
+    list: List,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    let attachment = repo.get_attachment(attachment_id)?;
+    repo.delete_attachment(attachment_id)?;
+    Ok(Redirect::to(&format!("{}/item/{}/edit", list.base(), attachment.item)))
+}
+
+pub(crate) async fn get_export(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    list: List,
+    Query(params): Query<ExportParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    let items = repo.active_and_deleted_in(&list.name)?;
+    let (content_type, file_name, body) = match params.format.parse()? {
+        transfer::Format::Json => (
+            "application/json",
+            "wannado.json",
+            transfer::to_json(&items)?.into_bytes(),
+        ),
+        transfer::Format::Csv => ("text/csv", "wannado.csv", transfer::to_csv(&items)?.into_bytes()),
+        transfer::Format::Markdown => (
+            "application/x-tar",
+            "wannado-markdown.tar",
+            transfer::to_markdown_archive(&items)?,
+        ),
+    };
+    let headers = [
+        (header::CONTENT_TYPE, content_type.to_owned()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", file_name),
+        ),
+    ];
+    Ok((headers, body))
+}
+
+pub(crate) async fn get_import(list: List, Csrf(csrf): Csrf) -> Html<String> {
+    Html(template::Import::new(list, csrf).to_string())
+}
+
+pub(crate) async fn post_import_preview(
+    list: List,
+    Csrf(csrf): Csrf,
+    ContentLengthLimit(mut multipart): ContentLengthLimit<Multipart, MAX_UPLOAD_SIZE>,
+) -> Result<Html<String>, AppError> {
+    let mut format = String::new();
+    let mut file = String::new();
+    let mut text = String::new();
+    while let Some(field) = multipart.next_field().await.map_err(bad_upload)? {
+        let name = field.name().unwrap_or_default().to_owned();
+        let value = field.text().await.map_err(bad_upload)?;
+        match name.as_str() {
+            "format" => format = value,
+            "file" => file = value,
+            "text" => text = value,
+            _ => {}
+        }
+    }
+    let source = if file.trim().is_empty() { text } else { file };
+    let items = importers::parse(format.parse()?, &source, Local::now().date_naive())?;
+    let viewmodel = template::ImportPreview::new(format, source, items, list, csrf);
+    Ok(Html(viewmodel.to_string()))
+}
+
+pub(crate) async fn post_import(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    CurrentUser(me): CurrentUser,
+    list: List,
+    Form(params): Form<ImportParams>,
+) -> Result<Redirect, AppError> {
+    let items = importers::parse(
+        params.format.parse()?,
+        &params.source,
+        Local::now().date_naive(),
+    )?;
+    let mut repo = lock_repo(&repomux)?;
+    repo.in_transaction(|repo| {
+        for imported in items {
+            let item = repo.add(
+                &list.name,
+                &imported.title,
+                &imported.body,
+                imported.important,
+                imported.urgent,
+                me,
+            )?;
+            if imported.completed {
+                repo.delete(&item.id, me)?;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(Redirect::to(&format!("{}/", list.base())))
+}
+
+pub(crate) async fn get_backups(list: List, Csrf(csrf): Csrf) -> Result<Html<String>, AppError> {
+    let dir = backup::backup_dir();
+    let snapshots = backup::snapshots(&dir)?;
+    let viewmodel = template::Backups::new(dir.display().to_string(), snapshots, list, csrf);
+    Ok(Html(viewmodel.to_string()))
+}
+
+pub(crate) async fn post_backup() -> Result<Redirect, AppError> {
+    backup::snapshot_database(std::path::Path::new(crate::DATABASE), &backup::backup_dir())?;
+    Ok(Redirect::to("/admin/backups"))
+}
+
+pub(crate) async fn get_backup(Path(name): Path<String>) -> Result<impl IntoResponse, AppError> {
+    let path = backup::snapshot_path(&backup::backup_dir(), &name)
+        .ok_or((StatusCode::NOT_FOUND, String::from("No such backup")))?;
+    let data = std::fs::read(path)
+        .map_err(|_| (StatusCode::NOT_FOUND, String::from("No such backup")))?;
+    let headers = [
+        (header::CONTENT_TYPE, String::from("application/vnd.sqlite3")),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", name),
+        ),
+    ];
+    Ok((headers, data))
+}
+
+pub(crate) async fn get_lists(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    list: List,
+    Csrf(csrf): Csrf,
+) -> Result<Html<String>, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let mut counts = Vec::new();
+    for name in &list.names {
+        counts.push((name.clone(), repo.list_size(name)?));
+    }
+    let viewmodel = template::Lists::new(counts, list, csrf);
+    Ok(Html(viewmodel.to_string()))
+}
+
+pub(crate) async fn post_list(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Form(params): Form<ListParams>,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.add_list(&params.name)?;
+    Ok(Redirect::to(&format!("{}/", lists::base(&params.name))))
+}
+
+pub(crate) async fn post_delete_list(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(name): Path<String>,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.delete_list(&name)?;
+    Ok(Redirect::to("/lists"))
+}
+
+pub(crate) async fn get_item_templates(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    list: List,
+    Csrf(csrf): Csrf,
+) -> Result<Html<String>, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let viewmodel = template::ItemTemplates::new(repo.item_templates()?, list, csrf);
+    Ok(Html(viewmodel.to_string()))
+}
+
+pub(crate) async fn get_new_item_template(list: List, Csrf(csrf): Csrf) -> Html<String> {
+    let viewmodel = template::EditItemTemplate::new(repo::ItemTemplate::default(), list, csrf);
+    Html(viewmodel.to_string())
+}
+
+pub(crate) async fn post_item_template(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Form(params): Form<ItemTemplateParams>,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.save_item_template(&params.into_template(0))?;
+    Ok(Redirect::to("/templates"))
+}
+
+pub(crate) async fn get_edit_item_template(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(id): Path<u32>,
+    list: List,
+    Csrf(csrf): Csrf,
+) -> Result<Html<String>, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let viewmodel = template::EditItemTemplate::new(repo.item_template(id)?, list, csrf);
+    Ok(Html(viewmodel.to_string()))
+}
+
+pub(crate) async fn post_edit_item_template(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(id): Path<u32>,
+    Form(params): Form<ItemTemplateParams>,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.item_template(id)?;
+    repo.save_item_template(&params.into_template(id))?;
+    Ok(Redirect::to("/templates"))
+}
+
+pub(crate) async fn post_delete_item_template(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(id): Path<u32>,
+) -> Result<Redirect, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.delete_item_template(id)?;
+    Ok(Redirect::to("/templates"))
+}
+
+/// Where the server can be reached, for links in feeds and the bookmarklet:
+/// `WANNADO_BASE_URL` if it's set, or else the host the request was sent to
+/// over plain HTTP.
+fn base_url(headers: &HeaderMap) -> String {
+    match std::env::var("WANNADO_BASE_URL") {
+        Ok(url) => url.trim_end_matches('/').to_owned(),
+        Err(_) => {
+            let host = headers
+                .get(header::HOST)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or("localhost");
+            format!("http://{}", host)
+        }
+    }
+}
+
+pub(crate) async fn get_calendar(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    list: List,
+    Query(filter): Query<ItemFilter>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let items = repo.calendar_items(&list.name, filter.query()?.as_ref())?;
+    let base_url = base_url(&headers);
+    let headers = [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")];
+    Ok((headers, calendar::to_ical(&items, &base_url)))
+}
+
+pub(crate) async fn get_feed(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    list: List,
+    Query(filter): Query<ItemFilter>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let items = repo.recently_active(&list.name, filter.query()?.as_ref(), feed::FEED_LENGTH)?;
+    let name = match list.name.as_str() {
+        repo::DEFAULT_LIST => String::from("Wannado"),
+        name => format!("Wannado: {}", name),
+    };
+    let title = match &filter.quadrant {
+        Some(quadrant) => format!("{} ({})", name, quadrant.replace('-', " ")),
+        None => name,
+    };
+    let mut viewmodel = template::Feed::new(title, base_url(&headers), list.base(), uri.to_string());
+    for event in feed::recent_activity(items) {
+        let links = repo.resolve_links(&event.item.body)?;
+        viewmodel.push(event, links);
+    }
+    let headers = [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")];
+    Ok((headers, viewmodel.to_string()))
+}
+
+/// Statistics about the list's items, as a page with charts or, with
+/// `?format=json`, as JSON.
+pub(crate) async fn get_stats(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    list: List,
+    Query(params): Query<StatsParams>,
+) -> Result<Response, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let stats = stats::stats(&repo, &list.name, Local::now())?;
+    match params.format.as_deref() {
+        None | Some("html") => Ok(Html(template::Stats::new(stats, list).to_string()).into_response()),
+        Some("json") => Ok(Json(stats).into_response()),
+        Some(format) => Err((
+            StatusCode::BAD_REQUEST,
+            format!("Unknown format '{}', expected html or json", format),
+        )),
+    }
+}
+
+pub(crate) async fn get_login(Query(params): Query<NextParams>) -> Html<String> {
+    let next = auth::safe_redirect(params.next.as_deref());
+    Html(template::Login::new(next, None).to_string())
+}
+
+pub(crate) async fn post_login(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Form(params): Form<LoginParams>,
+) -> Result<Response, AppError> {
+    let next = auth::safe_redirect(params.next.as_deref());
+    match auth::log_in(&repomux, &params.name, &params.password) {
+        Ok(token) => {
+            let headers = [(header::SET_COOKIE, auth::session_cookie(&token))];
+            Ok((headers, Redirect::to(&next)).into_response())
+        }
+        Err((status, msg)) => {
+            let viewmodel = template::Login::new(next, Some(msg));
+            Ok((status, Html(viewmodel.to_string())).into_response())
+        }
+    }
+}
+
+pub(crate) async fn post_logout(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(token) = auth::session_token(&headers) {
+        lock_repo(&repomux)?.delete_session(&auth::hash_token(&token))?;
+    }
+    let headers = [(header::SET_COOKIE, auth::session_cookie(""))];
+    Ok((headers, Redirect::to("/login")))
+}
+
+/// The logged-in user's API tokens. There's no notion of an admin, so users
+/// only see and manage their own tokens here; global tokens and other users'
+/// tokens are managed with `wannado token` on the server.
+pub(crate) async fn get_api_tokens(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    session: Option<Extension<repo::Session>>,
+    list: List,
+    Csrf(csrf): Csrf,
+) -> Result<Html<String>, AppError> {
+    let user = token_user(session)?;
+    let repo = lock_repo(&repomux)?;
+    let viewmodel = template::ApiTokens::new(own_api_tokens(&repo, user)?, None, list, csrf);
+    Ok(Html(viewmodel.to_string()))
+}
+
+/// Create a token for the logged-in user and show it, once, on the tokens
+/// page.
+pub(crate) async fn post_api_token(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    session: Option<Extension<repo::Session>>,
+    list: List,
+    Csrf(csrf): Csrf,
+    Form(params): Form<ApiTokenParams>,
+) -> Result<Html<String>, AppError> {
+    let user = token_user(session)?;
+    if params.owner.as_deref().is_some_and(|owner| owner != "me") {
+        return Err((
+            StatusCode::FORBIDDEN,
+            String::from("Global tokens can only be created with `wannado token create`"),
+        ));
+    }
+    let mut repo = lock_repo(&repomux)?;
+    let scope = params.scope.parse()?;
+    let (token, _) = auth::create_api_token(&mut repo, &params.name, Some(user), scope)?;
+    let viewmodel = template::ApiTokens::new(own_api_tokens(&repo, user)?, Some(token), list, csrf);
+    Ok(Html(viewmodel.to_string()))
+}
+
+/// Revoke one of the logged-in user's tokens.
+pub(crate) async fn post_revoke_api_token(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    session: Option<Extension<repo::Session>>,
+    Path(token_id): Path<u32>,
 ) -> Result<Redirect, AppError> {
+    let user = token_user(session)?;
+    let mut repo = lock_repo(&repomux)?;
+    repo.revoke_api_token(token_id, Some(user))?;
+    Ok(Redirect::to("/admin/tokens"))
+}
+
+// JSON API, used by the command-line client
+/// Every un-deleted item in the list in order of id, or with `?q=`, the ones
+/// matching a query (see the `query` module). `?limit=` gives a page of
+/// items at a time, and `?after=` the page after the item with that id.
+pub(crate) async fn get_api_items(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    list: List,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<repo::Item>>, AppError> {
+    let repo = lock_repo(&repomux)?;
+    let query = params
+        .q
+        .as_deref()
+        .map(str::trim)
+        .filter(|q| !q.is_empty())
+        .map(str::parse)
+        .transpose()?;
+    let items = repo.items_page(
+        &list.name,
+        query.as_ref(),
+        params.after.unwrap_or(0),
+        params.limit,
+    )?;
+    Ok(Json(items))
+}
+
+pub(crate) async fn post_api_item(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    CurrentUser(me): CurrentUser,
+    list: List,
+    Json(fields): Json<ItemFields>,
+) -> Result<Json<repo::Item>, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    let item = repo.add(
+        &list.name,
+        &fields.title,
+        &fields.body,
+        fields.important,
+        fields.urgent,
+        me,
+    )?;
+    Ok(Json(item))
+}
+
+pub(crate) async fn get_api_item(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(item_id): Path<u32>,
+) -> Result<Json<repo::Item>, AppError> {
+    let repo = lock_repo(&repomux)?;
+    Ok(Json(repo.get(item_id)?))
+}
+
+pub(crate) async fn put_api_item(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(item_id): Path<u32>,
+    CurrentUser(me): CurrentUser,
+    Json(fields): Json<ItemFields>,
+) -> Result<Json<repo::Item>, AppError> {
     let mut repo = lock_repo(&repomux)?;
-    repo.restore(&item_id)?;
-    Ok(Redirect::to(&format!("/item/{}", item_id)))
+    let mut item = repo.get(item_id)?;
+    item.title = fields.title;
+    item.body = fields.body;
+    item.important = fields.important;
+    item.urgent = fields.urgent;
+    repo.update(&mut item, me)?;
+    Ok(Json(item))
+}
+
+pub(crate) async fn delete_api_item(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    Path(item_id): Path<u32>,
+    CurrentUser(me): CurrentUser,
+) -> Result<StatusCode, AppError> {
+    let mut repo = lock_repo(&repomux)?;
+    repo.delete(&item_id, me)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The list's statistics, as on the stats page (see `stats`).
+pub(crate) async fn get_api_stats(
+    Extension(repomux): Extension<Arc<Mutex<repo::Repo>>>,
+    list: List,
+) -> Result<Json<stats::Stats>, AppError> {
+    let repo = lock_repo(&repomux)?;
+    Ok(Json(stats::stats(&repo, &list.name, Local::now())?))
 }
 
 // Helpers
-fn lock_repo(repomux: &Arc<Mutex<repo::Repo>>) -> Result<MutexGuard<repo::Repo>, AppError> {
+fn lock_repo(repomux: &Arc<Mutex<repo::Repo>>) -> Result<MutexGuard<'_, repo::Repo>, AppError> {
     repomux.lock().map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -121,21 +932,364 @@ fn lock_repo(repomux: &Arc<Mutex<repo::Repo>>) -> Result<MutexGuard<repo::Repo>,
     })
 }
 
+/// Send people to the list an item's in, if they've got to its page through
+/// another list's URL (e.g. from a `#42` link).
+fn redirect_to_list(item: &repo::Item, list: &List, path: &str) -> Option<Response> {
+    if item.list == list.name {
+        return None;
+    }
+    let to = format!("{}{}", lists::base(&item.list), path);
+    Some(Redirect::to(&to).into_response())
+}
+
+/// Who's managing API tokens: they belong to users, so there has to be one.
+fn token_user(session: Option<Extension<repo::Session>>) -> Result<u32, AppError> {
+    session.map(|Extension(session)| session.user).ok_or((
+        StatusCode::FORBIDDEN,
+        String::from("Log in to manage API tokens"),
+    ))
+}
+
+fn own_api_tokens(repo: &repo::Repo, user: u32) -> Result<Vec<repo::ApiToken>, AppError> {
+    let mut tokens = repo.api_tokens()?;
+    tokens.retain(|t| t.user == Some(user));
+    Ok(tokens)
+}
+
+fn user_id(users: &[repo::User], name: &str) -> Result<u32, AppError> {
+    users
+        .iter()
+        .find(|u| u.name == name)
+        .map(|u| u.id)
+        .ok_or((StatusCode::BAD_REQUEST, format!("There's no user called '{}'", name)))
+}
+
+/// The switches between views of the home page: the current user's items,
+/// everyone's, and each user's (with hotkeys 1 to 9).
+fn views(
+    users: &[repo::User],
+    me: Option<u32>,
+    who: &str,
+    base: &str,
+) -> Vec<template::ItemsView> {
+    if users.is_empty() {
+        return Vec::new();
+    }
+    let view = |label: &str, name: &str, hotkey: String| template::ItemsView {
+        label: label.to_owned(),
+        href: format!(
+            "{}/?{}",
+            base,
+            serde_urlencoded::to_string([("who", name)]).unwrap_or_default()
+        ),
+        hotkey,
+        active: who == name,
+    };
+    let mut views = Vec::new();
+    if me.is_some() {
+        views.push(view("Mine", "mine", String::from("m")));
+    }
+    views.push(view("Everyone", "everyone", String::from("a")));
+    for (n, user) in users.iter().take(9).enumerate() {
+        views.push(view(&user.name, &user.name, (n + 1).to_string()));
+    }
+    views
+}
+
+/// The most that can be uploaded at once, in bytes.
+pub(crate) const MAX_UPLOAD_SIZE: u64 = 25 * 1024 * 1024;
+
+/// How many items the home page and deleted items page show at a time.
+const PAGE_SIZE: usize = 50;
+
+/// A `Set-Cookie` value for the home page's view options. Default options
+/// clear the cookie.
+fn view_cookie(query: &str) -> String {
+    let max_age = if query.is_empty() { 0 } else { 365 * 24 * 60 * 60 };
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        view_options::VIEW_COOKIE,
+        query,
+        max_age
+    )
+}
+
+/// Whether an attachment's content type is safe to show in the browser.
+fn shows_inline(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    matches!(
+        mime,
+        "image/png" | "image/jpeg" | "image/gif" | "image/webp" | "application/pdf" | "text/plain"
+    ) || mime.starts_with("audio/")
+        || mime.starts_with("video/")
+}
+
+fn bad_upload(err: axum::extract::multipart::MultipartError) -> AppError {
+    (StatusCode::BAD_REQUEST, format!("Couldn't read upload: {}", err))
+}
+
+/// Keep just the file's name, without anything that would need escaping in a
+/// Content-Disposition header.
+pub(crate) fn sanitize_file_name(name: &str) -> String {
+    name.rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .collect()
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct LoginParams {
+    pub name: String,
+    pub password: String,
+    pub next: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ApiTokenParams {
+    pub name: String,
+    pub scope: String,
+    /// Only `me`: global tokens can't be created from the web.
+    pub owner: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct NextParams {
+    pub next: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ListParams {
+    pub name: String,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct SearchParams {
+    pub q: Option<String>,
+    pub after: Option<u32>,
+    pub limit: Option<usize>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct DeletedPageParams {
+    /// When the last item on the previous page was deleted, and its id,
+    /// like `1667260800-42`.
+    pub before: Option<String>,
+}
+
+impl DeletedPageParams {
+    fn before(&self) -> Result<Option<(i64, u32)>, AppError> {
+        let before = match &self.before {
+            Some(before) => before,
+            None => return Ok(None),
+        };
+        let invalid = || (StatusCode::BAD_REQUEST, format!("Invalid page '{}'", before));
+        let (deleted, id) = before.split_once('-').ok_or_else(invalid)?;
+        Ok(Some((
+            deleted.parse().map_err(|_| invalid())?,
+            id.parse().map_err(|_| invalid())?,
+        )))
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct SavedViewParams {
+    pub name: String,
+    /// The view options as a query string (see `view_options`).
+    pub query: Option<String>,
+}
+
+/// Item ids separated by commas, in their new order.
+#[derive(serde::Deserialize)]
+pub(crate) struct ItemOrderParams {
+    pub ids: String,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ItemsParams {
+    pub who: Option<String>,
+    /// Where the page of items starts: the keys of the last item on the
+    /// previous page (see `view_options::cursor`).
+    pub after: Option<String>,
+}
+
 #[derive(serde::Deserialize)]
 pub(crate) struct EditParams {
     pub title: String,
     pub body: String,
     pub important: Option<String>,
     pub urgent: Option<String>,
+    /// The owner's name, or empty for no-one. Left alone if absent.
+    pub owner: Option<String>,
+    /// Assignees' names, separated by commas. Left alone if absent.
+    pub assignees: Option<String>,
+    /// The list to move the item to. Left alone if absent.
+    pub list: Option<String>,
+    /// The template a new item's form was filled in from, if any, so that
+    /// its placeholders get filled in too.
+    pub template: Option<u32>,
+}
+
+/// An item's editable fields, as sent to the JSON API.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct ItemFields {
+    pub title: String,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub important: bool,
+    #[serde(default)]
+    pub urgent: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct DraftParams {
+    pub title: String,
+    pub body: String,
+}
+
+/// `?draft=restore` fills the editor in with the user's draft.
+#[derive(serde::Deserialize)]
+pub(crate) struct RestoreDraftParams {
+    pub draft: Option<String>,
+}
+
+impl RestoreDraftParams {
+    fn restore(&self) -> bool {
+        self.draft.as_deref() == Some("restore")
+    }
+}
+
+/// `?template=ID` fills the new item form in from a template.
+#[derive(serde::Deserialize)]
+pub(crate) struct TemplateParams {
+    pub template: Option<u32>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ItemTemplateParams {
+    pub name: String,
+    pub title: String,
+    pub body: String,
+    pub important: Option<String>,
+    pub urgent: Option<String>,
+    /// Separated by spaces or commas (see `item_templates::parse_tags`).
+    pub tags: String,
+}
+
+impl ItemTemplateParams {
+    fn into_template(self, id: u32) -> repo::ItemTemplate {
+        repo::ItemTemplate {
+            id,
+            name: self.name.trim().to_owned(),
+            title: self.title,
+            body: self.body,
+            important: self.important.is_some(),
+            urgent: self.urgent.is_some(),
+            tags: item_templates::parse_tags(&self.tags),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct RenderParams {
+    pub body: String,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct CaptureParams {
+    pub title: Option<String>,
+    pub body: Option<String>,
+    pub url: Option<String>,
+}
+
+/// Query parameters for narrowing down feeds of items.
+#[derive(serde::Deserialize)]
+pub(crate) struct ItemFilter {
+    pub quadrant: Option<String>,
+    pub tag: Option<String>,
+}
+
+impl ItemFilter {
+    /// The filter as a query, so that feeds can be selected in SQL. It's
+    /// `None` if there isn't one.
+    fn query(&self) -> Result<Option<query::Query>, AppError> {
+        use query::{Query, Term};
+        let term = |term| Query::Term(term);
+        let not = |t| Query::Not(Box::new(term(t)));
+        let mut parts = Vec::new();
+        if let Some(quadrant) = &self.quadrant {
+            parts.extend(match quadrant.parse()? {
+                repo::Quadrant::ImportantAndUrgent => [term(Term::Important), term(Term::Urgent)],
+                repo::Quadrant::Important => [term(Term::Important), not(Term::Urgent)],
+                repo::Quadrant::Urgent => [not(Term::Important), term(Term::Urgent)],
+                repo::Quadrant::Other => [not(Term::Important), not(Term::Urgent)],
+            });
+        }
+        if let Some(tag) = &self.tag {
+            parts.push(term(Term::Tag(tag.trim_start_matches('#').to_lowercase())));
+        }
+        Ok(parts
+            .into_iter()
+            .reduce(|a, b| Query::And(Box::new(a), Box::new(b))))
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ExportParams {
+    pub format: String,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct StatsParams {
+    pub format: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ImportParams {
+    pub format: String,
+    pub source: String,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct TaskParams {
+    pub offset: usize,
 }
 
 impl repo::Item {
-    fn apply(&mut self, edits: &EditParams) {
+    fn apply(
+        &mut self,
+        edits: &EditParams,
+        users: &[repo::User],
+        lists: &[String],
+    ) -> Result<(), AppError> {
         self.title.clear();
         self.title.insert_str(0, &edits.title);
         self.body.clear();
         self.body.insert_str(0, &edits.body);
         self.important = edits.important.is_some();
         self.urgent = edits.urgent.is_some();
+        if let Some(owner) = &edits.owner {
+            self.owner = match owner.trim() {
+                "" => None,
+                name => Some(user_id(users, name)?),
+            };
+        }
+        if let Some(assignees) = &edits.assignees {
+            self.assignees = assignees
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| user_id(users, name))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(list) = &edits.list {
+            if !lists.contains(list) {
+                return Err((StatusCode::BAD_REQUEST, format!("No list called '{}'", list)));
+            }
+            self.list.clone_from(list);
+        }
+        Ok(())
     }
 }
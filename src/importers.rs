@@ -0,0 +1,277 @@
+//! Importers for other to-do apps' formats: [Todo.txt] and [Taskwarrior]'s
+//! JSON export.
+//!
+//! Neither maps exactly onto Wannado's items, so:
+//!
+//! * high priorities (`(A)` or `(B)` in Todo.txt, `H` or `M` in Taskwarrior)
+//!   make an item important,
+//! * a due date within a week makes an item urgent,
+//! * projects, contexts, tags (as `#hashtags`), due dates (as `Due: <date>`)
+//!   and annotations are kept in the body,
+//! * completed (and deleted) tasks are imported as deleted items.
+//!
+//! [Todo.txt]: https://github.com/todotxt/todo.txt
+//! [Taskwarrior]: https://taskwarrior.org/docs/commands/export/
+
+use crate::{AppError, StatusCode};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+/// Tasks due within this many days are urgent.
+const URGENT_WITHIN_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    TodoTxt,
+    Taskwarrior,
+}
+
+impl std::str::FromStr for Source {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "todotxt" => Ok(Source::TodoTxt),
+            "taskwarrior" => Ok(Source::Taskwarrior),
+            _ => Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown format '{}', expected todotxt or taskwarrior", s),
+            )),
+        }
+    }
+}
+
+/// A task converted to the shape of an item, ready for `Repo::add`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedItem {
+    pub title: String,
+    pub body: String,
+    pub important: bool,
+    pub urgent: bool,
+    pub completed: bool,
+}
+
+pub fn parse(source: Source, src: &str, today: NaiveDate) -> Result<Vec<ImportedItem>, AppError> {
+    match source {
+        Source::TodoTxt => Ok(from_todotxt(src, today)),
+        Source::Taskwarrior => from_taskwarrior(src, today),
+    }
+}
+
+pub fn from_todotxt(src: &str, today: NaiveDate) -> Vec<ImportedItem> {
+    src.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| todotxt_line(line, today))
+        .collect()
+}
+
+fn todotxt_line(line: &str, today: NaiveDate) -> ImportedItem {
+    fn is_date(token: Option<&&str>) -> bool {
+        token.is_some_and(|t| NaiveDate::parse_from_str(t, "%Y-%m-%d").is_ok())
+    }
+
+    let mut tokens: Vec<&str> = line.split_whitespace().collect();
+    let mut completed = false;
+    let mut priority = None;
+    if tokens.first() == Some(&"x") {
+        completed = true;
+        tokens.remove(0);
+        if is_date(tokens.first()) {
+            tokens.remove(0); // Completion date
+        }
+    } else if let Some(p) = tokens.first().and_then(|t| todotxt_priority(t)) {
+        priority = Some(p);
+        tokens.remove(0);
+    }
+    if is_date(tokens.first()) {
+        tokens.remove(0); // Creation date
+    }
+
+    let mut words = Vec::new();
+    let mut projects = Vec::new();
+    let mut contexts = Vec::new();
+    let mut due = None;
+    for token in tokens {
+        if token.len() > 1 && token.starts_with('+') {
+            projects.push(token);
+        } else if token.len() > 1 && token.starts_with('@') {
+            contexts.push(token);
+        } else if let Some(date) = token.strip_prefix("due:") {
+            due = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok();
+        } else if let Some(p) = token.strip_prefix("pri:") {
+            // Completed tasks keep their priority as `pri:A`
+            priority = p.chars().next();
+        } else {
+            words.push(token);
+        }
+    }
+
+    let mut body = Vec::new();
+    if !projects.is_empty() {
+        body.push(format!("Projects: {}", projects.join(" ")));
+    }
+    if !contexts.is_empty() {
+        body.push(format!("Contexts: {}", contexts.join(" ")));
+    }
+    if let Some(due) = due {
+        body.push(format!("Due: {}", due));
+    }
+    body.push(format!("Imported from Todo.txt: `{}`", line.trim()));
+
+    ImportedItem {
+        title: words.join(" "),
+        body: body.join("\n\n"),
+        important: matches!(priority, Some('A') | Some('B')),
+        urgent: !completed && due.is_some_and(|d| is_due_soon(d, today)),
+        completed,
+    }
+}
+
+fn todotxt_priority(token: &str) -> Option<char> {
+    let mut chars = token.chars();
+    match (chars.next(), chars.next(), chars.next(), chars.next()) {
+        (Some('('), Some(p), Some(')'), None) if p.is_ascii_uppercase() => Some(p),
+        _ => None,
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Task {
+    description: String,
+    status: String,
+    priority: Option<String>,
+    project: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    due: Option<String>,
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+}
+
+#[derive(serde::Deserialize)]
+struct Annotation {
+    description: String,
+}
+
+/// Accepts both a JSON array (`task export`) and one task per line (older
+/// versions of Taskwarrior).
+pub fn from_taskwarrior(src: &str, today: NaiveDate) -> Result<Vec<ImportedItem>, AppError> {
+    fn invalid(err: serde_json::Error) -> AppError {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid Taskwarrior export: {}", err),
+        )
+    }
+    let tasks: Vec<Task> = if src.trim_start().starts_with('[') {
+        serde_json::from_str(src).map_err(invalid)?
+    } else {
+        src.lines()
+            .map(|l| l.trim().trim_end_matches(','))
+            .filter(|l| !l.is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .map_err(invalid)?
+    };
+    Ok(tasks
+        .into_iter()
+        .filter(|t| t.status != "recurring") // Templates for recurring tasks
+        .map(|t| taskwarrior_task(t, today))
+        .collect())
+}
+
+fn taskwarrior_task(task: Task, today: NaiveDate) -> ImportedItem {
+    let due = task
+        .due
+        .as_deref()
+        .and_then(|d| NaiveDateTime::parse_from_str(d, "%Y%m%dT%H%M%SZ").ok())
+        .map(|d| d.date());
+    let completed = task.status == "completed" || task.status == "deleted";
+
+    let mut body = Vec::new();
+    if let Some(project) = &task.project {
+        body.push(format!("Project: {}", project));
+    }
+    if !task.tags.is_empty() {
+        let tags: Vec<String> = task
+            .tags
+            .iter()
+            .map(|t| format!("#{}", t.replace(char::is_whitespace, "-")))
+            .collect();
+        body.push(format!("Tags: {}", tags.join(" ")));
+    }
+    if let Some(due) = due {
+        body.push(format!("Due: {}", due));
+    }
+    if !task.annotations.is_empty() {
+        let notes: Vec<String> = task
+            .annotations
+            .iter()
+            .map(|a| format!("- {}", a.description))
+            .collect();
+        body.push(notes.join("\n"));
+    }
+
+    ImportedItem {
+        title: task.description,
+        body: body.join("\n\n"),
+        important: matches!(task.priority.as_deref(), Some("H") | Some("M")),
+        urgent: !completed && due.is_some_and(|d| is_due_soon(d, today)),
+        completed,
+    }
+}
+
+fn is_due_soon(due: NaiveDate, today: NaiveDate) -> bool {
+    due <= today + Duration::days(URGENT_WITHIN_DAYS)
+}
+
+#[test]
+fn test_todotxt() {
+    let today = NaiveDate::from_ymd_opt(2022, 11, 1).unwrap();
+    let src = "(A) 2022-10-30 Call Mom +family @phone due:2022-11-03\n\n\
+               (C) Tidy the garage +house\n\
+               x 2022-10-31 2022-10-01 Pay rent pri:B due:2022-11-01\n\
+               Read https://example.com/ due:2023-01-01\n";
+    let items = from_todotxt(src, today);
+    assert_eq!(items.len(), 4);
+
+    assert_eq!(items[0].title, "Call Mom");
+    assert!(items[0].important && items[0].urgent && !items[0].completed);
+    assert!(items[0].body.contains("Projects: +family"));
+    assert!(items[0].body.contains("Contexts: @phone"));
+    assert!(items[0].body.contains("Due: 2022-11-03"));
+
+    assert_eq!(items[1].title, "Tidy the garage");
+    assert!(!items[1].important && !items[1].urgent);
+
+    assert_eq!(items[2].title, "Pay rent");
+    assert!(items[2].important && !items[2].urgent && items[2].completed);
+
+    assert_eq!(items[3].title, "Read https://example.com/");
+    assert!(!items[3].urgent);
+}
+
+#[test]
+fn test_taskwarrior() -> Result<(), AppError> {
+    let today = NaiveDate::from_ymd_opt(2022, 11, 1).unwrap();
+    let src = r#"[
+        {"id":1,"description":"Fix the build","entry":"20221030T120000Z","status":"pending",
+         "priority":"H","project":"ops","tags":["ci","urgent"],"due":"20221102T000000Z",
+         "annotations":[{"entry":"20221030T130000Z","description":"See the logs"}],
+         "uuid":"9b1d"},
+        {"id":0,"description":"Old thing","entry":"20221001T120000Z","status":"completed",
+         "end":"20221005T120000Z","uuid":"4f2c"},
+        {"id":0,"description":"Weekly review","status":"recurring","uuid":"77aa"}
+    ]"#;
+    let items = from_taskwarrior(src, today)?;
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].title, "Fix the build");
+    assert!(items[0].important && items[0].urgent);
+    assert!(items[0].body.contains("Project: ops"));
+    assert!(items[0].body.contains("Tags: #ci #urgent"));
+    assert!(items[0].body.contains("- See the logs"));
+    assert!(items[1].completed && !items[1].important);
+
+    let lines = "{\"description\":\"One\",\"status\":\"pending\"}\n\
+                 {\"description\":\"Two\",\"status\":\"pending\",\"priority\":\"L\"}\n";
+    assert_eq!(from_taskwarrior(lines, today)?.len(), 2);
+    Ok(())
+}
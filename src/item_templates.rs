@@ -0,0 +1,134 @@
+//! Templates for the kinds of items that get created over and over, like
+//! "Bug triage" or "Meeting notes", managed at `/templates`.
+//!
+//! Picking a template on the new item page fills the form in with its title,
+//! body (with its tags added at the end) and flags. Placeholders in the title
+//! and body are filled in when the item is created:
+//!
+//! * `{{date}}`: today's date, e.g. `2022-11-01`, or `{{date+7}}` for a
+//!   week from today (handy for `due:` lines)
+//! * `{{time}}`: the time, e.g. `14:30`
+//! * `{{weekday}}`: the day of the week, e.g. `Tuesday`
+//! * `{{user}}`: the name of the user creating the item
+//! * `{{list}}`: the name of the list it's being created in
+//!
+//! Anything else in double braces is left as it is.
+
+use crate::{markdown, repo};
+use chrono::{DateTime, Duration, Local};
+
+/// The values to fill placeholders in with.
+pub struct Placeholders {
+    pub now: DateTime<Local>,
+    pub user: Option<String>,
+    pub list: String,
+}
+
+impl Placeholders {
+    pub fn expand(&self, src: &str) -> String {
+        let mut output = String::with_capacity(src.len());
+        let mut rest = src;
+        while let Some(start) = rest.find("{{") {
+            let len = match rest[start + 2..].find("}}") {
+                Some(len) => len,
+                None => break,
+            };
+            let end = start + 2 + len + 2;
+            output.push_str(&rest[..start]);
+            match self.value(rest[start + 2..end - 2].trim()) {
+                Some(value) => output.push_str(&value),
+                None => output.push_str(&rest[start..end]),
+            }
+            rest = &rest[end..];
+        }
+        output.push_str(rest);
+        output
+    }
+
+    fn value(&self, name: &str) -> Option<String> {
+        match name {
+            "date" => Some(self.now.format("%Y-%m-%d").to_string()),
+            "time" => Some(self.now.format("%H:%M").to_string()),
+            "weekday" => Some(self.now.format("%A").to_string()),
+            "user" => Some(self.user.clone().unwrap_or_default()),
+            "list" => Some(self.list.clone()),
+            _ => {
+                let days: i64 = name.strip_prefix("date")?.trim().parse().ok()?;
+                if days.abs() > 100_000 {
+                    return None;
+                }
+                let date = self
+                    .now
+                    .naive_local()
+                    .date()
+                    .checked_add_signed(Duration::days(days))?;
+                Some(date.format("%Y-%m-%d").to_string())
+            }
+        }
+    }
+}
+
+/// A template's body for the new item form, with its tags on a line at the
+/// end.
+pub fn body_with_tags(template: &repo::ItemTemplate) -> String {
+    if template.tags.is_empty() {
+        return template.body.clone();
+    }
+    let tags: Vec<String> = template.tags.iter().map(|t| format!("#{}", t)).collect();
+    let body = template.body.trim_end();
+    if body.is_empty() {
+        tags.join(" ")
+    } else {
+        format!("{}\n\n{}", body, tags.join(" "))
+    }
+}
+
+/// Tags as typed into the template form, separated by spaces or commas, with
+/// or without their `#`.
+pub fn parse_tags(src: &str) -> Vec<String> {
+    let hashtags: Vec<String> = src
+        .split([' ', ','])
+        .filter(|t| !t.is_empty())
+        .map(|t| format!("#{}", t.trim_start_matches('#')))
+        .collect();
+    markdown::tags(&hashtags.join(" "))
+}
+
+#[test]
+fn test_placeholders() {
+    use chrono::TimeZone;
+
+    let placeholders = Placeholders {
+        now: Local.ymd(2022, 11, 1).and_hms(14, 30, 0),
+        user: Some(String::from("nat")),
+        list: String::from("work"),
+    };
+    assert_eq!(
+        placeholders.expand("Meeting {{date}} ({{ weekday }} {{time}})"),
+        "Meeting 2022-11-01 (Tuesday 14:30)"
+    );
+    assert_eq!(
+        placeholders.expand("due: {{date+7}}, from {{user}} in {{list}}; was {{date-1}}"),
+        "due: 2022-11-08, from nat in work; was 2022-10-31"
+    );
+    // Unknown and unfinished placeholders are left alone
+    assert_eq!(
+        placeholders.expand("{{name}} {{date+x}} {{date"),
+        "{{name}} {{date+x}} {{date"
+    );
+
+    assert_eq!(
+        parse_tags("bug, #Triage  ops-2"),
+        ["bug", "triage", "ops-2"]
+    );
+    let template = repo::ItemTemplate {
+        id: 1,
+        name: String::from("Bug triage"),
+        title: String::from("Bug: "),
+        body: String::from("## Steps\n\n"),
+        important: true,
+        urgent: false,
+        tags: vec![String::from("bug"), String::from("triage")],
+    };
+    assert_eq!(body_with_tags(&template), "## Steps\n\n#bug #triage");
+}
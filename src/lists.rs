@@ -0,0 +1,161 @@
+//! Separate lists of items, like "work" and "home", in one database.
+//!
+//! Every item is in one list: the default list (`main`) unless it's been
+//! moved. Pages for other lists are under `/l/LIST/`, e.g. `/l/work/item/42`.
+//! [`strip_prefix`] takes the prefix off before routing, so handlers see
+//! `/item/42` and get the list from the [`List`] extractor. URLs without a
+//! prefix are for the default list.
+//!
+//! Item ids are unique across lists, so links like `#42` work from any list;
+//! an item's page redirects to the list it's in.
+
+use crate::repo::{Repo, SavedView, Session, DEFAULT_LIST};
+use crate::view_options::ViewOptions;
+use crate::{AppError, StatusCode};
+use axum::body::Body;
+use axum::extract::{FromRequest, OriginalUri, RequestParts};
+use axum::http::Request;
+use std::sync::{Arc, Mutex};
+
+/// The list named in a request's URL.
+#[derive(Clone)]
+struct ListName(String);
+
+/// Serve `/l/LIST/PATH` as `/PATH` in the list LIST.
+pub fn strip_prefix(mut req: Request<Body>) -> Request<Body> {
+    let (list, path) = match split_path(req.uri().path()) {
+        Some((list, path)) => (list.to_owned(), path.to_owned()),
+        None => return req,
+    };
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    if let Ok(uri) = axum::http::Uri::from_parts(parts) {
+        let original = OriginalUri(req.uri().clone());
+        req.extensions_mut().insert(original);
+        req.extensions_mut().insert(ListName(list));
+        *req.uri_mut() = uri;
+    }
+    req
+}
+
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix("/l/")?;
+    match rest.find('/') {
+        Some(i) => Some((&rest[..i], &rest[i..])),
+        None => Some((rest, "/")),
+    }
+}
+
+/// Where a list's pages are, to go in front of paths like `/item/42`. It's
+/// empty for the default list.
+pub fn base(list: &str) -> String {
+    if list == DEFAULT_LIST {
+        String::new()
+    } else {
+        format!("/l/{}", list)
+    }
+}
+
+/// The list a request is for, and the names of every list for the header's
+/// list switcher.
+pub struct List {
+    pub name: String,
+    pub names: Vec<String>,
+    /// Saved views for the header, with how many items they show in the list.
+    pub saved_views: Vec<(SavedView, usize)>,
+    /// The session's CSRF token, for the footer's log out button.
+    pub csrf: String,
+}
+
+impl List {
+    pub fn base(&self) -> String {
+        base(&self.name)
+    }
+
+    pub fn href(&self, name: &str) -> String {
+        format!("{}/", base(name))
+    }
+}
+
+impl Default for List {
+    /// The default list, without the names of the others (e.g. for people who
+    /// haven't logged in).
+    fn default() -> Self {
+        List {
+            name: String::from(DEFAULT_LIST),
+            names: Vec::new(),
+            saved_views: Vec::new(),
+            csrf: String::new(),
+        }
+    }
+}
+
+#[axum::async_trait]
+impl<B: Send> FromRequest<B> for List {
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let name = match req.extensions().get::<ListName>() {
+            Some(ListName(name)) => name.clone(),
+            None => String::from(DEFAULT_LIST),
+        };
+        let repomux = req
+            .extensions()
+            .get::<Arc<Mutex<Repo>>>()
+            .cloned()
+            .expect("The item repo should be added before lists are looked up");
+        let repo = repomux.lock().map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Couldn't lock the item repo: {:?}", e),
+            )
+        })?;
+        let names = repo.lists()?;
+        if !names.contains(&name) {
+            return Err((StatusCode::NOT_FOUND, format!("No list called '{}'", name)));
+        }
+        let mut saved_views = Vec::new();
+        for view in repo.saved_views()? {
+            // Views whose options are no longer valid don't show anything
+            let count = match ViewOptions::from_query(&view.query) {
+                Some(options) => repo.count(&name, options.filter_query()?.as_ref())?,
+                None => 0,
+            };
+            saved_views.push((view, count));
+        }
+        let csrf = req
+            .extensions()
+            .get::<Session>()
+            .map(|s| s.csrf.clone())
+            .unwrap_or_default();
+        Ok(List {
+            name,
+            names,
+            saved_views,
+            csrf,
+        })
+    }
+}
+
+#[test]
+fn test_strip_prefix() {
+    let request = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+    let req = strip_prefix(request("/l/work/item/42?who=mine"));
+    assert_eq!(req.uri(), "/item/42?who=mine");
+    assert!(matches!(req.extensions().get(), Some(ListName(name)) if name == "work"));
+    let original = req.extensions().get::<OriginalUri>().unwrap();
+    assert_eq!(original.0, "/l/work/item/42?who=mine");
+
+    assert_eq!(strip_prefix(request("/l/home")).uri(), "/");
+    let req = strip_prefix(request("/item/42"));
+    assert_eq!(req.uri(), "/item/42");
+    assert!(req.extensions().get::<ListName>().is_none());
+
+    assert_eq!(base(DEFAULT_LIST), "");
+    assert_eq!(base("home"), "/l/home");
+}
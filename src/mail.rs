@@ -0,0 +1,328 @@
+//! Turn emails into items. If the environment variable `WANNADO_MAILDIR`
+//! names a [Maildir], new messages in it are checked every minute (or every
+//! `WANNADO_MAILDIR_INTERVAL_IN_SECONDS`) and each becomes an item:
+//!
+//! * the subject is the title, with `[!]` or `[important]` marking the item as
+//!   important and `[urgent]` marking it as urgent,
+//! * the text of the message (or its HTML converted to text) is the body,
+//! * attachments are saved as the item's attachments.
+//!
+//! Processed messages are moved to the Maildir's `cur` directory and marked
+//! as seen; messages that couldn't be read or added are flagged instead.
+//!
+//! [Maildir]: https://cr.yp.to/proto/maildir.html
+
+use crate::handlers::sanitize_file_name;
+use crate::repo::{Repo, DEFAULT_LIST};
+use crate::{AppError, StatusCode};
+use mail_parser::{MessageParser, MimeHeaders};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub struct Mail {
+    pub title: String,
+    pub body: String,
+    pub important: bool,
+    pub urgent: bool,
+    pub attachments: Vec<MailAttachment>,
+}
+
+pub struct MailAttachment {
+    pub name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+pub fn start_maildir_ingestion(repomux: Arc<Mutex<Repo>>) {
+    let maildir = match std::env::var("WANNADO_MAILDIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => {
+            println!("No maildir to ingest");
+            return;
+        }
+    };
+    let interval = if let Ok(src) = std::env::var("WANNADO_MAILDIR_INTERVAL_IN_SECONDS") {
+        let seconds = src.parse::<u64>().expect(
+            "Invalid maildir interval, expected WANNADO_MAILDIR_INTERVAL_IN_SECONDS to be an integer",
+        );
+        Duration::from_secs(seconds)
+    } else {
+        Duration::from_secs(60)
+    };
+    println!(
+        "Ingesting mail from {} every {:?}",
+        maildir.display(),
+        interval
+    );
+    std::thread::spawn(move || loop {
+        if let Err((_, msg)) = ingest_maildir(&repomux, &maildir) {
+            eprintln!("Error ingesting mail: {}", msg);
+        }
+        std::thread::sleep(interval);
+    });
+}
+
+/// Create items from every new message in `maildir`. Returns the number of
+/// items created.
+pub fn ingest_maildir(repomux: &Mutex<Repo>, maildir: &Path) -> Result<usize, AppError> {
+    let mut messages: Vec<PathBuf> = std::fs::read_dir(maildir.join("new"))
+        .map_err(io_error)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()
+        .map_err(io_error)?;
+    messages.sort();
+
+    let mut count = 0;
+    for path in messages {
+        let raw = std::fs::read(&path).map_err(io_error)?;
+        let flag = match parse(&raw) {
+            Some(mail) => {
+                let mut repo = repomux.lock().map_err(|e| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Couldn't lock the item repo: {:?}", e),
+                    )
+                })?;
+                match repo.in_transaction(|repo| add_mail(repo, mail)) {
+                    Ok(()) => {
+                        count += 1;
+                        "S"
+                    }
+                    Err((_, msg)) => {
+                        eprintln!("Couldn't add email {}: {}", path.display(), msg);
+                        "F"
+                    }
+                }
+            }
+            None => {
+                eprintln!("Couldn't parse email {}", path.display());
+                "F"
+            }
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let processed = maildir.join("cur").join(format!("{}:2,{}", name, flag));
+        std::fs::rename(&path, processed).map_err(io_error)?;
+    }
+    Ok(count)
+}
+
+/// Add `mail` as an item. This takes a few steps, so call it in a transaction
+/// to avoid leaving half an item behind if one fails.
+pub fn add_mail(repo: &mut Repo, mail: Mail) -> Result<(), AppError> {
+    let mut item = repo.add(DEFAULT_LIST, &mail.title, &mail.body, mail.important, mail.urgent, None)?;
+    if mail.attachments.is_empty() {
+        return Ok(());
+    }
+    let mut list = String::from("\n\nAttachments:\n\n");
+    for attachment in &mail.attachments {
+        let saved = repo.add_attachment(
+            item.id,
+            &attachment.name,
+            &attachment.content_type,
+            &attachment.data,
+        )?;
+        list.push_str(&format!(
+            "- [{}](attachment:{})\n",
+            link_text(&saved.name),
+            saved.id
+        ));
+    }
+    item.body.push_str(&list);
+    repo.update(&mut item, None)
+}
+
+pub fn parse(raw: &[u8]) -> Option<Mail> {
+    let message = MessageParser::default().parse(raw)?;
+
+    let (title, important, urgent) = parse_subject(message.subject().unwrap_or_default());
+    let mut body = String::new();
+    if let Some(from) = message.from().and_then(|f| f.first()) {
+        let address = from.address.as_deref().unwrap_or_default();
+        match &from.name {
+            Some(name) => body.push_str(&format!("From: {} <{}>\n", name, address)),
+            None => body.push_str(&format!("From: {}\n", address)),
+        }
+    }
+    if let Some(date) = message.date() {
+        body.push_str(&format!("Date: {}\n", date.to_rfc3339()));
+    }
+    if !body.is_empty() {
+        body.push('\n');
+    }
+    body.push_str(message.body_text(0).unwrap_or_default().trim());
+
+    let attachments = message
+        .attachments()
+        .map(|part| {
+            let content_type = part
+                .content_type()
+                .map(|ct| match &ct.c_subtype {
+                    Some(subtype) => format!("{}/{}", ct.c_type, subtype),
+                    None => ct.c_type.to_string(),
+                })
+                .unwrap_or_else(|| String::from("application/octet-stream"));
+            let name = sanitize_file_name(part.attachment_name().unwrap_or_default());
+            MailAttachment {
+                name: if name.is_empty() {
+                    String::from("attachment")
+                } else {
+                    name
+                },
+                content_type,
+                data: part.contents().to_vec(),
+            }
+        })
+        .collect();
+
+    Some(Mail {
+        title: if title.is_empty() {
+            String::from("(no subject)")
+        } else {
+            title
+        },
+        body,
+        important,
+        urgent,
+        attachments,
+    })
+}
+
+/// Split the flag markers and any `Fwd:` prefix out of a subject.
+fn parse_subject(subject: &str) -> (String, bool, bool) {
+    let mut title = subject.to_owned();
+    let mut strip = |markers: &[&str]| {
+        let mut found = false;
+        for marker in markers {
+            while let Some(i) = title.to_ascii_lowercase().find(marker) {
+                title.replace_range(i..i + marker.len(), "");
+                found = true;
+            }
+        }
+        found
+    };
+    let important = strip(&["[!]", "[important]"]);
+    let urgent = strip(&["[urgent]"]);
+    let mut title = title.trim();
+    while let Some(rest) = ["fwd:", "fw:"].iter().find_map(|p| {
+        title
+            .to_ascii_lowercase()
+            .starts_with(p)
+            .then(|| &title[p.len()..])
+    }) {
+        title = rest.trim_start();
+    }
+    (
+        title.split_whitespace().collect::<Vec<_>>().join(" "),
+        important,
+        urgent,
+    )
+}
+
+/// Escape the characters that would end a Markdown link's text early.
+fn link_text(name: &str) -> String {
+    name.replace('\\', "\\\\")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+}
+
+fn io_error(err: std::io::Error) -> AppError {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Maildir error: {:?}", err),
+    )
+}
+
+#[test]
+fn test_parse_subject() {
+    assert_eq!(
+        parse_subject("Fwd: [!] Renew  the [URGENT] lease"),
+        (String::from("Renew the lease"), true, true)
+    );
+    assert_eq!(
+        parse_subject("Lunch?"),
+        (String::from("Lunch?"), false, false)
+    );
+}
+
+#[test]
+fn test_ingest_maildir() -> Result<(), AppError> {
+    let maildir = std::env::temp_dir().join(format!("wannado-maildir-test-{}", std::process::id()));
+    for sub in ["new", "cur", "tmp"] {
+        std::fs::create_dir_all(maildir.join(sub)).map_err(io_error)?;
+    }
+    let message = "From: Alice <alice@example.com>\r\n\
+                   Subject: [urgent] Review the budget\r\n\
+                   Date: Tue, 1 Nov 2022 09:00:00 +0000\r\n\
+                   MIME-Version: 1.0\r\n\
+                   Content-Type: multipart/mixed; boundary=\"XYZ\"\r\n\
+                   \r\n\
+                   --XYZ\r\n\
+                   Content-Type: text/plain\r\n\
+                   \r\n\
+                   Numbers attached.\r\n\
+                   --XYZ\r\n\
+                   Content-Type: text/csv\r\n\
+                   Content-Disposition: attachment; filename=\"budget.csv\"\r\n\
+                   \r\n\
+                   a,b\r\n1,2\r\n\
+                   --XYZ--\r\n";
+    std::fs::write(maildir.join("new").join("1667293200.M1P1.host"), message).map_err(io_error)?;
+
+    let mut repo = Repo::new(rusqlite::Connection::open_in_memory().unwrap());
+    repo.init().unwrap();
+    let repomux = Mutex::new(repo);
+    assert_eq!(ingest_maildir(&repomux, &maildir)?, 1);
+    assert_eq!(ingest_maildir(&repomux, &maildir)?, 0);
+
+    let mut repo = repomux.into_inner().unwrap();
+    let item = repo.all_in(DEFAULT_LIST)?.pop().unwrap();
+    assert_eq!(item.title, "Review the budget");
+    assert!(item.urgent && !item.important);
+    assert!(item.body.starts_with("From: Alice <alice@example.com>\n"));
+    assert!(item.body.contains("Numbers attached."));
+    let attachments = repo.attachments(item.id)?;
+    assert_eq!(attachments[0].name, "budget.csv");
+    assert_eq!(attachments[0].content_type, "text/csv");
+    assert!(item
+        .body
+        .contains(&format!("[budget.csv](attachment:{})", attachments[0].id)));
+    assert!(maildir
+        .join("cur")
+        .join("1667293200.M1P1.host:2,S")
+        .exists());
+
+    std::fs::remove_dir_all(&maildir).map_err(io_error)?;
+    Ok(())
+}
+
+#[test]
+fn test_attachment_names() -> Result<(), AppError> {
+    let message = "Subject: Drafts\r\n\
+                   MIME-Version: 1.0\r\n\
+                   Content-Type: multipart/mixed; boundary=\"XYZ\"\r\n\
+                   \r\n\
+                   --XYZ\r\n\
+                   Content-Type: text/plain\r\n\
+                   \r\n\
+                   See attached.\r\n\
+                   --XYZ\r\n\
+                   Content-Type: text/plain\r\n\
+                   Content-Disposition: attachment; filename=\"../[draft] notes.txt\"\r\n\
+                   \r\n\
+                   Notes\r\n\
+                   --XYZ--\r\n";
+    let mail = parse(message.as_bytes()).unwrap();
+    assert_eq!(mail.attachments[0].name, "[draft] notes.txt");
+
+    let mut repo = Repo::new(rusqlite::Connection::open_in_memory().unwrap());
+    repo.init().unwrap();
+    repo.in_transaction(|repo| add_mail(repo, mail))?;
+    let item = repo.all_in(DEFAULT_LIST)?.pop().unwrap();
+    let attachments = repo.attachments(item.id)?;
+    assert!(item.body.contains(&format!(
+        "- [\\[draft\\] notes.txt](attachment:{})",
+        attachments[0].id
+    )));
+    Ok(())
+}
@@ -1,23 +1,60 @@
 use axum::{extract::Extension, http::StatusCode};
 use std::sync::{Arc, Mutex};
+use tower::{make::Shared, ServiceExt};
 use tower_http::services::ServeDir;
+use tower_http::set_header::SetResponseHeaderLayer;
 
+mod auth;
+mod backup;
+mod calendar;
+mod capture;
+mod cli;
+mod client;
+mod feed;
 mod handlers;
+mod importers;
+mod item_templates;
+mod lists;
+mod mail;
+mod markdown;
+mod query;
+mod render;
 mod repo;
 mod script;
+mod stats;
 mod template;
+mod transfer;
+mod tui;
+mod view_options;
 
 // ------------------------------------------------------
 // Helpers
 pub(crate) type AppError = (StatusCode, String);
 
+pub(crate) const DATABASE: &str = "./items.sqlite3";
+
+/// Only run scripts from `/static`, as a second line of defence behind the
+/// sanitizing of item bodies. Inline styles are allowed for the alignment of
+/// table columns in rendered Markdown; images can come from anywhere.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'self'; script-src 'self'; \
+    style-src 'self' 'unsafe-inline'; img-src * data:; object-src 'none'; \
+    base-uri 'none'; frame-ancestors 'none'; form-action 'self'";
+
 #[tokio::main]
 async fn main() {
     use std::net::SocketAddr;
 
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(result) = cli::run(&args[1..]) {
+        if let Err((_, msg)) = result {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Initialize database
     let addr: SocketAddr = {
-        let args: Vec<String> = std::env::args().collect();
         if args.len() == 2 {
             args[1]
                 .parse()
@@ -30,44 +67,134 @@ async fn main() {
         }
     };
 
-    let app = newapp();
+    let repomux = Arc::new(Mutex::new(open_repo()));
+    backup::start_scheduled_backups(DATABASE.into());
+    mail::start_maildir_ingestion(repomux.clone());
+    let app = newapp(repomux).map_request(lists::strip_prefix);
 
     script::start_recurring_script();
     println!("Listening on {addr}");
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(Shared::new(app))
         .await
         .expect("Failed to start server");
 }
 
-fn newapp() -> axum::Router {
+fn newapp(repomux: Arc<Mutex<repo::Repo>>) -> axum::Router {
+    use axum::middleware::from_fn;
     use axum::routing::{get, get_service, post};
-    use repo::Repo;
 
     let static_files =
         get_service(ServeDir::new("./static").precompressed_br()).handle_error(|err: std::io::Error| async move {
             (StatusCode::NOT_FOUND, format!("Not Found: {}", err))
         });
-    let cxn = rusqlite::Connection::open("./items.sqlite3").expect("Couldn't open database");
-    let mut repo = Repo::new(cxn);
-    repo.init().expect("Database initialisation failed");
-    let repomux = Arc::new(Mutex::new(repo));
 
     axum::Router::new()
         .route("/", get(handlers::get_items))
+        .route("/login", get(handlers::get_login).post(handlers::post_login))
+        .route("/logout", post(handlers::post_logout))
         .route(
             "/item/new",
             get(handlers::get_new_item).post(handlers::post_new_item),
         )
+        .route("/item/new/draft", post(handlers::post_new_item_draft))
+        .route(
+            "/item/new/draft/discard",
+            post(handlers::post_discard_new_item_draft),
+        )
+        .route(
+            "/capture",
+            get(handlers::get_capture).post(handlers::post_capture),
+        )
+        .route("/render", post(handlers::post_render))
+        .route("/items/order", post(handlers::post_item_order))
+        .route("/views", post(handlers::post_saved_view))
+        .route("/views/delete", post(handlers::post_delete_saved_view))
+        .route("/capture/bookmarklet", get(handlers::get_bookmarklet))
+        .route("/manifest.webmanifest", get(handlers::get_manifest))
+        .route("/calendar.ics", get(handlers::get_calendar))
+        .route("/feed.atom", get(handlers::get_feed))
+        .route("/stats", get(handlers::get_stats))
+        .route("/export", get(handlers::get_export))
+        .route("/import", get(handlers::get_import).post(handlers::post_import))
+        .route("/import/preview", post(handlers::post_import_preview))
+        .route(
+            "/admin/backups",
+            get(handlers::get_backups).post(handlers::post_backup),
+        )
+        .route("/admin/backups/:name", get(handlers::get_backup))
+        .route(
+            "/admin/tokens",
+            get(handlers::get_api_tokens).post(handlers::post_api_token),
+        )
+        .route(
+            "/admin/tokens/:id/revoke",
+            post(handlers::post_revoke_api_token),
+        )
+        .route("/lists", get(handlers::get_lists).post(handlers::post_list))
+        .route("/lists/:name/delete", post(handlers::post_delete_list))
+        .route(
+            "/templates",
+            get(handlers::get_item_templates).post(handlers::post_item_template),
+        )
+        .route("/templates/new", get(handlers::get_new_item_template))
+        .route(
+            "/templates/:id",
+            get(handlers::get_edit_item_template).post(handlers::post_edit_item_template),
+        )
+        .route(
+            "/templates/:id/delete",
+            post(handlers::post_delete_item_template),
+        )
         .route("/deleted", get(handlers::get_deleted_items))
         .route("/deleted/:id", get(handlers::get_deleted_item))
         .route("/deleted/:id/restore", post(handlers::restore_item))
+        .route("/deleted/:id/purge", post(handlers::post_purge_item))
         .route("/item/:id", get(handlers::get_item))
         .route(
             "/item/:id/edit",
             get(handlers::get_edit_item).post(handlers::post_edit_item),
         )
+        .route("/item/:id/draft", post(handlers::post_item_draft))
+        .route(
+            "/item/:id/draft/discard",
+            post(handlers::post_discard_item_draft),
+        )
         .route("/item/:id/delete", post(handlers::post_delete_item))
+        .route("/item/:id/task", post(handlers::post_toggle_task))
+        .route("/item/:id/attachments", post(handlers::post_attachments))
+        .route(
+            "/api/items",
+            get(handlers::get_api_items).post(handlers::post_api_item),
+        )
+        .route(
+            "/api/items/:id",
+            get(handlers::get_api_item)
+                .put(handlers::put_api_item)
+                .delete(handlers::delete_api_item),
+        )
+        .route("/api/stats", get(handlers::get_api_stats))
+        .route("/attachment/:id", get(handlers::get_attachment))
+        .route("/attachment/:id/delete", post(handlers::post_delete_attachment))
+        .layer(from_fn(auth::require_login))
         .layer(Extension(repomux))
         .nest("/static", static_files)
+        .layer(SetResponseHeaderLayer::if_not_present(
+            axum::http::header::CONTENT_SECURITY_POLICY,
+            axum::http::HeaderValue::from_static(CONTENT_SECURITY_POLICY),
+        ))
+}
+
+pub(crate) fn open_repo() -> repo::Repo {
+    let cxn = rusqlite::Connection::open(DATABASE).expect("Couldn't open database");
+    let mut repo = repo::Repo::new(cxn);
+    if let Ok(dir) = std::env::var("WANNADO_ATTACHMENTS_DIR") {
+        std::fs::create_dir_all(&dir).expect("Couldn't create attachments directory");
+        repo.store_attachments_in(dir.into());
+    }
+    repo.init().expect("Database initialisation failed");
+    repo.index_links().expect("Indexing links between items failed");
+    repo.remove_orphaned_attachments()
+        .expect("Removing orphaned attachments failed");
+    repo
 }
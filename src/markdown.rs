@@ -0,0 +1,481 @@
+//! Helpers for working with the CommonMark in item bodies.
+//!
+//! Rendering happens in the `render` module; the functions here deal with
+//! the structure of the source text (e.g. locating task-list markers so they
+//! can be toggled in place, or finding references to other items).
+
+use crate::{AppError, StatusCode};
+use chrono::NaiveDate;
+use pulldown_cmark::{escape::escape_html, CowStr, Event, Options, Parser, Tag};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+pub fn options() -> Options {
+    let features = Features::get();
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options.insert(Options::ENABLE_SMART_PUNCTUATION);
+    if features.footnotes {
+        options.insert(Options::ENABLE_FOOTNOTES);
+    }
+    if features.strikethrough {
+        options.insert(Options::ENABLE_STRIKETHROUGH);
+    }
+    options
+}
+
+/// Optional extras for rendering bodies, chosen per installation with
+/// `WANNADO_MARKDOWN_FEATURES` (see the `render` module).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    pub highlight: bool,
+    pub footnotes: bool,
+    pub strikethrough: bool,
+    pub anchors: bool,
+    pub autolink: bool,
+}
+
+impl Features {
+    pub const ALL: Features = Features {
+        highlight: true,
+        footnotes: true,
+        strikethrough: true,
+        anchors: true,
+        autolink: true,
+    };
+
+    pub const NONE: Features = Features {
+        highlight: false,
+        footnotes: false,
+        strikethrough: false,
+        anchors: false,
+        autolink: false,
+    };
+
+    /// The installation's features: everything unless the environment says
+    /// otherwise.
+    pub fn get() -> Features {
+        static FEATURES: OnceLock<Features> = OnceLock::new();
+        *FEATURES.get_or_init(|| match std::env::var("WANNADO_MARKDOWN_FEATURES") {
+            Ok(src) => src.parse().unwrap_or_else(|(_, msg): AppError| {
+                eprintln!("{}, using all of them", msg);
+                Features::ALL
+            }),
+            Err(_) => Features::ALL,
+        })
+    }
+}
+
+impl std::str::FromStr for Features {
+    type Err = AppError;
+
+    /// Comma-separated feature names, or `all` or `none`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut features = Features::NONE;
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match name {
+                "all" => features = Features::ALL,
+                "none" => {}
+                "highlight" => features.highlight = true,
+                "footnotes" => features.footnotes = true,
+                "strikethrough" => features.strikethrough = true,
+                "anchors" => features.anchors = true,
+                "autolink" => features.autolink = true,
+                _ => {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        format!("Unknown Markdown feature '{}'", name),
+                    ))
+                }
+            }
+        }
+        Ok(features)
+    }
+}
+
+/// A `- [ ]` or `- [x]` marker in a body, identified by the byte offset of
+/// its opening bracket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskMarker {
+    pub offset: usize,
+    pub checked: bool,
+}
+
+pub fn task_markers(src: &str) -> Vec<TaskMarker> {
+    Parser::new_ext(src, options())
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::TaskListMarker(checked) => Some(TaskMarker {
+                offset: range.start,
+                checked,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Number of completed and total tasks in a body.
+pub fn task_progress(src: &str) -> (usize, usize) {
+    let markers = task_markers(src);
+    let done = markers.iter().filter(|m| m.checked).count();
+    (done, markers.len())
+}
+
+/// Flip the task marker starting at `offset`. Returns `None` if there's no
+/// task marker there.
+pub fn toggle_task(src: &str, offset: usize) -> Option<String> {
+    let marker = task_markers(src).into_iter().find(|m| m.offset == offset)?;
+    let replacement = if marker.checked { "[ ]" } else { "[x]" };
+    let mut toggled = String::with_capacity(src.len());
+    toggled.push_str(src.get(..offset)?);
+    toggled.push_str(replacement);
+    toggled.push_str(src.get(offset + 3..)?);
+    Some(toggled)
+}
+
+/// A link to another item, written as `#42` or `[[Item title]]`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Reference {
+    Id(u32),
+    Title(String),
+}
+
+/// What a reference points at, for rendering.
+#[derive(Debug, Clone)]
+pub struct LinkTarget {
+    pub id: u32,
+    pub title: String,
+    pub deleted: bool,
+}
+
+/// The resolved references in a body. References missing from this are
+/// rendered as broken links.
+#[derive(Debug, Clone, Default)]
+pub struct Links {
+    targets: HashMap<Reference, LinkTarget>,
+}
+
+impl Links {
+    pub fn insert(&mut self, reference: Reference, target: LinkTarget) {
+        self.targets.insert(reference, target);
+    }
+
+    fn get(&self, reference: &Reference) -> Option<&LinkTarget> {
+        self.targets.get(reference)
+    }
+}
+
+/// All the references to other items in a body, in order of appearance.
+pub fn references(src: &str) -> Vec<Reference> {
+    let events = merge_text(Parser::new_ext(src, options()));
+    let mut references = Vec::new();
+    for_linkable_text(&events, |text| {
+        references.extend(
+            split_references(text)
+                .into_iter()
+                .filter_map(|span| match span {
+                    Span::Reference(_, reference) => Some(reference),
+                    Span::Text(_) => None,
+                }),
+        )
+    });
+    references
+}
+
+/// Replace references in text with links to the items they point at.
+pub fn link_references<'a>(
+    events: impl IntoIterator<Item = Event<'a>>,
+    links: &Links,
+) -> Vec<Event<'a>> {
+    let mut output = Vec::new();
+    let mut nesting = 0;
+    for event in merge_text(events) {
+        match event {
+            Event::Start(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => {
+                nesting += 1;
+                output.push(event);
+            }
+            Event::End(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => {
+                nesting -= 1;
+                output.push(event);
+            }
+            Event::Text(text) if nesting == 0 => {
+                for span in split_references(&text) {
+                    output.push(match span {
+                        Span::Text(t) => Event::Text(CowStr::from(t.to_owned())),
+                        Span::Reference(src, reference) => {
+                            Event::Html(CowStr::from(render_reference(src, links.get(&reference))))
+                        }
+                    })
+                }
+            }
+            _ => output.push(event),
+        }
+    }
+    output
+}
+
+fn render_reference(src: &str, target: Option<&LinkTarget>) -> String {
+    let mut label = String::new();
+    let mut html = String::new();
+    // Writing to a String can't fail
+    let _ = escape_html(
+        &mut label,
+        src.trim_start_matches("[[").trim_end_matches("]]"),
+    );
+    match target {
+        Some(target) => {
+            let mut title = String::new();
+            let _ = escape_html(&mut title, &target.title);
+            let (href, class) = if target.deleted {
+                (format!("/deleted/{}", target.id), "item-link broken")
+            } else {
+                (format!("/item/{}", target.id), "item-link")
+            };
+            html.push_str(&format!(
+                "<a href=\"{}\" class=\"{}\" title=\"{}\">{}</a>",
+                href, class, title, label
+            ));
+        }
+        None => html.push_str(&format!(
+            "<span class=\"item-link broken\">{}</span>",
+            label
+        )),
+    }
+    html
+}
+
+/// Point `attachment:<id>` link and image URLs at the attachment.
+pub fn attachment_urls(event: Event<'_>) -> Event<'_> {
+    fn rewrite(dest: CowStr<'_>) -> CowStr<'_> {
+        match dest.strip_prefix("attachment:") {
+            Some(id) => CowStr::from(format!("/attachment/{}", id)),
+            None => dest,
+        }
+    }
+    match event {
+        Event::Start(Tag::Link(kind, dest, title)) => {
+            Event::Start(Tag::Link(kind, rewrite(dest), title))
+        }
+        Event::Start(Tag::Image(kind, dest, title)) => {
+            Event::Start(Tag::Image(kind, rewrite(dest), title))
+        }
+        Event::End(Tag::Link(kind, dest, title)) => {
+            Event::End(Tag::Link(kind, rewrite(dest), title))
+        }
+        Event::End(Tag::Image(kind, dest, title)) => {
+            Event::End(Tag::Image(kind, rewrite(dest), title))
+        }
+        _ => event,
+    }
+}
+
+/// Hashtags (e.g. `#ops`) in a body, lower-cased and without duplicates.
+/// Numeric tags like `#42` are references to other items, not tags.
+pub fn tags(src: &str) -> Vec<String> {
+    let events = merge_text(Parser::new_ext(src, options()));
+    let mut tags: Vec<String> = Vec::new();
+    for_linkable_text(&events, |text| {
+        for (i, _) in text.match_indices('#') {
+            if text[..i].ends_with(char::is_alphanumeric) {
+                continue;
+            }
+            let tag: String = text[i + 1..]
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+                .collect();
+            let tag = tag.trim_end_matches(['-', '_']).to_lowercase();
+            if tag.starts_with(char::is_alphabetic) && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+    });
+    tags
+}
+
+/// The date in a `due: 2022-11-01` line, if there is one.
+pub fn due_date(src: &str) -> Option<NaiveDate> {
+    keyword_date(src, "due")
+}
+
+/// The date in a `restore-on 2022-11-01` line, which the automation script
+/// can use to un-delete (i.e. snooze) items.
+pub fn snoozed_until(src: &str) -> Option<NaiveDate> {
+    keyword_date(src, "restore-on")
+}
+
+/// The first date following `keyword` (and an optional colon), ignoring case.
+fn keyword_date(src: &str, keyword: &str) -> Option<NaiveDate> {
+    let lower = src.to_lowercase();
+    lower.match_indices(keyword).find_map(|(i, _)| {
+        if lower[..i].ends_with(|c: char| c.is_alphanumeric() || c == '-') {
+            return None;
+        }
+        let rest = lower[i + keyword.len()..]
+            .trim_start_matches(':')
+            .trim_start();
+        NaiveDate::parse_from_str(rest.get(..10)?, "%Y-%m-%d").ok()
+    })
+}
+
+/// Pulldown-cmark splits text on brackets, which would hide `[[...]]`
+/// references, so adjacent text events are joined before looking for them.
+fn merge_text<'a>(events: impl IntoIterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    let mut merged: Vec<Event<'a>> = Vec::new();
+    for event in events {
+        match (merged.last_mut(), event) {
+            (Some(Event::Text(previous)), Event::Text(text)) => {
+                *previous = CowStr::from(format!("{}{}", previous, text));
+            }
+            (_, event) => merged.push(event),
+        }
+    }
+    merged
+}
+
+/// Call `f` on each bit of text that isn't already a link or code.
+fn for_linkable_text<'a>(events: &[Event<'a>], mut f: impl FnMut(&str)) {
+    let mut nesting = 0;
+    for event in events {
+        match event {
+            Event::Start(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => nesting += 1,
+            Event::End(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => nesting -= 1,
+            Event::Text(text) if nesting == 0 => f(text),
+            _ => {}
+        }
+    }
+}
+
+enum Span<'a> {
+    Text(&'a str),
+    Reference(&'a str, Reference),
+}
+
+fn split_references(text: &str) -> Vec<Span<'_>> {
+    let mut spans = Vec::new();
+    let mut start = 0; // Start of the current run of plain text
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let found = if let Some(inner) = rest.strip_prefix("[[") {
+            inner.find("]]").and_then(|end| {
+                let title = &inner[..end];
+                let valid = !title.trim().is_empty() && !title.contains(['[', ']', '\n']);
+                valid.then(|| (end + 4, Reference::Title(title.trim().to_owned())))
+            })
+        } else if let Some(inner) = rest.strip_prefix('#') {
+            let digits = inner.len() - inner.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let boundary = !text[..i].ends_with(char::is_alphanumeric)
+                && !inner[digits..].starts_with(char::is_alphanumeric);
+            match inner[..digits].parse() {
+                Ok(id) if boundary => Some((digits + 1, Reference::Id(id))),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        match found {
+            Some((len, reference)) => {
+                if start < i {
+                    spans.push(Span::Text(&text[start..i]));
+                }
+                spans.push(Span::Reference(&text[i..i + len], reference));
+                i += len;
+                start = i;
+            }
+            None => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    if start < text.len() {
+        spans.push(Span::Text(&text[start..]));
+    }
+    spans
+}
+
+#[test]
+fn test_toggle_task() {
+    let src = "Shopping:\n\n- [ ] eggs\n- [X] milk\n\n`[ ]` isn't a task\n";
+    let markers = task_markers(src);
+    assert_eq!(markers.len(), 2);
+    assert_eq!(&src[markers[0].offset..markers[0].offset + 3], "[ ]");
+    assert!(markers[1].checked);
+    assert_eq!(task_progress(src), (1, 2));
+
+    let toggled = toggle_task(src, markers[0].offset).unwrap();
+    assert_eq!(task_progress(&toggled), (2, 2));
+    let toggled = toggle_task(&toggled, markers[1].offset).unwrap();
+    assert_eq!(
+        toggled,
+        "Shopping:\n\n- [x] eggs\n- [ ] milk\n\n`[ ]` isn't a task\n"
+    );
+
+    assert!(toggle_task(src, src.find("`[ ]`").unwrap() + 1).is_none());
+    assert!(toggle_task(src, 0).is_none());
+}
+
+#[test]
+fn test_references() {
+    let src = "See #12 and [[Buy milk]], not `#13`, [#14](/x) or issue#15.\n\n\
+               ```\n#16\n```\n\n[[]] [[Gone]]";
+    assert_eq!(
+        references(src),
+        vec![
+            Reference::Id(12),
+            Reference::Title("Buy milk".to_owned()),
+            Reference::Title("Gone".to_owned()),
+        ]
+    );
+
+    let mut links = Links::default();
+    links.insert(
+        Reference::Id(12),
+        LinkTarget {
+            id: 12,
+            title: "Twelve <12>".to_owned(),
+            deleted: false,
+        },
+    );
+    links.insert(
+        Reference::Title("Buy milk".to_owned()),
+        LinkTarget {
+            id: 3,
+            title: "Buy milk".to_owned(),
+            deleted: true,
+        },
+    );
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(
+        &mut html,
+        link_references(Parser::new_ext(src, options()), &links).into_iter(),
+    );
+    assert!(
+        html.contains(r#"<a href="/item/12" class="item-link" title="Twelve &lt;12&gt;">#12</a>"#)
+    );
+    assert!(html.contains(
+        r#"<a href="/deleted/3" class="item-link broken" title="Buy milk">Buy milk</a>"#
+    ));
+    assert!(html.contains(r#"<span class="item-link broken">Gone</span>"#));
+    assert!(html.contains("issue#15"));
+}
+
+#[test]
+fn test_tags_and_dates() {
+    let src = "#ops #42 and #Ops-team, not `#code` or issue#1\n\n\
+               Due: 2022-11-03\n\nrestore-on 2022-12-01\n\n## Heading";
+    assert_eq!(tags(src), vec!["ops", "ops-team"]);
+    assert_eq!(due_date(src), NaiveDate::from_ymd_opt(2022, 11, 3));
+    assert_eq!(snoozed_until(src), NaiveDate::from_ymd_opt(2022, 12, 1));
+    assert_eq!(due_date("overdue: 2022-11-03"), None);
+    assert_eq!(due_date("due: someday"), None);
+}
+
+#[test]
+fn test_features() {
+    assert_eq!("all".parse::<Features>().unwrap(), Features::ALL);
+    assert_eq!("none".parse::<Features>().unwrap(), Features::NONE);
+    let features: Features = "footnotes, strikethrough".parse().unwrap();
+    assert!(features.footnotes && features.strikethrough);
+    assert!(!features.highlight && !features.anchors && !features.autolink);
+    assert!("footnotes,emoji".parse::<Features>().is_err());
+}
@@ -0,0 +1,481 @@
+//! A small query language for finding items, like
+//! `urgent AND tag:ops AND age>7d`. Queries are compiled to SQL over the
+//! items table (see `Repo::search`), and used by the home page's `q` option
+//! (and so saved views), the API's `/api/items?q=` and `wannado ls --query`.
+//!
+//! Terms:
+//!
+//! * `important`, `urgent`: items with that flag
+//! * `tag:ops` or `#ops`: items tagged `#ops`
+//! * `age>7d`, `age<2w`: items created more than a week ago, or in the last
+//!   two weeks (`d` for days, `w` for weeks)
+//! * `due`: items with a due date; `due<7d` for ones due in the next week
+//!   (or overdue), `due>7d` for ones due after that
+//! * `owner:nat`: items owned by the user `nat`
+//! * `title:word`: items with the word in their title
+//! * any other word, or `"a phrase"` in quotes: items with it in their
+//!   title or body, ignoring case
+//!
+//! Terms can be combined with `AND` (or just a space), `OR` and `NOT` (or a
+//! `-` in front of a term), and grouped with brackets. `AND` binds tighter
+//! than `OR`, so `important OR urgent AND #ops` is
+//! `important OR (urgent AND #ops)`. Keywords aren't case-sensitive; put
+//! words like `"urgent"` in quotes to search for them as text.
+
+use crate::view_options::Age;
+use crate::{AppError, StatusCode};
+use chrono::{DateTime, Duration, Local};
+use rusqlite::types::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Term(Term),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    Important,
+    Urgent,
+    Tag(String),
+    Age(Age),
+    HasDue,
+    /// Due on or before this many days from today.
+    DueWithin(i64),
+    /// Due after this many days from today.
+    DueAfter(i64),
+    Owner(String),
+    Title(String),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    /// A word, and whether it was in quotes.
+    Word(String, bool),
+}
+
+impl std::str::FromStr for Query {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Parser::parse(tokenize(s)?)
+    }
+}
+
+impl Query {
+    /// A condition for a `WHERE` clause over the items table, with its
+    /// parameters. It uses the SQL functions set up by `Repo::init`.
+    pub fn to_sql(&self, now: DateTime<Local>) -> (String, Vec<Value>) {
+        let mut params = Vec::new();
+        let sql = self.write_sql(now, &mut params);
+        (sql, params)
+    }
+
+    fn write_sql(&self, now: DateTime<Local>, params: &mut Vec<Value>) -> String {
+        match self {
+            Query::And(a, b) => format!(
+                "({} AND {})",
+                a.write_sql(now, params),
+                b.write_sql(now, params)
+            ),
+            Query::Or(a, b) => format!(
+                "({} OR {})",
+                a.write_sql(now, params),
+                b.write_sql(now, params)
+            ),
+            // Terms are NULL for items without the field they look at (e.g.
+            // items without a due date or an owner), which NOT would leave
+            // NULL, so count those as false first
+            Query::Not(q) => format!("NOT coalesce({}, 0)", q.write_sql(now, params)),
+            Query::Term(term) => term.write_sql(now, params),
+        }
+    }
+}
+
+impl Term {
+    fn write_sql(&self, now: DateTime<Local>, params: &mut Vec<Value>) -> String {
+        let mut param = |value: Value| {
+            params.push(value);
+            "?"
+        };
+        let due_date = |days: i64| {
+            let date = now.naive_local().date() + Duration::days(days);
+            Value::Text(date.format("%Y-%m-%d").to_string())
+        };
+        match self {
+            Term::Important => String::from("coalesce(json_extract(item, '$.important'), 0)"),
+            Term::Urgent => String::from("coalesce(json_extract(item, '$.urgent'), 0)"),
+            Term::Tag(tag) => format!(
+                "wannado_has_tag(json_extract(item, '$.body'), {})",
+                param(Value::Text(tag.clone()))
+            ),
+            Term::Age(age) => {
+                let cutoff = now.timestamp() - age.days * 24 * 60 * 60;
+                let op = if age.older { "<" } else { ">=" };
+                format!(
+                    "json_extract(item, '$.created') {} {}",
+                    op,
+                    param(Value::Integer(cutoff))
+                )
+            }
+            Term::HasDue => String::from("wannado_due(json_extract(item, '$.body')) IS NOT NULL"),
+            Term::DueWithin(days) => format!(
+                "wannado_due(json_extract(item, '$.body')) <= {}",
+                param(due_date(*days))
+            ),
+            Term::DueAfter(days) => format!(
+                "wannado_due(json_extract(item, '$.body')) > {}",
+                param(due_date(*days))
+            ),
+            Term::Owner(name) => format!(
+                "json_extract(item, '$.owner') IN (SELECT id FROM users WHERE name = {})",
+                param(Value::Text(name.clone()))
+            ),
+            Term::Title(text) => format!(
+                "wannado_contains(json_extract(item, '$.title'), {})",
+                param(Value::Text(text.clone()))
+            ),
+            Term::Text(text) => {
+                let title = param(Value::Text(text.clone()));
+                let body = param(Value::Text(text.clone()));
+                format!(
+                    "(wannado_contains(json_extract(item, '$.title'), {}) \
+                     OR wannado_contains(json_extract(item, '$.body'), {}))",
+                    title, body
+                )
+            }
+        }
+    }
+
+    fn parse(word: &str, quoted: bool) -> Result<Term, AppError> {
+        if quoted {
+            return Ok(Term::Text(word.to_owned()));
+        }
+        let lower = word.to_lowercase();
+        let value = |prefix: &str| word[prefix.len()..].to_owned();
+        let term = match lower.as_str() {
+            "important" => Term::Important,
+            "urgent" => Term::Urgent,
+            "due" => Term::HasDue,
+            _ if lower.starts_with("tag:") => {
+                Term::Tag(lower["tag:".len()..].trim_start_matches('#').to_owned())
+            }
+            _ if lower.starts_with('#') => Term::Tag(lower[1..].to_owned()),
+            _ if lower.starts_with("age<") || lower.starts_with("age>") => {
+                Term::Age(lower["age".len()..].parse()?)
+            }
+            _ if lower.starts_with("due<") || lower.starts_with("due>") => {
+                let age: Age = lower["due".len()..].parse()?;
+                if age.older {
+                    Term::DueAfter(age.days)
+                } else {
+                    Term::DueWithin(age.days)
+                }
+            }
+            _ if lower.starts_with("owner:") => Term::Owner(value("owner:")),
+            _ if lower.starts_with("title:") => Term::Title(value("title:")),
+            _ => Term::Text(word.to_owned()),
+        };
+        match &term {
+            Term::Tag(value) | Term::Owner(value) | Term::Title(value) if value.is_empty() => {
+                Err(invalid(&format!("'{}' needs a value", word)))
+            }
+            _ => Ok(term),
+        }
+    }
+}
+
+/// How deeply queries can be nested, counting `AND`, `OR` and `NOT`. Compiling
+/// and dropping queries recurse through them, so this keeps those from
+/// running out of stack.
+const MAX_DEPTH: usize = 1000;
+
+/// Operators waiting for their operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Open,
+    Or,
+    And,
+    Not,
+}
+
+impl Op {
+    /// How tightly the operator binds. Brackets are only closed by `)`.
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Open => 0,
+            Op::Or => 1,
+            Op::And => 2,
+            Op::Not => 3,
+        }
+    }
+}
+
+/// A shunting-yard parser, which doesn't recurse so that deeply nested
+/// queries can't overflow the stack. Parsed queries are kept with their depth.
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    ops: Vec<Op>,
+    queries: Vec<(Query, usize)>,
+}
+
+impl Parser {
+    fn parse(tokens: Vec<Token>) -> Result<Query, AppError> {
+        let mut parser = Parser {
+            tokens: tokens.into_iter().peekable(),
+            ops: Vec::new(),
+            queries: Vec::new(),
+        };
+        loop {
+            parser.term()?;
+            if !parser.operator()? {
+                break;
+            }
+        }
+        while parser.ops.last().is_some_and(|&op| op != Op::Open) {
+            parser.reduce()?;
+        }
+        if !parser.ops.is_empty() {
+            return Err(invalid("missing ')'"));
+        }
+        let (query, _) = parser
+            .queries
+            .pop()
+            .ok_or_else(|| invalid("expected a term"))?;
+        Ok(query)
+    }
+
+    /// Read a term, along with any `NOT`s and brackets in front of it.
+    fn term(&mut self) -> Result<(), AppError> {
+        loop {
+            match self.tokens.next() {
+                Some(Token::Open) => self.ops.push(Op::Open),
+                Some(Token::Word(word, false)) if word.eq_ignore_ascii_case("not") => {
+                    self.ops.push(Op::Not)
+                }
+                Some(Token::Word(word, false))
+                    if ["and", "or"].iter().any(|k| word.eq_ignore_ascii_case(k)) =>
+                {
+                    return Err(invalid(&format!("expected a term before '{}'", word)));
+                }
+                Some(Token::Word(word, false)) if word.len() > 1 && word.starts_with('-') => {
+                    let term = Term::parse(&word[1..], false)?;
+                    self.queries
+                        .push((Query::Not(Box::new(Query::Term(term))), 2));
+                    return Ok(());
+                }
+                Some(Token::Word(word, quoted)) => {
+                    self.queries
+                        .push((Query::Term(Term::parse(&word, quoted)?), 1));
+                    return Ok(());
+                }
+                Some(Token::Close) => return Err(invalid("unmatched ')'")),
+                None => return Err(invalid("expected a term")),
+            }
+        }
+    }
+
+    /// Read any closing brackets after a term, and the operator before the
+    /// next one. Returns false at the end of the query.
+    fn operator(&mut self) -> Result<bool, AppError> {
+        loop {
+            let op = match self.tokens.peek() {
+                None => return Ok(false),
+                Some(Token::Close) => {
+                    self.tokens.next();
+                    while self.ops.last().is_some_and(|&op| op != Op::Open) {
+                        self.reduce()?;
+                    }
+                    if self.ops.pop().is_none() {
+                        return Err(invalid("unmatched ')'"));
+                    }
+                    continue;
+                }
+                Some(Token::Word(word, false)) if word.eq_ignore_ascii_case("or") => {
+                    self.tokens.next();
+                    Op::Or
+                }
+                Some(Token::Word(word, false)) if word.eq_ignore_ascii_case("and") => {
+                    self.tokens.next();
+                    Op::And
+                }
+                // Terms next to each other are ANDed, with or without the `AND`
+                Some(_) => Op::And,
+            };
+            while self
+                .ops
+                .last()
+                .is_some_and(|top| top.precedence() >= op.precedence())
+            {
+                self.reduce()?;
+            }
+            self.ops.push(op);
+            return Ok(true);
+        }
+    }
+
+    /// Apply the last operator to its operands.
+    fn reduce(&mut self) -> Result<(), AppError> {
+        let mut operand = || self.queries.pop().ok_or_else(|| invalid("expected a term"));
+        let (query, depth) = match self.ops.pop() {
+            Some(Op::Not) => {
+                let (query, depth) = operand()?;
+                (Query::Not(Box::new(query)), depth + 1)
+            }
+            Some(op @ (Op::And | Op::Or)) => {
+                let (b, b_depth) = operand()?;
+                let (a, a_depth) = operand()?;
+                let query = if op == Op::And {
+                    Query::And(Box::new(a), Box::new(b))
+                } else {
+                    Query::Or(Box::new(a), Box::new(b))
+                };
+                (query, a_depth.max(b_depth) + 1)
+            }
+            Some(Op::Open) | None => unreachable!("brackets are closed, not reduced"),
+        };
+        if depth > MAX_DEPTH {
+            return Err(invalid("query too deeply nested"));
+        }
+        self.queries.push((query, depth));
+        Ok(())
+    }
+}
+
+/// Split a query into brackets and words. Quotes can go around a whole word
+/// (`"a phrase"`) or a value (`title:"a phrase"`).
+fn tokenize(src: &str) -> Result<Vec<Token>, AppError> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let quoted = c == '"';
+                let mut word = String::new();
+                let mut in_quotes = false;
+                while let Some(&c) = chars.peek() {
+                    if !in_quotes && (c.is_whitespace() || c == '(' || c == ')') {
+                        break;
+                    }
+                    chars.next();
+                    if c == '"' {
+                        in_quotes = !in_quotes;
+                    } else {
+                        word.push(c);
+                    }
+                }
+                if in_quotes {
+                    return Err(invalid("missing '\"'"));
+                }
+                tokens.push(Token::Word(word, quoted));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn invalid(msg: &str) -> AppError {
+    (StatusCode::BAD_REQUEST, format!("Invalid query: {}", msg))
+}
+
+#[test]
+fn test_parse() {
+    fn term(term: Term) -> Box<Query> {
+        Box::new(Query::Term(term))
+    }
+    let parse = |src: &str| src.parse::<Query>();
+    assert_eq!(
+        parse("urgent AND tag:ops AND age>7d").unwrap(),
+        Query::And(
+            Box::new(Query::And(
+                term(Term::Urgent),
+                term(Term::Tag(String::from("ops")))
+            )),
+            term(Term::Age(Age {
+                older: true,
+                days: 7
+            }))
+        )
+    );
+    // AND binds tighter than OR, and can be left out
+    assert_eq!(
+        parse("important or urgent #Ops").unwrap(),
+        Query::Or(
+            term(Term::Important),
+            Box::new(Query::And(
+                term(Term::Urgent),
+                term(Term::Tag(String::from("ops")))
+            ))
+        )
+    );
+    assert_eq!(
+        parse("NOT (due<3d OR -owner:nat) title:\"weekly report\" \"urgent\" agenda").unwrap(),
+        Query::And(
+            Box::new(Query::And(
+                Box::new(Query::And(
+                    Box::new(Query::Not(Box::new(Query::Or(
+                        term(Term::DueWithin(3)),
+                        Box::new(Query::Not(term(Term::Owner(String::from("nat")))))
+                    )))),
+                    term(Term::Title(String::from("weekly report")))
+                )),
+                term(Term::Text(String::from("urgent")))
+            )),
+            term(Term::Text(String::from("agenda")))
+        )
+    );
+    for bad in [
+        "",
+        "(urgent",
+        "urgent)",
+        "AND urgent",
+        "urgent OR",
+        "age>soon",
+        "tag:",
+        "\"open",
+    ] {
+        assert_eq!(
+            parse(bad).unwrap_err().0,
+            StatusCode::BAD_REQUEST,
+            "{}",
+            bad
+        );
+    }
+
+    let nots = |n: usize| format!("{}urgent", "NOT ".repeat(n));
+    assert!(parse(&nots(999)).unwrap().to_sql(Local::now()).0.len() > 999);
+    let brackets = format!("{}urgent{}", "(".repeat(5000), ")".repeat(5000));
+    assert_eq!(parse(&brackets).unwrap(), *term(Term::Urgent));
+    let ors = format!("{}#ops{}", "(#ops OR ".repeat(2000), ")".repeat(2000));
+    for deep in [nots(1000), "#ops ".repeat(5000), ors] {
+        assert_eq!(
+            parse(&deep).unwrap_err().1,
+            "Invalid query: query too deeply nested"
+        );
+    }
+
+    let (sql, params) = parse("#ops -milk").unwrap().to_sql(Local::now());
+    assert_eq!(
+        sql,
+        "(wannado_has_tag(json_extract(item, '$.body'), ?) AND NOT coalesce(\
+         (wannado_contains(json_extract(item, '$.title'), ?) \
+         OR wannado_contains(json_extract(item, '$.body'), ?)), 0))"
+    );
+    assert_eq!(params.len(), 3);
+}
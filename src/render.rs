@@ -0,0 +1,452 @@
+//! Rendering item bodies as HTML.
+//!
+//! Bodies are CommonMark with tables, task lists, smart punctuation and links
+//! to other items (see the `markdown` module), plus these extras, chosen
+//! with `WANNADO_MARKDOWN_FEATURES` (comma-separated, or `all` or `none`; all
+//! of them by default):
+//!
+//! * `highlight`: fenced code blocks in a known language are highlighted
+//! * `footnotes`: `[^note]` references and `[^note]: ...` definitions
+//! * `strikethrough`: `~~struck out~~`
+//! * `anchors`: headings get ids and links to themselves, and bodies with
+//!   three or more headings start with a table of contents
+//! * `autolink`: bare `http://` and `https://` URLs become links
+//!
+//! The output is sanitized with an allow-list (see [`sanitize`]), so it's
+//! safe to put in pages as it is.
+
+use crate::markdown::{self, Features, Links};
+use pulldown_cmark::escape::{escape_href, escape_html};
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Parser, Tag};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{append_highlighted_html_for_styled_line, IncludeBackground};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// Bodies with at least this many headings get a table of contents.
+const TOC_MIN_HEADINGS: usize = 3;
+
+/// Render a body. With `tasks`, task-list checkboxes are enabled and carry
+/// the source offset of their marker so they can be toggled.
+pub fn to_html(src: &str, links: &Links, tasks: bool) -> String {
+    let features = Features::get();
+    let parser = Parser::new_ext(src, markdown::options())
+        .into_offset_iter()
+        .map(|(event, range)| match event {
+            Event::TaskListMarker(checked) if tasks => Event::Html(CowStr::from(format!(
+                "<input type=\"checkbox\" class=\"task\" data-offset=\"{}\"{}/>\n",
+                range.start,
+                if checked { " checked=\"\"" } else { "" }
+            ))),
+            _ => markdown::attachment_urls(event),
+        });
+    let mut events = markdown::link_references(parser, links);
+    if features.autolink {
+        events = autolink(events);
+    }
+    if features.footnotes {
+        events = footnotes(events);
+    }
+    if features.highlight {
+        events = highlight(events);
+    }
+    let mut output = String::new();
+    if features.anchors {
+        let headings;
+        (events, headings) = anchor_headings(events);
+        if headings.len() >= TOC_MIN_HEADINGS {
+            output.push_str(&toc(&headings));
+        }
+    }
+    html::push_html(&mut output, events.into_iter());
+    sanitize(&output)
+}
+
+/// Clean rendered HTML with an allow-list of tags and attributes, so raw HTML
+/// in bodies can't run scripts. Tables, task-list checkboxes, links, images,
+/// item links and the extras above are kept.
+pub fn sanitize(html: &str) -> String {
+    static SANITIZER: OnceLock<ammonia::Builder<'static>> = OnceLock::new();
+    SANITIZER
+        .get_or_init(|| {
+            let mut builder = ammonia::Builder::default();
+            builder
+                .add_tags(["input"])
+                .add_tag_attributes("input", ["type", "checked", "disabled", "data-offset"])
+                .add_tag_attributes("th", ["style"])
+                .add_tag_attributes("td", ["style"])
+                .add_tag_attributes("span", ["style"])
+                .add_tag_attributes("div", ["id"])
+                .add_allowed_classes("a", ["item-link", "broken", "anchor"])
+                .add_allowed_classes("span", ["item-link", "broken"])
+                .add_allowed_classes("input", ["task"])
+                .add_allowed_classes("pre", ["highlight"])
+                .add_allowed_classes("nav", ["toc"])
+                .add_allowed_classes(
+                    "li",
+                    ["toc-h1", "toc-h2", "toc-h3", "toc-h4", "toc-h5", "toc-h6"],
+                )
+                .add_allowed_classes("sup", ["footnote-reference", "footnote-definition-label"])
+                .add_allowed_classes("div", ["footnote-definition"])
+                .attribute_filter(allowed_attribute);
+            for heading in ["h1", "h2", "h3", "h4", "h5", "h6"] {
+                builder.add_tag_attributes(heading, ["id"]);
+            }
+            builder
+        })
+        .clean(html)
+        .to_string()
+}
+
+fn allowed_attribute<'a>(element: &str, attribute: &str, value: &'a str) -> Option<Cow<'a, str>> {
+    let allowed = match (element, attribute) {
+        // Checkboxes are the only inputs Markdown makes
+        ("input", "type") => return Some(Cow::from("checkbox")),
+        // Table column alignment
+        (_, "style") if element == "th" || element == "td" => ["left", "center", "right"]
+            .iter()
+            .any(|align| value == format!("text-align: {}", align)),
+        // Colours and fonts from syntax highlighting
+        ("span", "style") => value
+            .split(';')
+            .filter(|declaration| !declaration.is_empty())
+            .all(highlight_style),
+        // Anchors for headings and footnotes, which can't clash with the page's
+        (_, "id") => value.starts_with("md-"),
+        _ => true,
+    };
+    allowed.then_some(Cow::from(value))
+}
+
+fn highlight_style(declaration: &str) -> bool {
+    matches!(
+        declaration,
+        "text-decoration:underline" | "font-weight:bold" | "font-style:italic"
+    ) || declaration
+        .strip_prefix("color:#")
+        .is_some_and(|hex| matches!(hex.len(), 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Turn bare URLs in text into links.
+fn autolink(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut output = Vec::new();
+    let mut nesting = 0;
+    for event in events {
+        match event {
+            Event::Start(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => {
+                nesting += 1;
+                output.push(event);
+            }
+            Event::End(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => {
+                nesting -= 1;
+                output.push(event);
+            }
+            Event::Text(text) if nesting == 0 => {
+                let mut rest: &str = &text;
+                while let Some((before, url, after)) = find_url(rest) {
+                    if !before.is_empty() {
+                        output.push(Event::Text(CowStr::from(before.to_owned())));
+                    }
+                    let mut href = String::new();
+                    let mut label = String::new();
+                    // Writing to a String can't fail
+                    let _ = escape_href(&mut href, url);
+                    let _ = escape_html(&mut label, url);
+                    output.push(Event::Html(CowStr::from(format!(
+                        "<a href=\"{}\">{}</a>",
+                        href, label
+                    ))));
+                    rest = after;
+                }
+                if !rest.is_empty() {
+                    output.push(Event::Text(CowStr::from(rest.to_owned())));
+                }
+            }
+            _ => output.push(event),
+        }
+    }
+    output
+}
+
+/// The first URL in `text`, with the text before and after it. Punctuation
+/// at the end, like a full stop or an unmatched bracket, isn't part of it.
+fn find_url(text: &str) -> Option<(&str, &str, &str)> {
+    let start = text.match_indices("http").map(|(i, _)| i).find(|&i| {
+        let rest = &text[i..];
+        (rest.starts_with("https://") || rest.starts_with("http://"))
+            && !text[..i].ends_with(char::is_alphanumeric)
+    })?;
+    let len = text[start..]
+        .find(|c: char| c.is_whitespace() || c == '<')
+        .unwrap_or(text.len() - start);
+    let mut url = &text[start..start + len];
+    loop {
+        let mut trimmed = url.trim_end_matches(['.', ',', ':', ';', '!', '?', '\'', '"', '’', '”']);
+        if trimmed.ends_with(')') && trimmed.matches(')').count() > trimmed.matches('(').count() {
+            trimmed = &trimmed[..trimmed.len() - 1];
+        }
+        if trimmed == url {
+            break;
+        }
+        url = trimmed;
+    }
+    Some((&text[..start], url, &text[start + url.len()..]))
+}
+
+/// Number footnotes in order of appearance, with ids that can't clash with
+/// the page's own.
+fn footnotes(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut numbers: HashMap<String, usize> = HashMap::new();
+    let mut number = |name: &str| {
+        let next = numbers.len() + 1;
+        *numbers.entry(name.to_owned()).or_insert(next)
+    };
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::FootnoteReference(name) => Event::Html(CowStr::from(format!(
+                "<sup class=\"footnote-reference\"><a href=\"#md-fn-{0}\">{0}</a></sup>",
+                number(&name)
+            ))),
+            Event::Start(Tag::FootnoteDefinition(name)) => Event::Html(CowStr::from(format!(
+                "<div class=\"footnote-definition\" id=\"md-fn-{0}\">\
+                 <sup class=\"footnote-definition-label\">{0}</sup>",
+                number(&name)
+            ))),
+            Event::End(Tag::FootnoteDefinition(_)) => Event::Html(CowStr::from("</div>\n")),
+            _ => event,
+        })
+        .collect()
+}
+
+/// Highlight fenced code blocks in languages syntect knows.
+fn highlight(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut output = Vec::new();
+    // The language and code of the block being highlighted
+    let mut block: Option<(&SyntaxReference, String)> = None;
+    for event in events {
+        match (&mut block, event) {
+            (None, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))) => {
+                match language(&info) {
+                    Some(syntax) => block = Some((syntax, String::new())),
+                    None => output.push(Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))),
+                }
+            }
+            (Some((_, code)), Event::Text(text)) => code.push_str(&text),
+            (Some((syntax, code)), Event::End(Tag::CodeBlock(_))) => {
+                output.push(Event::Html(CowStr::from(highlighted(syntax, code))));
+                block = None;
+            }
+            (_, event) => output.push(event),
+        }
+    }
+    output
+}
+
+fn language(info: &str) -> Option<&'static SyntaxReference> {
+    let token = info
+        .split([' ', ',', '{'])
+        .next()
+        .filter(|t| !t.is_empty())?;
+    syntaxes().find_syntax_by_token(token)
+}
+
+fn highlighted(syntax: &SyntaxReference, code: &str) -> String {
+    let mut html = String::from("<pre class=\"highlight\"><code>");
+    let mut highlighter = HighlightLines::new(syntax, theme());
+    for line in LinesWithEndings::from(code) {
+        let highlighted = highlighter
+            .highlight_line(line, syntaxes())
+            .ok()
+            .and_then(|regions| {
+                let mut html = String::new();
+                append_highlighted_html_for_styled_line(&regions, IncludeBackground::No, &mut html)
+                    .ok()
+                    .map(|_| html)
+            });
+        match highlighted {
+            Some(line) => html.push_str(&line),
+            None => {
+                let _ = escape_html(&mut html, line);
+            }
+        }
+    }
+    html.push_str("</code></pre>\n");
+    html
+}
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    THEME.get_or_init(|| {
+        ThemeSet::load_defaults()
+            .themes
+            .remove("InspiredGitHub")
+            .unwrap_or_default()
+    })
+}
+
+/// A heading, for the table of contents.
+struct Heading {
+    level: usize,
+    id: String,
+    text: String,
+}
+
+/// Give headings ids and links to themselves.
+fn anchor_headings(events: Vec<Event<'_>>) -> (Vec<Event<'_>>, Vec<Heading>) {
+    let mut headings: Vec<Heading> = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for event in &events {
+        match event {
+            Event::Start(Tag::Heading(level, ..)) => {
+                current = Some((*level as usize, String::new()))
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, heading)) = &mut current {
+                    heading.push_str(text);
+                }
+            }
+            Event::End(Tag::Heading(..)) => {
+                if let Some((level, text)) = current.take() {
+                    let slug = slug(&text);
+                    let mut id = slug.clone();
+                    let mut n = 1;
+                    while headings.iter().any(|h| h.id == id) {
+                        n += 1;
+                        id = format!("{}-{}", slug, n);
+                    }
+                    headings.push(Heading { level, id, text });
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut index = 0;
+    let events = events
+        .into_iter()
+        .map(|event| match event {
+            Event::Start(Tag::Heading(level, ..)) => Event::Html(CowStr::from(format!(
+                "<{} id=\"md-{}\">",
+                level, headings[index].id
+            ))),
+            Event::End(Tag::Heading(level, ..)) => {
+                let id = &headings[index].id;
+                index += 1;
+                Event::Html(CowStr::from(format!(
+                    " <a class=\"anchor\" href=\"#md-{}\">#</a></{}>\n",
+                    id, level
+                )))
+            }
+            _ => event,
+        })
+        .collect();
+    (events, headings)
+}
+
+/// A heading's text as lower-case ASCII words separated by dashes.
+fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if (c.is_whitespace() || c == '-') && !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    match slug.trim_end_matches('-') {
+        "" => String::from("section"),
+        slug => slug.to_owned(),
+    }
+}
+
+fn toc(headings: &[Heading]) -> String {
+    let mut html = String::from("<nav class=\"toc\"><ul>\n");
+    for heading in headings {
+        let mut text = String::new();
+        let _ = escape_html(&mut text, &heading.text);
+        html.push_str(&format!(
+            "<li class=\"toc-h{}\"><a href=\"#md-{}\">{}</a></li>\n",
+            heading.level, heading.id, text
+        ));
+    }
+    html.push_str("</ul></nav>\n");
+    html
+}
+
+#[test]
+fn test_sanitize() {
+    let payloads = [
+        "<script>alert(1)</script>",
+        "<img src=x onerror=alert(1)>",
+        "<a href=\"javascript:alert(1)\">x</a>",
+        "<svg onload=alert(1)>",
+        "<iframe src=\"https://evil.example\"></iframe>",
+        "<input type=\"text\" autofocus onfocus=alert(1)>",
+        "<td style=\"background: url(javascript:alert(1))\">",
+        "<span style=\"color:#fff;background:url(https://evil.example)\">",
+        "<style>body { display: none }</style>",
+    ];
+    for payload in payloads {
+        let clean = sanitize(payload);
+        for bad in [
+            "<script",
+            "onerror",
+            "javascript:",
+            "onload",
+            "<iframe",
+            "onfocus",
+            "<style",
+            "<svg",
+            "url(",
+        ] {
+            assert!(!clean.contains(bad), "{} became {}", payload, clean);
+        }
+    }
+    assert_eq!(
+        sanitize(r#"<input type="checkbox" class="task" data-offset="2" checked=""/>"#),
+        r#"<input type="checkbox" class="task" data-offset="2" checked="">"#
+    );
+    let table = r#"<table><thead><tr><th style="text-align: center">A</th></tr></thead></table>"#;
+    assert_eq!(sanitize(table), table);
+    assert!(
+        sanitize(r#"<a href="/item/1" class="item-link">#1</a>"#).contains(r#"class="item-link""#)
+    );
+    assert!(sanitize(r#"<img src="/attachment/3" alt="shot">"#).contains(r#"src="/attachment/3""#));
+    assert_eq!(sanitize(r#"<h2 id="home">Home</h2>"#), "<h2>Home</h2>");
+}
+
+#[test]
+fn test_to_html() {
+    let links = Links::default();
+    let html = to_html(
+        "# Plan\n\n## Steps\n\nSee https://example.com/a_(b). ~~Old~~[^1]\n\n## Steps\n\n\
+         ```rust\nfn main() {}\n```\n\n```nonsense\nplain <b>\n```\n\n[^1]: A note.\n",
+        &links,
+        false,
+    );
+    assert!(html.starts_with("<nav class=\"toc\"><ul>\n<li class=\"toc-h1\"><a href=\"#md-plan\""));
+    assert!(html.contains(r##"<h2 id="md-steps-2">Steps <a class="anchor" href="#md-steps-2""##));
+    assert!(html.contains(r#"<a href="https://example.com/a_(b)" rel="noopener noreferrer">"#));
+    assert!(html.contains("</a>. <del>Old</del>"));
+    assert!(html.contains(r##"<sup class="footnote-reference"><a href="#md-fn-1""##));
+    assert!(html.contains(r#"<div class="footnote-definition" id="md-fn-1">"#));
+    assert!(html.contains(r#"<pre class="highlight"><code><span style="font-weight:bold;color:"#));
+    assert!(html.contains("<pre><code>plain &lt;b&gt;"));
+
+    assert_eq!(slug("  What's -- next?"), "whats-next");
+    assert_eq!(slug("!!"), "section");
+    assert_eq!(
+        find_url("(see http://a.example/x).").map(|(_, url, _)| url),
+        Some("http://a.example/x")
+    );
+    assert!(find_url("nothttp://a.example").is_none());
+}
@@ -1,10 +1,22 @@
 use super::{AppError, StatusCode};
-use chrono::Utc;
-use rusqlite::{params, Connection, OptionalExtension};
+use crate::markdown;
+use crate::query::Query;
+use crate::view_options::ViewOptions;
+use chrono::{Local, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// The list that items are in unless they've been put in another one.
+pub const DEFAULT_LIST: &str = "main";
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct Item {
     pub id: u32,
+    /// The name of the list the item is in (see `lists`).
+    #[serde(default = "default_list")]
+    pub list: String,
     pub title: String,
     pub body: String,
     pub important: bool,
@@ -12,9 +24,71 @@ pub struct Item {
     pub created: i64,
     pub modified: Option<i64>,
     pub deleted: Option<i64>,
+    #[serde(default)]
+    pub restored: Option<i64>,
+    /// The user the item belongs to, if any.
+    #[serde(default)]
+    pub owner: Option<u32>,
+    /// Other users the item has been assigned to.
+    #[serde(default)]
+    pub assignees: Vec<u32>,
+    /// Who created, last modified, deleted and restored the item. These are
+    /// empty if it was done without logging in (or before there were users).
+    #[serde(default)]
+    pub created_by: Option<u32>,
+    #[serde(default)]
+    pub modified_by: Option<u32>,
+    #[serde(default)]
+    pub deleted_by: Option<u32>,
+    #[serde(default)]
+    pub restored_by: Option<u32>,
+}
+
+/// The four quadrants of the Eisenhower matrix that items are sorted into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quadrant {
+    ImportantAndUrgent,
+    Important,
+    Urgent,
+    Other,
+}
+
+impl std::str::FromStr for Quadrant {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "important-and-urgent" => Ok(Quadrant::ImportantAndUrgent),
+            "important" => Ok(Quadrant::Important),
+            "urgent" => Ok(Quadrant::Urgent),
+            "other" => Ok(Quadrant::Other),
+            _ => Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Unknown quadrant '{}', expected important-and-urgent, important, urgent or other",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+impl Quadrant {
+    pub fn of(important: bool, urgent: bool) -> Quadrant {
+        match (important, urgent) {
+            (true, true) => Quadrant::ImportantAndUrgent,
+            (true, false) => Quadrant::Important,
+            (false, true) => Quadrant::Urgent,
+            (false, false) => Quadrant::Other,
+        }
+    }
 }
 
 impl Item {
+    pub fn quadrant(&self) -> Quadrant {
+        Quadrant::of(self.important, self.urgent)
+    }
+
     fn serialize(&self) -> Result<String, AppError> {
         serde_json::to_string(&self).map_err(|e| {
             (
@@ -33,46 +107,239 @@ impl Item {
         })
     }
 
-    fn modified(&mut self) {
+    fn modified(&mut self, by: Option<u32>) {
         self.modified = Some(Utc::now().timestamp());
+        self.modified_by = by;
     }
 
-    fn delete(&mut self) {
+    fn delete(&mut self, by: Option<u32>) {
         self.deleted = Some(Utc::now().timestamp());
+        self.deleted_by = by;
     }
 
-    fn restore(&mut self) {
+    fn restore(&mut self, by: Option<u32>) {
         self.deleted = None;
+        self.deleted_by = None;
+        self.restored = Some(Utc::now().timestamp());
+        self.restored_by = by;
+    }
+}
+
+/// A file attached to an item. The contents are stored separately (see
+/// `Repo::attachment_data`).
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Attachment {
+    pub id: u32,
+    pub item: u32,
+    pub name: String,
+    pub content_type: String,
+    pub size: i64,
+    pub created: i64,
+}
+
+/// A user account (see `auth`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub id: u32,
+    pub name: String,
+}
+
+/// A logged-in user's session.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub user: u32,
+    pub csrf: String,
+}
+
+/// A token for scripts to use the API with. Tokens belong to a user, or to
+/// no-one if they're global.
+#[derive(Debug, Clone)]
+pub struct ApiToken {
+    pub id: u32,
+    pub name: String,
+    pub user: Option<u32>,
+    pub user_name: Option<String>,
+    pub scope: String,
+    pub created: i64,
+    pub last_used: Option<i64>,
+}
+
+/// What a draft is of: an existing item, or a new one in a list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DraftOf {
+    Item(u32),
+    NewItem(String),
+}
+
+impl DraftOf {
+    fn key(&self) -> String {
+        match self {
+            DraftOf::Item(id) => format!("item/{}", id),
+            DraftOf::NewItem(list) => format!("new/{}", list),
+        }
     }
 }
 
+/// Unsaved edits, autosaved by the editor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Draft {
+    pub title: String,
+    pub body: String,
+    pub saved: i64,
+}
+
+/// A named starting point for new items, like "Bug triage" (see
+/// `item_templates`). The title and body can contain placeholders.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ItemTemplate {
+    pub id: u32,
+    pub name: String,
+    pub title: String,
+    pub body: String,
+    pub important: bool,
+    pub urgent: bool,
+    pub tags: Vec<String>,
+}
+
+/// A named set of home page options (see `view_options`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedView {
+    pub name: String,
+    /// The options as a query string.
+    pub query: String,
+}
+
 pub struct Repo {
     cxn: Connection,
+    attachments_dir: Option<PathBuf>,
 }
 
 /// Public methods of Repo
 impl Repo {
     pub fn new(cxn: Connection) -> Self {
-        Repo { cxn }
+        Repo {
+            cxn,
+            attachments_dir: None,
+        }
+    }
+
+    /// Keep attachment contents as files in `dir` instead of in the database.
+    pub fn store_attachments_in(&mut self, dir: PathBuf) {
+        self.attachments_dir = Some(dir);
     }
 
     pub fn init(&mut self) -> rusqlite::Result<()> {
+        self.cxn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS items (item BLOB NOT NULL);
+             CREATE TABLE IF NOT EXISTS links (source INTEGER NOT NULL, target INTEGER NOT NULL);
+             CREATE TABLE IF NOT EXISTS attachments (
+                id INTEGER PRIMARY KEY,
+                item INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                content_type TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                created INTEGER NOT NULL,
+                data BLOB
+             );
+             CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                password_hash TEXT NOT NULL,
+                created INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS sessions (
+                token TEXT PRIMARY KEY,
+                user INTEGER NOT NULL,
+                csrf TEXT NOT NULL,
+                expires INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS lists (
+                name TEXT PRIMARY KEY,
+                created INTEGER NOT NULL
+             );
+             INSERT OR IGNORE INTO lists (name, created) VALUES ('main', strftime('%s', 'now'));
+             CREATE TABLE IF NOT EXISTS drafts (
+                key TEXT NOT NULL,
+                user INTEGER NOT NULL,
+                title TEXT NOT NULL,
+                body TEXT NOT NULL,
+                saved INTEGER NOT NULL,
+                PRIMARY KEY (key, user)
+             );
+             CREATE TABLE IF NOT EXISTS item_positions (
+                item INTEGER PRIMARY KEY,
+                position INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS saved_views (
+                name TEXT PRIMARY KEY,
+                query TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS item_templates (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                title TEXT NOT NULL,
+                body TEXT NOT NULL,
+                important INTEGER NOT NULL,
+                urgent INTEGER NOT NULL,
+                tags TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS api_tokens (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                user INTEGER,
+                token_hash TEXT NOT NULL UNIQUE,
+                scope TEXT NOT NULL,
+                created INTEGER NOT NULL,
+                last_used INTEGER
+             );",
+        )?;
+        self.add_query_functions()
+    }
+
+    /// Rebuild the index of links between items (e.g. after the automation
+    /// script has been editing bodies directly).
+    pub fn index_links(&mut self) -> Result<(), AppError> {
+        for item in self.active_and_deleted()? {
+            self.index_item_links(&item)?;
+        }
+        Ok(())
+    }
+
+    /// Run `f` in a transaction: if it fails, none of its changes are kept.
+    pub fn in_transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut Repo) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
         self.cxn
-            .execute(
-                "CREATE TABLE IF NOT EXISTS items (item BLOB NOT NULL)",
-                params![],
-            )
-            .map(|_| ())
+            .execute_batch("SAVEPOINT in_transaction")
+            .map_err(convert_db_error)?;
+        match f(self) {
+            Ok(value) => {
+                self.cxn
+                    .execute_batch("RELEASE in_transaction")
+                    .map_err(convert_db_error)?;
+                Ok(value)
+            }
+            Err(err) => {
+                self.cxn
+                    .execute_batch("ROLLBACK TO in_transaction; RELEASE in_transaction")
+                    .map_err(convert_db_error)?;
+                Err(err)
+            }
+        }
     }
 
+    /// Add a new item to `list`, owned by the user adding it (`by`).
     pub fn add(
         &mut self,
+        list: &str,
         title: &str,
         body: &str,
         important: bool,
         urgent: bool,
+        by: Option<u32>,
     ) -> Result<Item, AppError> {
-        let tx = self.cxn.transaction().map_err(convert_db_error)?;
+        let tx = self.cxn.savepoint().map_err(convert_db_error)?;
 
         // Get new id
         let id: u32 = tx
@@ -84,6 +351,7 @@ impl Repo {
             .map_err(convert_db_error)?;
         let item = Item {
             id,
+            list: String::from(list),
             title: String::from(title),
             body: String::from(body),
             important,
@@ -91,6 +359,13 @@ impl Repo {
             created: Utc::now().timestamp(),
             modified: None,
             deleted: None,
+            restored: None,
+            owner: by,
+            assignees: Vec::new(),
+            created_by: by,
+            modified_by: None,
+            deleted_by: None,
+            restored_by: None,
         };
         tx.execute(
             "UPDATE items SET item = ? WHERE rowid = ?",
@@ -98,6 +373,37 @@ impl Repo {
         )
         .map_err(convert_db_error)?;
         tx.commit().map_err(convert_db_error)?;
+        self.index_item_links(&item)?;
+        self.index_title_references(&[&item.title])?;
+        Ok(item)
+    }
+
+    /// Store an item as-is under its own id, replacing any existing item
+    /// with that id.
+    pub fn put(&mut self, item: &Item) -> Result<(), AppError> {
+        let old = self.get_any(item.id).ok();
+        self.cxn
+            .execute(
+                "INSERT OR REPLACE INTO items (rowid, item) VALUES (?, ?)",
+                params![item.id, item.serialize()?],
+            )
+            .map_err(convert_db_error)?;
+        self.index_item_links(item)?;
+        self.index_changed_title(old.as_ref(), item)
+    }
+
+    /// Store a copy of an item under a new id, keeping its timestamps.
+    pub fn append(&mut self, item: &Item) -> Result<Item, AppError> {
+        let id: u32 = self
+            .cxn
+            .query_row(
+                "INSERT INTO items (item) VALUES ('') RETURNING rowid",
+                params![],
+                |r| r.get(0),
+            )
+            .map_err(convert_db_error)?;
+        let item = Item { id, ..item.clone() };
+        self.put(&item)?;
         Ok(item)
     }
 
@@ -133,111 +439,1642 @@ impl Repo {
             .and_then(|s| Item::deserialize(&s))
     }
 
-    // Get all un-deleted items
-    pub fn all(&mut self) -> Result<Vec<Item>, AppError> {
-        let active_items = self
-            .active_and_deleted()?
-            .into_iter()
-            .filter(|item| item.deleted.is_none())
-            .collect();
-        Ok(active_items)
+    // Every item, deleted or not
+    pub fn active_and_deleted(&mut self) -> Result<Vec<Item>, AppError> {
+        self.items_where("1", [])
     }
 
-    // Items that have been deleted
-    pub fn deleted(&mut self) -> Result<Vec<Item>, AppError> {
-        let deleted_items = self
-            .active_and_deleted()?
-            .into_iter()
-            .filter(|item| item.deleted.is_some())
-            .collect();
-        Ok(deleted_items)
+    /// Un-deleted items in one list that match a query.
+    pub fn search(&self, list: &str, query: &Query) -> Result<Vec<Item>, AppError> {
+        let (condition, params) = query.to_sql(Local::now());
+        let list = Value::Text(list.to_owned());
+        self.items_where(
+            &format!(
+                "json_extract(item, '$.deleted') IS NULL AND {} AND {}",
+                IN_LIST, condition
+            ),
+            params_from_iter(std::iter::once(list).chain(params)),
+        )
     }
 
-    fn active_and_deleted(&mut self) -> Result<Vec<Item>, AppError> {
-        let serialized_items = self.all_items_raw().map_err(convert_db_error)?;
-        serialized_items
-            .iter()
-            .map(|s| Item::deserialize(s))
+    /// Up to `limit` un-deleted items in one list (matching `query`, if
+    /// given) with ids after `after`, in order of id. Pass the last id of a
+    /// page as `after` to get the next one.
+    pub fn items_page(
+        &self,
+        list: &str,
+        query: Option<&Query>,
+        after: u32,
+        limit: Option<usize>,
+    ) -> Result<Vec<Item>, AppError> {
+        let (condition, params) = match query {
+            Some(query) => query.to_sql(Local::now()),
+            None => (String::from("1"), Vec::new()),
+        };
+        // A negative limit is no limit
+        let limit = limit.map_or(-1, |l| l as i64);
+        let params = [Value::Text(list.to_owned()), Value::Integer(after.into())]
             .into_iter()
+            .chain(params)
+            .chain([Value::Integer(limit)]);
+        self.items_where(
+            &format!(
+                "json_extract(item, '$.deleted') IS NULL AND {} AND rowid > ? AND {}
+                 ORDER BY rowid LIMIT ?",
+                IN_LIST, condition
+            ),
+            params_from_iter(params),
+        )
+    }
+
+    /// Up to `limit` un-deleted items in one list that `options` show, in
+    /// their order, starting after the item whose keys are `after` (i.e. the
+    /// last item of the previous page). With `shown`, only items owned by or
+    /// assigned to that user are included. Each item comes with its keys, the
+    /// values of `ViewOptions::order_sql` and its id.
+    pub fn view_page(
+        &self,
+        list: &str,
+        options: &ViewOptions,
+        shown: Option<u32>,
+        after: Option<&[Value]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<Value>, Item)>, AppError> {
+        let (mut keys, by_tag) = options.order_sql();
+        keys.push("items.rowid");
+        let mut conditions = vec![
+            String::from("json_extract(item, '$.deleted') IS NULL"),
+            String::from(IN_LIST),
+        ];
+        let mut params = vec![Value::Text(list.to_owned())];
+        if let Some(query) = options.filter_query()? {
+            let (condition, query_params) = query.to_sql(Local::now());
+            conditions.push(condition);
+            params.extend(query_params);
+        }
+        if let Some(user) = shown {
+            conditions.push(String::from(
+                "(json_extract(item, '$.owner') = ?
+                  OR EXISTS (SELECT 1 FROM json_each(item, '$.assignees')
+                             WHERE json_each.value = ?))",
+            ));
+            params.extend([Value::Integer(user.into()), Value::Integer(user.into())]);
+        }
+        if let Some(after) = after {
+            if after.len() != keys.len() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    String::from("That page is from a different view"),
+                ));
+            }
+            conditions.push(format!(
+                "({}) > ({})",
+                keys.join(", "),
+                vec!["?"; keys.len()].join(", ")
+            ));
+            params.extend(after.iter().cloned());
+        }
+        params.push(Value::Integer(limit as i64));
+        let join = if by_tag {
+            "LEFT JOIN json_each(wannado_tags(json_extract(items.item, '$.body'))) AS tag"
+        } else {
+            ""
+        };
+        let sql = format!(
+            "SELECT {keys}, item FROM items {join} WHERE {conditions} ORDER BY {keys} LIMIT ?",
+            keys = keys.join(", "),
+            join = join,
+            conditions = conditions.join(" AND ")
+        );
+        let mut stmt = self.cxn.prepare(&sql).map_err(convert_db_error)?;
+        let rows: Vec<(Vec<Value>, String)> = stmt
+            .query_map(params_from_iter(params), |r| {
+                let values = (0..keys.len()).map(|i| r.get(i)).collect::<Result<_, _>>()?;
+                Ok((values, r.get(keys.len())?))
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error)?;
+        rows.into_iter()
+            .map(|(values, item)| Ok((values, Item::deserialize(&item)?)))
             .collect()
     }
 
-    fn all_items_raw(&mut self) -> rusqlite::Result<Vec<String>> {
-        let mut stmt = self.cxn.prepare("SELECT item FROM items")?;
-        let result = stmt.query_map([], |r| r.get(0))?;
-        result.collect()
+    /// How many un-deleted items in one list match `query` (or how many
+    /// there are, without one).
+    pub fn count(&self, list: &str, query: Option<&Query>) -> Result<usize, AppError> {
+        let (condition, params) = match query {
+            Some(query) => query.to_sql(Local::now()),
+            None => (String::from("1"), Vec::new()),
+        };
+        let params = std::iter::once(Value::Text(list.to_owned())).chain(params);
+        let sql = format!(
+            "SELECT COUNT(*) FROM items
+             WHERE json_extract(item, '$.deleted') IS NULL AND {} AND {}",
+            IN_LIST, condition
+        );
+        self.cxn
+            .query_row(&sql, params_from_iter(params), |r| r.get::<_, i64>(0))
+            .map(|count| count as usize)
+            .map_err(convert_db_error)
+    }
+
+    /// Items in one list (matching `query`, if given) that the calendar
+    /// shows: un-deleted items with a due date, and deleted items that will be
+    /// restored on a date (see `calendar`).
+    pub fn calendar_items(&self, list: &str, query: Option<&Query>) -> Result<Vec<Item>, AppError> {
+        let (condition, params) = match query {
+            Some(query) => query.to_sql(Local::now()),
+            None => (String::from("1"), Vec::new()),
+        };
+        let params = std::iter::once(Value::Text(list.to_owned())).chain(params);
+        self.items_where(
+            &format!(
+                "{} AND {} AND CASE WHEN json_extract(item, '$.deleted') IS NULL
+                     THEN wannado_due(json_extract(item, '$.body')) IS NOT NULL
+                     ELSE wannado_snoozed(json_extract(item, '$.body')) IS NOT NULL
+                 END",
+                IN_LIST, condition
+            ),
+            params_from_iter(params),
+        )
+    }
+
+    /// The `limit` items in one list (matching `query`, if given), deleted
+    /// or not, that were most recently created, modified, deleted or
+    /// restored, most recent first. These have the `limit` most recent
+    /// events for the feed (see `feed`) between them.
+    pub fn recently_active(
+        &self,
+        list: &str,
+        query: Option<&Query>,
+        limit: usize,
+    ) -> Result<Vec<Item>, AppError> {
+        let (condition, params) = match query {
+            Some(query) => query.to_sql(Local::now()),
+            None => (String::from("1"), Vec::new()),
+        };
+        let params = std::iter::once(Value::Text(list.to_owned()))
+            .chain(params)
+            .chain([Value::Integer(limit as i64)]);
+        self.items_where(
+            &format!(
+                "{} AND {}
+                 ORDER BY max(
+                     json_extract(item, '$.created'),
+                     coalesce(json_extract(item, '$.modified'), 0),
+                     coalesce(json_extract(item, '$.deleted'), 0),
+                     coalesce(json_extract(item, '$.restored'), 0)
+                 ) DESC, rowid DESC
+                 LIMIT ?",
+                IN_LIST, condition
+            ),
+            params_from_iter(params),
+        )
+    }
+
+    // Un-deleted items in one list
+    pub fn all_in(&mut self, list: &str) -> Result<Vec<Item>, AppError> {
+        self.items_where(
+            &format!("json_extract(item, '$.deleted') IS NULL AND {}", IN_LIST),
+            params![list],
+        )
+    }
+
+    // Deleted items in one list
+    pub fn deleted_in(&mut self, list: &str) -> Result<Vec<Item>, AppError> {
+        self.items_where(
+            &format!("json_extract(item, '$.deleted') IS NOT NULL AND {}", IN_LIST),
+            params![list],
+        )
     }
 
-    pub fn update(&mut self, item: &mut Item) -> Result<(), AppError> {
+    /// Up to `limit` deleted items in one list, most recently deleted first,
+    /// starting after the item deleted at `before.0` with the id `before.1`
+    /// (i.e. the last item of the previous page).
+    pub fn deleted_page(
+        &self,
+        list: &str,
+        before: Option<(i64, u32)>,
+        limit: usize,
+    ) -> Result<Vec<Item>, AppError> {
+        let (deleted, id) = before.unwrap_or((i64::MAX, u32::MAX));
+        self.items_where(
+            &format!(
+                "json_extract(item, '$.deleted') IS NOT NULL AND {}
+                   AND (json_extract(item, '$.deleted'), rowid) < (?, ?)
+                 ORDER BY json_extract(item, '$.deleted') DESC, rowid DESC
+                 LIMIT ?",
+                IN_LIST
+            ),
+            params![list, deleted, id, limit as i64],
+        )
+    }
+
+    // Every item in one list, deleted or not
+    pub fn active_and_deleted_in(&mut self, list: &str) -> Result<Vec<Item>, AppError> {
+        self.items_where(IN_LIST, params![list])
+    }
+
+    /// Save changes to an item made by the user `by`.
+    pub fn update(&mut self, item: &mut Item, by: Option<u32>) -> Result<(), AppError> {
         let cmd = "UPDATE items SET item = ? WHERE rowid = ?";
-        item.modified();
+        let old = self.get_any(item.id).ok();
+        item.modified(by);
         self.cxn
             .execute(cmd, params![item.serialize()?, item.id])
-            .map_err(convert_db_error)
-            .map(|_| ())
+            .map_err(convert_db_error)?;
+        self.index_item_links(item)?;
+        self.index_changed_title(old.as_ref(), item)
     }
 
-    pub fn delete(&mut self, id: &u32) -> Result<(), AppError> {
+    pub fn delete(&mut self, id: &u32, by: Option<u32>) -> Result<(), AppError> {
         let mut item = self.get(*id)?;
-        item.delete();
-        self.update(&mut item)?;
+        item.delete(by);
+        self.update(&mut item, by)?;
         Ok(())
     }
 
-    pub fn restore(&mut self, id: &u32) -> Result<(), AppError> {
+    pub fn restore(&mut self, id: &u32, by: Option<u32>) -> Result<(), AppError> {
         let mut item = self.get_deleted(*id)?;
-        item.restore();
-        self.update(&mut item)?;
+        item.restore(by);
+        self.update(&mut item, by)?;
         Ok(())
     }
+
+    /// Active items whose bodies link to the given item.
+    pub fn backlinks(&self, id: u32) -> Result<Vec<Item>, AppError> {
+        let mut stmt = self
+            .cxn
+            .prepare(
+                "SELECT item FROM items WHERE rowid IN
+                    (SELECT source FROM links WHERE target = ? AND source != target)
+                 ORDER BY rowid",
+            )
+            .map_err(convert_db_error)?;
+        let serialized_items: Vec<String> = stmt
+            .query_map(params![id], |r| r.get(0))
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error)?;
+        let items: Vec<Item> = serialized_items
+            .iter()
+            .map(|s| Item::deserialize(s))
+            .collect::<Result<_, _>>()?;
+        Ok(items.into_iter().filter(|i| i.deleted.is_none()).collect())
+    }
+
+    /// Look up the items referenced in a body so it can be rendered with
+    /// links to them.
+    pub fn resolve_links(&self, body: &str) -> Result<markdown::Links, AppError> {
+        let mut links = markdown::Links::default();
+        for reference in markdown::references(body) {
+            if let Some(id) = self.resolve_reference(&reference)? {
+                let item = self.get_any(id)?;
+                let target = markdown::LinkTarget {
+                    id,
+                    title: item.title,
+                    deleted: item.deleted.is_some(),
+                };
+                links.insert(reference, target);
+            }
+        }
+        Ok(links)
+    }
 }
 
-// Helpers
-fn convert_db_error(err: rusqlite::Error) -> AppError {
-    (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        format!("Database error: {:?}", err),
-    )
+/// Backups
+impl Repo {
+    /// Copy the database to `path` with SQLite's online backup API.
+    pub fn backup_to(&self, path: &std::path::Path) -> Result<(), AppError> {
+        self.cxn
+            .backup(rusqlite::DatabaseName::Main, path, None)
+            .map_err(convert_db_error)
+    }
 }
 
-#[test]
-fn test_repo() -> Result<(), AppError> {
-    fn compare_item_fields(item1: &Item, item2: &Item) {
-        assert!(item1.id == item2.id);
-        assert!(item1.title == item2.title);
-        assert!(item1.body == item2.body);
-        assert!(item1.important == item2.important);
-        assert!(item1.urgent == item2.urgent);
+/// Attachments
+impl Repo {
+    pub fn add_attachment(
+        &mut self,
+        item_id: u32,
+        name: &str,
+        content_type: &str,
+        data: &[u8],
+    ) -> Result<Attachment, AppError> {
+        self.get(item_id)?;
+        let stored_data = match self.attachments_dir {
+            Some(_) => None,
+            None => Some(data),
+        };
+        let attachment: Attachment = self
+            .cxn
+            .query_row(
+                "INSERT INTO attachments (item, name, content_type, size, created, data)
+                 VALUES (?, ?, ?, ?, ?, ?)
+                 RETURNING id, item, name, content_type, size, created",
+                params![
+                    item_id,
+                    name,
+                    content_type,
+                    data.len() as i64,
+                    Utc::now().timestamp(),
+                    stored_data
+                ],
+                attachment_from_row,
+            )
+            .map_err(convert_db_error)?;
+        if let Some(path) = self.attachment_path(attachment.id) {
+            if let Err(e) = std::fs::write(&path, data) {
+                self.delete_attachment(attachment.id)?;
+                return Err(convert_io_error(e));
+            }
+        }
+        Ok(attachment)
     }
 
-    let cxn = Connection::open_in_memory().map_err(convert_db_error)?;
-    let mut repo = Repo::new(cxn);
-    repo.init().map_err(convert_db_error)?;
+    pub fn attachments(&self, item_id: u32) -> Result<Vec<Attachment>, AppError> {
+        let mut stmt = self
+            .cxn
+            .prepare(
+                "SELECT id, item, name, content_type, size, created FROM attachments
+                 WHERE item = ? ORDER BY id",
+            )
+            .map_err(convert_db_error)?;
+        let result = stmt
+            .query_map(params![item_id], attachment_from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error);
+        result
+    }
 
-    let mut item = repo.add("Test Item", "Test item body.", true, false)?;
-    assert!(item.modified.is_none());
-    assert!(item.deleted.is_none());
+    pub fn get_attachment(&self, id: u32) -> Result<Attachment, AppError> {
+        self.cxn
+            .query_row(
+                "SELECT id, item, name, content_type, size, created FROM attachments WHERE id = ?",
+                params![id],
+                attachment_from_row,
+            )
+            .optional()
+            .map_err(convert_db_error)?
+            .ok_or((StatusCode::NOT_FOUND, String::from("No such attachment")))
+    }
 
-    let retrieved = repo.get(item.id)?;
-    compare_item_fields(&item, &retrieved);
-    assert!(item.modified.is_none());
-    assert!(item.deleted.is_none());
-    assert!(retrieved.modified.is_none());
-    assert!(retrieved.deleted.is_none());
+    pub fn attachment_data(&self, id: u32) -> Result<Vec<u8>, AppError> {
+        let stored: Option<Vec<u8>> = self
+            .cxn
+            .query_row(
+                "SELECT data FROM attachments WHERE id = ?",
+                params![id],
+                |r| r.get(0),
+            )
+            .optional()
+            .map_err(convert_db_error)?
+            .ok_or((StatusCode::NOT_FOUND, String::from("No such attachment")))?;
+        match (stored, self.attachment_path(id)) {
+            (Some(data), _) => Ok(data),
+            (None, Some(path)) => std::fs::read(path).map_err(convert_io_error),
+            (None, None) => Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                String::from("Attachment is stored on disk but no attachments directory is set"),
+            )),
+        }
+    }
 
-    item.title = String::from("Updated title");
-    repo.update(&mut item)?;
-    assert!(item.modified.is_some());
-    assert!(item.deleted.is_none());
+    pub fn delete_attachment(&mut self, id: u32) -> Result<(), AppError> {
+        self.cxn
+            .execute("DELETE FROM attachments WHERE id = ?", params![id])
+            .map_err(convert_db_error)?;
+        if let Some(path) = self.attachment_path(id) {
+            if path.exists() {
+                std::fs::remove_file(path).map_err(convert_io_error)?;
+            }
+        }
+        Ok(())
+    }
 
-    let updated = repo.get(item.id)?;
-    compare_item_fields(&item, &updated);
-    assert!(updated.modified.is_some());
-    assert!(updated.deleted.is_none());
+    /// Permanently remove a deleted item and its attachments.
+    pub fn purge(&mut self, id: &u32) -> Result<(), AppError> {
+        self.get_deleted(*id)?;
+        for attachment in self.attachments(*id)? {
+            self.delete_attachment(attachment.id)?;
+        }
+        self.cxn
+            .execute("DELETE FROM links WHERE source = ?1 OR target = ?1", params![id])
+            .map_err(convert_db_error)?;
+        self.cxn
+            .execute("DELETE FROM drafts WHERE key = ?", params![DraftOf::Item(*id).key()])
+            .map_err(convert_db_error)?;
+        self.cxn
+            .execute("DELETE FROM item_positions WHERE item = ?", params![id])
+            .map_err(convert_db_error)?;
+        self.cxn
+            .execute("DELETE FROM items WHERE rowid = ?", params![id])
+            .map_err(convert_db_error)
+            .map(|_| ())
+    }
 
-    repo.delete(&item.id)?; // It's too bad this doesn't mark the item as deleted...
-    assert!(repo.get(item.id).is_err());
-    assert!(repo.get_deleted(item.id).is_ok());
+    /// Remove attachments whose items no longer exist (e.g. because the
+    /// automation script deleted them).
+    pub fn remove_orphaned_attachments(&mut self) -> Result<(), AppError> {
+        let mut stmt = self
+            .cxn
+            .prepare("SELECT id FROM attachments WHERE item NOT IN (SELECT rowid FROM items)")
+            .map_err(convert_db_error)?;
+        let orphans: Vec<u32> = stmt
+            .query_map([], |r| r.get(0))
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error)?;
+        drop(stmt);
+        for id in orphans {
+            self.delete_attachment(id)?;
+        }
+        Ok(())
+    }
+
+    fn attachment_path(&self, id: u32) -> Option<PathBuf> {
+        self.attachments_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("attachment-{}", id)))
+    }
+}
+
+/// Lists of items
+impl Repo {
+    /// The names of every list, in order of name.
+    pub fn lists(&self) -> Result<Vec<String>, AppError> {
+        let mut stmt = self
+            .cxn
+            .prepare("SELECT name FROM lists ORDER BY name")
+            .map_err(convert_db_error)?;
+        let result = stmt
+            .query_map([], |r| r.get(0))
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error);
+        result
+    }
+
+    pub fn has_list(&self, name: &str) -> Result<bool, AppError> {
+        self.cxn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM lists WHERE name = ?)",
+                params![name],
+                |r| r.get(0),
+            )
+            .map_err(convert_db_error)
+    }
+
+    /// How many items are in a list, deleted or not.
+    pub fn list_size(&self, name: &str) -> Result<usize, AppError> {
+        self.cxn
+            .query_row(
+                &format!("SELECT count(*) FROM items WHERE {}", IN_LIST),
+                params![name],
+                |r| r.get::<_, i64>(0),
+            )
+            .map(|count| count as usize)
+            .map_err(convert_db_error)
+    }
+
+    /// Whether a list has any items in it, deleted or not.
+    pub fn list_has_items(&self, name: &str) -> Result<bool, AppError> {
+        self.cxn
+            .query_row(
+                &format!("SELECT EXISTS (SELECT 1 FROM items WHERE {})", IN_LIST),
+                params![name],
+                |r| r.get(0),
+            )
+            .map_err(convert_db_error)
+    }
+
+    /// Create a list. Names are used in URLs and environment variables, so
+    /// they're limited to lower-case letters, digits and dashes.
+    pub fn add_list(&mut self, name: &str) -> Result<(), AppError> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Invalid list name '{}', use lower-case letters, digits and dashes",
+                    name
+                ),
+            ));
+        }
+        if self.has_list(name)? {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("There's already a list called '{}'", name),
+            ));
+        }
+        self.cxn
+            .execute(
+                "INSERT INTO lists (name, created) VALUES (?, ?)",
+                params![name, Utc::now().timestamp()],
+            )
+            .map_err(convert_db_error)?;
+        Ok(())
+    }
+
+    /// Delete an empty list. Lists with items in them, even deleted ones,
+    /// can't be deleted, and neither can the default list.
+    pub fn delete_list(&mut self, name: &str) -> Result<(), AppError> {
+        if name == DEFAULT_LIST {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("The default list can't be deleted"),
+            ));
+        }
+        if self.list_has_items(name)? {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("The list '{}' still has items in it", name),
+            ));
+        }
+        let deleted = self
+            .cxn
+            .execute("DELETE FROM lists WHERE name = ?", params![name])
+            .map_err(convert_db_error)?;
+        if deleted == 0 {
+            return Err((StatusCode::NOT_FOUND, String::from("No such list")));
+        }
+        Ok(())
+    }
+}
+
+/// Users and sessions
+impl Repo {
+    pub fn add_user(&mut self, name: &str, password_hash: &str) -> Result<u32, AppError> {
+        if self.user_password_hash(name)?.is_some() {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("There's already a user called '{}'", name),
+            ));
+        }
+        self.cxn
+            .query_row(
+                "INSERT INTO users (name, password_hash, created) VALUES (?, ?, ?) RETURNING id",
+                params![name, password_hash, Utc::now().timestamp()],
+                |r| r.get(0),
+            )
+            .map_err(convert_db_error)
+    }
+
+    /// Whether any users have been created, which turns on authentication.
+    pub fn has_users(&self) -> Result<bool, AppError> {
+        self.cxn
+            .query_row("SELECT EXISTS (SELECT 1 FROM users)", [], |r| r.get(0))
+            .map_err(convert_db_error)
+    }
+
+    /// Every user, in order of name.
+    pub fn users(&self) -> Result<Vec<User>, AppError> {
+        let mut stmt = self
+            .cxn
+            .prepare("SELECT id, name FROM users ORDER BY name")
+            .map_err(convert_db_error)?;
+        let result = stmt
+            .query_map([], |r| {
+                Ok(User {
+                    id: r.get(0)?,
+                    name: r.get(1)?,
+                })
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error);
+        result
+    }
+
+    /// The id and password hash of the user called `name`.
+    pub fn user_password_hash(&self, name: &str) -> Result<Option<(u32, String)>, AppError> {
+        self.cxn
+            .query_row(
+                "SELECT id, password_hash FROM users WHERE name = ?",
+                params![name],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .optional()
+            .map_err(convert_db_error)
+    }
+
+    /// Sessions are stored by a hash of their token (see `auth::hash_token`),
+    /// like API tokens.
+    pub fn add_session(
+        &mut self,
+        token_hash: &str,
+        user: u32,
+        csrf: &str,
+        expires: i64,
+    ) -> Result<(), AppError> {
+        self.cxn
+            .execute(
+                "DELETE FROM sessions WHERE expires <= ?",
+                params![Utc::now().timestamp()],
+            )
+            .map_err(convert_db_error)?;
+        self.cxn
+            .execute(
+                "INSERT INTO sessions (token, user, csrf, expires) VALUES (?, ?, ?, ?)",
+                params![token_hash, user, csrf, expires],
+            )
+            .map_err(convert_db_error)?;
+        Ok(())
+    }
+
+    /// The unexpired session whose token hashes to `token_hash`, if there is
+    /// one.
+    pub fn session(&self, token_hash: &str) -> Result<Option<Session>, AppError> {
+        self.cxn
+            .query_row(
+                "SELECT sessions.user, sessions.csrf
+                 FROM sessions JOIN users ON users.id = sessions.user
+                 WHERE sessions.token = ? AND sessions.expires > ?",
+                params![token_hash, Utc::now().timestamp()],
+                |r| {
+                    Ok(Session {
+                        user: r.get(0)?,
+                        csrf: r.get(1)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(convert_db_error)
+    }
+
+    pub fn delete_session(&mut self, token_hash: &str) -> Result<(), AppError> {
+        self.cxn
+            .execute("DELETE FROM sessions WHERE token = ?", params![token_hash])
+            .map_err(convert_db_error)?;
+        Ok(())
+    }
+}
+
+/// API tokens
+impl Repo {
+    pub fn add_api_token(
+        &mut self,
+        name: &str,
+        user: Option<u32>,
+        token_hash: &str,
+        scope: &str,
+    ) -> Result<ApiToken, AppError> {
+        let id: u32 = self
+            .cxn
+            .query_row(
+                "INSERT INTO api_tokens (name, user, token_hash, scope, created)
+                 VALUES (?, ?, ?, ?, ?) RETURNING id",
+                params![name, user, token_hash, scope, Utc::now().timestamp()],
+                |r| r.get(0),
+            )
+            .map_err(convert_db_error)?;
+        self.api_tokens()?
+            .into_iter()
+            .find(|t| t.id == id)
+            .ok_or((StatusCode::NOT_FOUND, String::from("No such API token")))
+    }
+
+    pub fn api_tokens(&self) -> Result<Vec<ApiToken>, AppError> {
+        let mut stmt = self
+            .cxn
+            .prepare(
+                "SELECT api_tokens.id, api_tokens.name, api_tokens.user, users.name,
+                        api_tokens.scope, api_tokens.created, api_tokens.last_used
+                 FROM api_tokens LEFT JOIN users ON users.id = api_tokens.user
+                 ORDER BY api_tokens.id",
+            )
+            .map_err(convert_db_error)?;
+        let result = stmt
+            .query_map([], api_token_from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error);
+        result
+    }
+
+    /// Find the token with `token_hash` and record that it's been used.
+    pub fn use_api_token(&mut self, token_hash: &str) -> Result<Option<ApiToken>, AppError> {
+        self.cxn
+            .query_row(
+                "UPDATE api_tokens SET last_used = ? WHERE token_hash = ?
+                 RETURNING id, name, user, NULL, scope, created, last_used",
+                params![Utc::now().timestamp(), token_hash],
+                api_token_from_row,
+            )
+            .optional()
+            .map_err(convert_db_error)
+    }
+
+    /// Revoke a token. With `user`, only that user's own tokens can be revoked
+    /// (from their session); without, any token can (from the command line).
+    pub fn revoke_api_token(&mut self, id: u32, user: Option<u32>) -> Result<(), AppError> {
+        let deleted = self
+            .cxn
+            .execute(
+                "DELETE FROM api_tokens WHERE id = ?1 AND (?2 IS NULL OR user = ?2)",
+                params![id, user],
+            )
+            .map_err(convert_db_error)?;
+        if deleted == 0 {
+            return Err((StatusCode::NOT_FOUND, String::from("No such API token")));
+        }
+        Ok(())
+    }
+}
+
+/// Drafts, one per item (or new item in a list) for each user. Drafts saved
+/// without logging in are shared, as user 0.
+impl Repo {
+    pub fn save_draft(
+        &mut self,
+        of: &DraftOf,
+        user: Option<u32>,
+        title: &str,
+        body: &str,
+    ) -> Result<(), AppError> {
+        self.cxn
+            .execute(
+                "INSERT OR REPLACE INTO drafts (key, user, title, body, saved)
+                 VALUES (?, ?, ?, ?, ?)",
+                params![of.key(), user.unwrap_or(0), title, body, Utc::now().timestamp()],
+            )
+            .map_err(convert_db_error)?;
+        Ok(())
+    }
+
+    pub fn draft(&self, of: &DraftOf, user: Option<u32>) -> Result<Option<Draft>, AppError> {
+        self.cxn
+            .query_row(
+                "SELECT title, body, saved FROM drafts WHERE key = ? AND user = ?",
+                params![of.key(), user.unwrap_or(0)],
+                |r| {
+                    Ok(Draft {
+                        title: r.get(0)?,
+                        body: r.get(1)?,
+                        saved: r.get(2)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(convert_db_error)
+    }
+
+    pub fn discard_draft(&mut self, of: &DraftOf, user: Option<u32>) -> Result<(), AppError> {
+        self.cxn
+            .execute(
+                "DELETE FROM drafts WHERE key = ? AND user = ?",
+                params![of.key(), user.unwrap_or(0)],
+            )
+            .map_err(convert_db_error)?;
+        Ok(())
+    }
+}
+
+/// The home page's manual order and saved views
+impl Repo {
+    /// Items' places in the manual order, lowest first. Items that have
+    /// never been moved don't have one.
+    pub fn item_positions(&self) -> Result<HashMap<u32, i64>, AppError> {
+        let mut stmt = self
+            .cxn
+            .prepare("SELECT item, position FROM item_positions")
+            .map_err(convert_db_error)?;
+        let result = stmt
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error);
+        result
+    }
+
+    /// Put `ids` in that order, in the places they already take up, so that
+    /// items not in `ids` (e.g. because they were filtered out) stay where
+    /// they are. Items without places get new ones at the end.
+    pub fn reorder(&mut self, ids: &[u32]) -> Result<(), AppError> {
+        let positions = self.item_positions()?;
+        let mut places: Vec<i64> = ids.iter().filter_map(|id| positions.get(id).copied()).collect();
+        let mut next = positions.values().max().map_or(0, |p| p + 1);
+        while places.len() < ids.len() {
+            places.push(next);
+            next += 1;
+        }
+        places.sort_unstable();
+        let tx = self.cxn.savepoint().map_err(convert_db_error)?;
+        for (id, position) in ids.iter().zip(places) {
+            tx.execute(
+                "INSERT OR REPLACE INTO item_positions (item, position) VALUES (?, ?)",
+                params![id, position],
+            )
+            .map_err(convert_db_error)?;
+        }
+        tx.commit().map_err(convert_db_error)
+    }
+
+    /// Every saved view, in order of name.
+    pub fn saved_views(&self) -> Result<Vec<SavedView>, AppError> {
+        let mut stmt = self
+            .cxn
+            .prepare("SELECT name, query FROM saved_views ORDER BY name COLLATE NOCASE")
+            .map_err(convert_db_error)?;
+        let result = stmt
+            .query_map([], |r| {
+                Ok(SavedView {
+                    name: r.get(0)?,
+                    query: r.get(1)?,
+                })
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error);
+        result
+    }
+
+    /// Save a view, replacing any with the same name.
+    pub fn save_view(&mut self, name: &str, query: &str) -> Result<(), AppError> {
+        if name.trim().is_empty() {
+            return Err((StatusCode::BAD_REQUEST, String::from("Views need a name")));
+        }
+        self.cxn
+            .execute(
+                "INSERT OR REPLACE INTO saved_views (name, query) VALUES (?, ?)",
+                params![name.trim(), query],
+            )
+            .map_err(convert_db_error)?;
+        Ok(())
+    }
+
+    pub fn delete_saved_view(&mut self, name: &str) -> Result<(), AppError> {
+        let deleted = self
+            .cxn
+            .execute("DELETE FROM saved_views WHERE name = ?", params![name])
+            .map_err(convert_db_error)?;
+        if deleted == 0 {
+            return Err((StatusCode::NOT_FOUND, String::from("No such view")));
+        }
+        Ok(())
+    }
+}
+
+/// Item templates. Tags are stored separated by spaces, without their `#`.
+impl Repo {
+    /// Every template, in order of name.
+    pub fn item_templates(&self) -> Result<Vec<ItemTemplate>, AppError> {
+        let mut stmt = self
+            .cxn
+            .prepare(
+                "SELECT id, name, title, body, important, urgent, tags
+                 FROM item_templates ORDER BY name COLLATE NOCASE",
+            )
+            .map_err(convert_db_error)?;
+        let result = stmt
+            .query_map([], item_template_from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error);
+        result
+    }
+
+    pub fn item_template(&self, id: u32) -> Result<ItemTemplate, AppError> {
+        self.cxn
+            .query_row(
+                "SELECT id, name, title, body, important, urgent, tags
+                 FROM item_templates WHERE id = ?",
+                params![id],
+                item_template_from_row,
+            )
+            .optional()
+            .map_err(convert_db_error)?
+            .ok_or((StatusCode::NOT_FOUND, String::from("No such template")))
+    }
+
+    /// Add a template, or replace the one with the same id if it's already
+    /// saved. Returns the template's id.
+    pub fn save_item_template(&mut self, template: &ItemTemplate) -> Result<u32, AppError> {
+        if template.name.trim().is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("Templates need a name"),
+            ));
+        }
+        let taken: Option<u32> = self
+            .cxn
+            .query_row(
+                "SELECT id FROM item_templates WHERE name = ? AND id != ?",
+                params![template.name, template.id],
+                |r| r.get(0),
+            )
+            .optional()
+            .map_err(convert_db_error)?;
+        if taken.is_some() {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("There's already a template called '{}'", template.name),
+            ));
+        }
+        let id = (template.id != 0).then_some(template.id);
+        self.cxn
+            .query_row(
+                "INSERT OR REPLACE INTO item_templates (id, name, title, body, important, urgent, tags)
+                 VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING id",
+                params![
+                    id,
+                    template.name,
+                    template.title,
+                    template.body,
+                    template.important,
+                    template.urgent,
+                    template.tags.join(" "),
+                ],
+                |r| r.get(0),
+            )
+            .map_err(convert_db_error)
+    }
+
+    pub fn delete_item_template(&mut self, id: u32) -> Result<(), AppError> {
+        let deleted = self
+            .cxn
+            .execute("DELETE FROM item_templates WHERE id = ?", params![id])
+            .map_err(convert_db_error)?;
+        if deleted == 0 {
+            return Err((StatusCode::NOT_FOUND, String::from("No such template")));
+        }
+        Ok(())
+    }
+}
+
+/// Statistics about a list's items (see `stats`)
+impl Repo {
+    /// How many items in one list are open and how many are done (deleted).
+    pub fn open_and_done(&self, list: &str) -> Result<(usize, usize), AppError> {
+        self.cxn
+            .query_row(
+                &format!(
+                    "SELECT count(*) - count(json_extract(item, '$.deleted')),
+                            count(json_extract(item, '$.deleted'))
+                     FROM items WHERE {}",
+                    IN_LIST
+                ),
+                params![list],
+                |r| Ok((r.get::<_, i64>(0)? as usize, r.get::<_, i64>(1)? as usize)),
+            )
+            .map_err(convert_db_error)
+    }
+
+    /// How many items in one list were created and done in each week, by the
+    /// date of the Monday the week starts on in local time (like
+    /// `view_options::week_of`), e.g. "2022-10-31".
+    pub fn weekly_activity(&self, list: &str) -> Result<HashMap<String, (usize, usize)>, AppError> {
+        const WEEK: &str = "'unixepoch', 'localtime', 'weekday 0', '-6 days'";
+        let sql = format!(
+            "SELECT week, sum(created), sum(done) FROM (
+                 SELECT date(json_extract(item, '$.created'), {week}) AS week,
+                        1 AS created, 0 AS done
+                 FROM items WHERE {in_list}
+                 UNION ALL
+                 SELECT date(json_extract(item, '$.deleted'), {week}), 0, 1
+                 FROM items WHERE {in_list} AND json_extract(item, '$.deleted') IS NOT NULL
+             ) GROUP BY week",
+            week = WEEK,
+            in_list = IN_LIST
+        );
+        let mut stmt = self.cxn.prepare(&sql).map_err(convert_db_error)?;
+        let result = stmt
+            .query_map(params![list, list], |r| {
+                let counts = (r.get::<_, i64>(1)? as usize, r.get::<_, i64>(2)? as usize);
+                Ok((r.get(0)?, counts))
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error);
+        result
+    }
+
+    /// For the open items in one list in each (non-empty) quadrant: how many
+    /// there are, and on average how long ago, as of `now`, they were
+    /// created and last modified (or created), in seconds.
+    pub fn quadrant_ages(&self, list: &str, now: i64) -> Result<Vec<(Quadrant, usize, f64, f64)>, AppError> {
+        let sql = format!(
+            "SELECT coalesce(json_extract(item, '$.important'), 0),
+                    coalesce(json_extract(item, '$.urgent'), 0),
+                    count(*),
+                    avg(max(? - json_extract(item, '$.created'), 0)),
+                    avg(max(? - coalesce(json_extract(item, '$.modified'),
+                                         json_extract(item, '$.created')), 0))
+             FROM items WHERE json_extract(item, '$.deleted') IS NULL AND {}
+             GROUP BY 1, 2",
+            IN_LIST
+        );
+        let mut stmt = self.cxn.prepare(&sql).map_err(convert_db_error)?;
+        let result = stmt
+            .query_map(params![now, now, list], |r| {
+                let quadrant = Quadrant::of(r.get(0)?, r.get(1)?);
+                Ok((quadrant, r.get::<_, i64>(2)? as usize, r.get(3)?, r.get(4)?))
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error);
+        result
+    }
+
+    /// Up to `limit` open items in one list, oldest first.
+    pub fn oldest_open(&self, list: &str, limit: usize) -> Result<Vec<Item>, AppError> {
+        self.items_where(
+            &format!(
+                "json_extract(item, '$.deleted') IS NULL AND {}
+                 ORDER BY json_extract(item, '$.created'), rowid LIMIT ?",
+                IN_LIST
+            ),
+            params![list, limit as i64],
+        )
+    }
+}
+
+/// Private methods of Repo
+impl Repo {
+    /// Items in rows matching a condition (and any clauses after it).
+    fn items_where<P: rusqlite::Params>(&self, condition: &str, params: P) -> Result<Vec<Item>, AppError> {
+        let sql = format!("SELECT item FROM items WHERE {}", condition);
+        let mut stmt = self.cxn.prepare(&sql).map_err(convert_db_error)?;
+        let serialized: Vec<String> = stmt
+            .query_map(params, |r| r.get(0))
+            .and_then(|rows| rows.collect())
+            .map_err(convert_db_error)?;
+        serialized.iter().map(|s| Item::deserialize(s)).collect()
+    }
+
+    /// SQL functions for compiled queries (see `query`) to look inside
+    /// bodies with.
+    fn add_query_functions(&self) -> rusqlite::Result<()> {
+        use rusqlite::functions::FunctionFlags;
+
+        let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+        self.cxn.create_scalar_function("wannado_has_tag", 2, flags, |ctx| {
+            let body: Option<String> = ctx.get(0)?;
+            let tag: String = ctx.get(1)?;
+            Ok(body.is_some_and(|body| markdown::tags(&body).contains(&tag)))
+        })?;
+        self.cxn.create_scalar_function("wannado_due", 1, flags, |ctx| {
+            let body: Option<String> = ctx.get(0)?;
+            Ok(body
+                .and_then(|body| markdown::due_date(&body))
+                .map(|due| due.format("%Y-%m-%d").to_string()))
+        })?;
+        self.cxn.create_scalar_function("wannado_snoozed", 1, flags, |ctx| {
+            let body: Option<String> = ctx.get(0)?;
+            Ok(body
+                .and_then(|body| markdown::snoozed_until(&body))
+                .map(|until| until.format("%Y-%m-%d").to_string()))
+        })?;
+        self.cxn.create_scalar_function("wannado_contains", 2, flags, |ctx| {
+            let text: Option<String> = ctx.get(0)?;
+            let part: String = ctx.get(1)?;
+            Ok(text.is_some_and(|text| text.to_lowercase().contains(&part.to_lowercase())))
+        })?;
+        // Like lower(), but for every language rather than just ASCII
+        self.cxn.create_scalar_function("wannado_lower", 1, flags, |ctx| {
+            let text: Option<String> = ctx.get(0)?;
+            Ok(text.map(|text| text.to_lowercase()))
+        })?;
+        // As a JSON array, to join with json_each()
+        self.cxn.create_scalar_function("wannado_tags", 1, flags, |ctx| {
+            let body: Option<String> = ctx.get(0)?;
+            let tags = body.map(|body| markdown::tags(&body)).unwrap_or_default();
+            Ok(serde_json::Value::from(tags).to_string())
+        })
+    }
+
+    fn resolve_reference(&self, reference: &markdown::Reference) -> Result<Option<u32>, AppError> {
+        let (query, param) = match reference {
+            markdown::Reference::Id(id) => ("SELECT rowid FROM items WHERE rowid = ?", id.to_string()),
+            // Prefer active items if several have the same title
+            markdown::Reference::Title(title) => (
+                "SELECT rowid FROM items
+                 WHERE json_extract(item, '$.title') = ? COLLATE NOCASE
+                 ORDER BY json_extract(item, '$.deleted') IS NOT NULL, rowid
+                 LIMIT 1",
+                title.clone(),
+            ),
+        };
+        self.cxn
+            .query_row(query, params![param], |r| r.get(0))
+            .optional()
+            .map_err(convert_db_error)
+    }
+
+    /// Re-index the links from items that mention any of `titles`, since
+    /// their `[[title]]` references might now point at a different item (or
+    /// at one, where they didn't before).
+    fn index_title_references(&self, titles: &[&str]) -> Result<(), AppError> {
+        for title in titles.iter().map(|t| t.trim()).filter(|t| !t.is_empty()) {
+            let sources = self.items_where(
+                "instr(lower(json_extract(item, '$.body')), lower(?)) > 0",
+                params![title],
+            )?;
+            for source in sources {
+                self.index_item_links(&source)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// After `item` replaces `old`, re-index the references to its old and
+    /// new titles if it's been renamed, deleted or restored (which changes
+    /// which item a title refers to).
+    fn index_changed_title(&self, old: Option<&Item>, item: &Item) -> Result<(), AppError> {
+        match old {
+            Some(old) if old.title != item.title || old.deleted != item.deleted => {
+                self.index_title_references(&[&old.title, &item.title])
+            }
+            Some(_) => Ok(()),
+            None => self.index_title_references(&[&item.title]),
+        }
+    }
+
+    fn index_item_links(&self, item: &Item) -> Result<(), AppError> {
+        self.cxn
+            .execute("DELETE FROM links WHERE source = ?", params![item.id])
+            .map_err(convert_db_error)?;
+        for reference in markdown::references(&item.body) {
+            if let Some(target) = self.resolve_reference(&reference)? {
+                self.cxn
+                    .execute(
+                        "INSERT INTO links (source, target) VALUES (?, ?)",
+                        params![item.id, target],
+                    )
+                    .map_err(convert_db_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Helpers
+
+/// A condition for rows in the list given as a parameter. Items from before
+/// there were lists are in the default list.
+const IN_LIST: &str = "coalesce(json_extract(item, '$.list'), 'main') = ?";
+
+fn default_list() -> String {
+    String::from(DEFAULT_LIST)
+}
+
+fn attachment_from_row(r: &rusqlite::Row) -> rusqlite::Result<Attachment> {
+    Ok(Attachment {
+        id: r.get(0)?,
+        item: r.get(1)?,
+        name: r.get(2)?,
+        content_type: r.get(3)?,
+        size: r.get(4)?,
+        created: r.get(5)?,
+    })
+}
+
+fn item_template_from_row(r: &rusqlite::Row) -> rusqlite::Result<ItemTemplate> {
+    let tags: String = r.get(6)?;
+    Ok(ItemTemplate {
+        id: r.get(0)?,
+        name: r.get(1)?,
+        title: r.get(2)?,
+        body: r.get(3)?,
+        important: r.get(4)?,
+        urgent: r.get(5)?,
+        tags: tags.split_whitespace().map(String::from).collect(),
+    })
+}
+
+fn api_token_from_row(r: &rusqlite::Row) -> rusqlite::Result<ApiToken> {
+    Ok(ApiToken {
+        id: r.get(0)?,
+        name: r.get(1)?,
+        user: r.get(2)?,
+        user_name: r.get(3)?,
+        scope: r.get(4)?,
+        created: r.get(5)?,
+        last_used: r.get(6)?,
+    })
+}
+
+fn convert_io_error(err: std::io::Error) -> AppError {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Attachment storage error: {:?}", err),
+    )
+}
+
+fn convert_db_error(err: rusqlite::Error) -> AppError {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Database error: {:?}", err),
+    )
+}
+
+#[test]
+fn test_repo() -> Result<(), AppError> {
+    fn compare_item_fields(item1: &Item, item2: &Item) {
+        assert!(item1.id == item2.id);
+        assert!(item1.title == item2.title);
+        assert!(item1.body == item2.body);
+        assert!(item1.important == item2.important);
+        assert!(item1.urgent == item2.urgent);
+    }
+
+    let cxn = Connection::open_in_memory().map_err(convert_db_error)?;
+    let mut repo = Repo::new(cxn);
+    repo.init().map_err(convert_db_error)?;
+
+    let mut item = repo.add(DEFAULT_LIST, "Test Item", "Test item body.", true, false, None)?;
+    assert!(item.modified.is_none());
+    assert!(item.deleted.is_none());
+
+    let retrieved = repo.get(item.id)?;
+    compare_item_fields(&item, &retrieved);
+    assert!(item.modified.is_none());
+    assert!(item.deleted.is_none());
+    assert!(retrieved.modified.is_none());
+    assert!(retrieved.deleted.is_none());
+
+    item.title = String::from("Updated title");
+    repo.update(&mut item, None)?;
+    assert!(item.modified.is_some());
+    assert!(item.deleted.is_none());
+
+    let updated = repo.get(item.id)?;
+    compare_item_fields(&item, &updated);
+    assert!(updated.modified.is_some());
+    assert!(updated.deleted.is_none());
+
+    repo.delete(&item.id, None)?; // It's too bad this doesn't mark the item as deleted...
+    assert!(repo.get(item.id).is_err());
+    assert!(repo.get_deleted(item.id).is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_backlinks() -> Result<(), AppError> {
+    let cxn = Connection::open_in_memory().map_err(convert_db_error)?;
+    let mut repo = Repo::new(cxn);
+    repo.init().map_err(convert_db_error)?;
+
+    let target = repo.add(DEFAULT_LIST, "Buy milk", "", false, false, None)?;
+    let body = format!("Don't forget #{}", target.id);
+    let by_id = repo.add(DEFAULT_LIST, "Groceries", &body, false, false, None)?;
+    let mut by_title = repo.add(DEFAULT_LIST, "Errands", "Nothing yet", false, false, None)?;
+    assert_eq!(repo.backlinks(target.id)?.len(), 1);
+
+    by_title.body = String::from("Then [[buy MILK]]");
+    repo.update(&mut by_title, None)?;
+    let backlinks: Vec<u32> = repo.backlinks(target.id)?.iter().map(|i| i.id).collect();
+    assert_eq!(backlinks, vec![by_id.id, by_title.id]);
+
+    repo.delete(&by_id.id, None)?;
+    let backlinks: Vec<u32> = repo.backlinks(target.id)?.iter().map(|i| i.id).collect();
+    assert_eq!(backlinks, vec![by_title.id]);
+
+    repo.delete(&target.id, None)?;
+    let links = repo.resolve_links(&by_title.body)?;
+    let rendered = markdown::link_references(
+        pulldown_cmark::Parser::new_ext(&by_title.body, markdown::options()),
+        &links,
+    );
+    assert!(rendered.iter().any(|e| matches!(e,
+        pulldown_cmark::Event::Html(html) if html.contains("item-link broken"))));
+
+    // References to titles that don't exist yet, or any more
+    let early = repo.add(DEFAULT_LIST, "Plans", "See [[Call plumber]]", false, false, None)?;
+    let mut plumber = repo.add(DEFAULT_LIST, "Call plumber", "", false, false, None)?;
+    let backlinks: Vec<u32> = repo.backlinks(plumber.id)?.iter().map(|i| i.id).collect();
+    assert_eq!(backlinks, vec![early.id]);
+    plumber.title = String::from("Call electrician");
+    repo.update(&mut plumber, None)?;
+    assert!(repo.backlinks(plumber.id)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_attachments() -> Result<(), AppError> {
+    fn check(mut repo: Repo) -> Result<(), AppError> {
+        repo.init().map_err(convert_db_error)?;
+        let item = repo.add(DEFAULT_LIST, "Screenshots", "![shot](attachment:1)", false, false, None)?;
+        let attachment = repo.add_attachment(item.id, "shot.png", "image/png", b"not really a png")?;
+        assert_eq!(attachment.size, 16);
+        assert_eq!(repo.get_attachment(attachment.id)?.name, "shot.png");
+        assert_eq!(repo.attachment_data(attachment.id)?, b"not really a png");
+
+        // Soft deletion keeps attachments around for restoration
+        repo.delete(&item.id, None)?;
+        assert_eq!(repo.attachments(item.id)?.len(), 1);
+        assert!(repo.attachment_data(attachment.id).is_ok());
+
+        repo.purge(&item.id)?;
+        assert!(repo.get_deleted(item.id).is_err());
+        assert!(repo.attachments(item.id)?.is_empty());
+        assert!(repo.attachment_data(attachment.id).is_err());
+        Ok(())
+    }
+
+    let cxn = Connection::open_in_memory().map_err(convert_db_error)?;
+    check(Repo::new(cxn))?;
+
+    let dir = std::env::temp_dir().join(format!("wannado-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).map_err(convert_io_error)?;
+    let cxn = Connection::open_in_memory().map_err(convert_db_error)?;
+    let mut repo = Repo::new(cxn);
+    repo.store_attachments_in(dir.clone());
+    check(repo)?;
+    assert_eq!(std::fs::read_dir(&dir).map_err(convert_io_error)?.count(), 0);
+    std::fs::remove_dir(&dir).map_err(convert_io_error)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_users_and_sessions() -> Result<(), AppError> {
+    let mut repo = Repo::new(Connection::open_in_memory().map_err(convert_db_error)?);
+    repo.init().map_err(convert_db_error)?;
+    assert!(!repo.has_users()?);
+    let id = repo.add_user("nat", "not-really-a-hash")?;
+    assert!(repo.has_users()?);
+    assert!(repo.add_user("nat", "another").is_err());
+    assert_eq!(
+        repo.user_password_hash("nat")?,
+        Some((id, String::from("not-really-a-hash")))
+    );
+    assert_eq!(repo.user_password_hash("someone-else")?, None);
+
+    let now = Utc::now().timestamp();
+    repo.add_session("token", id, "csrf", now + 60)?;
+    repo.add_session("expired", id, "csrf", now - 60)?;
+    assert_eq!(repo.session("token")?.unwrap().csrf, "csrf");
+    assert!(repo.session("expired")?.is_none());
+    repo.delete_session("token")?;
+    assert!(repo.session("token")?.is_none());
+
+    let token = repo.add_api_token("cron", Some(id), "hash", "read")?;
+    assert_eq!(token.user_name.as_deref(), Some("nat"));
+    assert!(token.last_used.is_none());
+    let global = repo.add_api_token("global", None, "other-hash", "write")?;
+    assert!(repo.use_api_token("hash")?.unwrap().last_used.is_some());
+    assert!(repo.use_api_token("no-such-hash")?.is_none());
+    // Users can only revoke their own tokens
+    let sam = repo.add_user("sam", "hash")?;
+    assert!(repo.revoke_api_token(token.id, Some(sam)).is_err());
+    assert!(repo.revoke_api_token(global.id, Some(id)).is_err());
+    repo.revoke_api_token(token.id, Some(id))?;
+    assert!(repo.use_api_token("hash")?.is_none());
+    assert_eq!(repo.api_tokens()?.len(), 1);
+    assert!(repo.revoke_api_token(token.id, None).is_err());
+    repo.revoke_api_token(global.id, None)?;
+    Ok(())
+}
+
+#[test]
+fn test_ownership() -> Result<(), AppError> {
+    let mut repo = Repo::new(Connection::open_in_memory().map_err(convert_db_error)?);
+    repo.init().map_err(convert_db_error)?;
+    let nat = repo.add_user("nat", "hash")?;
+    let sam = repo.add_user("sam", "hash")?;
+    assert_eq!(repo.users()?.iter().map(|u| u.id).collect::<Vec<_>>(), vec![nat, sam]);
+
+    let mut item = repo.add(DEFAULT_LIST, "Plan offsite", "", false, false, Some(nat))?;
+    assert_eq!((item.owner, item.created_by), (Some(nat), Some(nat)));
+    let shown_to = |repo: &Repo, user| -> Result<usize, AppError> {
+        Ok(repo.view_page(DEFAULT_LIST, &ViewOptions::default(), Some(user), None, 10)?.len())
+    };
+    assert_eq!((shown_to(&repo, nat)?, shown_to(&repo, sam)?), (1, 0));
+
+    item.assignees.push(sam);
+    repo.update(&mut item, Some(sam))?;
+    let item = repo.get(item.id)?;
+    assert_eq!(shown_to(&repo, sam)?, 1);
+    assert_eq!(item.modified_by, Some(sam));
+
+    repo.delete(&item.id, Some(sam))?;
+    assert_eq!(repo.get_deleted(item.id)?.deleted_by, Some(sam));
+    repo.restore(&item.id, Some(nat))?;
+    let item = repo.get(item.id)?;
+    assert_eq!((item.deleted_by, item.restored_by), (None, Some(nat)));
+
+    // Items from before there were owners
+    let old = Item::deserialize(
+        r#"{"id":1,"title":"Old","body":"","important":false,"urgent":false,"created":0,"modified":null,"deleted":null}"#,
+    )?;
+    assert!(old.owner.is_none() && old.assignees.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_lists() -> Result<(), AppError> {
+    let mut repo = Repo::new(Connection::open_in_memory().map_err(convert_db_error)?);
+    repo.init().map_err(convert_db_error)?;
+    assert_eq!(repo.lists()?, [DEFAULT_LIST]);
+    repo.add_list("home")?;
+    assert_eq!(repo.add_list("home").unwrap_err().0, StatusCode::CONFLICT);
+    assert_eq!(repo.add_list("Home").unwrap_err().0, StatusCode::BAD_REQUEST);
+    assert_eq!(repo.add_list("").unwrap_err().0, StatusCode::BAD_REQUEST);
+
+    let item = repo.add("home", "Fix the gate", "", false, false, None)?;
+    repo.add(DEFAULT_LIST, "Send report", "", false, false, None)?;
+    assert_eq!(repo.all_in("home")?.len(), 1);
+    repo.delete(&item.id, None)?;
+    assert_eq!((repo.list_size("home")?, repo.list_size(DEFAULT_LIST)?), (1, 1));
+    assert!(repo.list_has_items("home")?);
+    repo.restore(&item.id, None)?;
+    let mut item = repo.get(item.id)?;
+    assert_eq!(repo.get(item.id)?.list, "home");
+    assert_eq!(repo.delete_list("home").unwrap_err().0, StatusCode::CONFLICT);
+    assert_eq!(repo.delete_list(DEFAULT_LIST).unwrap_err().0, StatusCode::BAD_REQUEST);
+
+    // Moving the item empties the list, so it can be deleted
+    item.list = String::from(DEFAULT_LIST);
+    repo.update(&mut item, None)?;
+    assert_eq!(repo.all_in(DEFAULT_LIST)?.len(), 2);
+    assert!(!repo.list_has_items("home")?);
+    repo.delete_list("home")?;
+    assert_eq!(repo.delete_list("home").unwrap_err().0, StatusCode::NOT_FOUND);
+    assert_eq!(repo.lists()?, [DEFAULT_LIST]);
+    Ok(())
+}
+
+#[test]
+fn test_drafts() -> Result<(), AppError> {
+    let mut repo = Repo::new(Connection::open_in_memory().map_err(convert_db_error)?);
+    repo.init().map_err(convert_db_error)?;
+    let item = repo.add(DEFAULT_LIST, "Plan trip", "", false, false, None)?;
+    let of = DraftOf::Item(item.id);
+    let new = DraftOf::NewItem(String::from(DEFAULT_LIST));
+
+    repo.save_draft(&of, Some(1), "Plan trip", "Book flights")?;
+    repo.save_draft(&of, Some(1), "Plan trip", "Book flights and hotel")?;
+    repo.save_draft(&new, None, "Call mum", "")?;
+    assert_eq!(repo.draft(&of, Some(1))?.unwrap().body, "Book flights and hotel");
+    assert!(repo.draft(&of, Some(2))?.is_none());
+    assert!(repo.draft(&of, None)?.is_none());
+    assert_eq!(repo.draft(&new, None)?.unwrap().title, "Call mum");
+
+    repo.discard_draft(&new, None)?;
+    assert!(repo.draft(&new, None)?.is_none());
+    repo.delete(&item.id, None)?;
+    repo.purge(&item.id)?;
+    assert!(repo.draft(&of, Some(1))?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_item_templates() -> Result<(), AppError> {
+    let mut repo = Repo::new(Connection::open_in_memory().map_err(convert_db_error)?);
+    repo.init().map_err(convert_db_error)?;
+    let mut triage = ItemTemplate {
+        id: 0,
+        name: String::from("Bug triage"),
+        title: String::from("Bug: "),
+        body: String::from("## Steps to reproduce\n\n1. "),
+        important: true,
+        urgent: false,
+        tags: vec![String::from("bug"), String::from("triage")],
+    };
+    triage.id = repo.save_item_template(&triage)?;
+    let meeting = ItemTemplate {
+        id: 0,
+        name: String::from("Meeting notes"),
+        title: String::from("Meeting {{date}}"),
+        tags: vec![],
+        ..triage.clone()
+    };
+    let meeting_id = repo.save_item_template(&meeting)?;
+    assert_eq!(repo.item_template(triage.id)?, triage);
+    assert_eq!(repo.item_templates()?.len(), 2);
+
+    // Names are unique, but a template can be saved again under its own name
+    let duplicate = ItemTemplate { id: meeting_id, ..triage.clone() };
+    assert_eq!(repo.save_item_template(&duplicate).unwrap_err().0, StatusCode::CONFLICT);
+    triage.urgent = true;
+    assert_eq!(repo.save_item_template(&triage)?, triage.id);
+    assert!(repo.item_template(triage.id)?.urgent);
+
+    repo.delete_item_template(triage.id)?;
+    assert_eq!(repo.item_template(triage.id).unwrap_err().0, StatusCode::NOT_FOUND);
+    assert_eq!(repo.delete_item_template(triage.id).unwrap_err().0, StatusCode::NOT_FOUND);
+    assert_eq!(repo.item_templates()?[0].name, "Meeting notes");
+    Ok(())
+}
+
+#[test]
+fn test_order_and_saved_views() -> Result<(), AppError> {
+    let mut repo = Repo::new(Connection::open_in_memory().map_err(convert_db_error)?);
+    repo.init().map_err(convert_db_error)?;
+    repo.reorder(&[3, 1])?;
+    repo.reorder(&[5])?;
+    assert_eq!(repo.item_positions()?, HashMap::from([(3, 0), (1, 1), (5, 2)]));
+    // Moving 5 before 3 (with 1 filtered out) leaves 1 in its place
+    repo.reorder(&[5, 3])?;
+    assert_eq!(repo.item_positions()?, HashMap::from([(5, 0), (1, 1), (3, 2)]));
+
+    repo.save_view("Ops", "tag=ops")?;
+    repo.save_view("Ops", "tag=ops&sort=due")?;
+    repo.save_view("Quick wins", "flag=unflagged")?;
+    assert_eq!(repo.save_view(" ", "").unwrap_err().0, StatusCode::BAD_REQUEST);
+    let views = repo.saved_views()?;
+    assert_eq!(views.len(), 2);
+    assert_eq!(views[0].query, "tag=ops&sort=due");
+    repo.delete_saved_view("Ops")?;
+    assert_eq!(repo.delete_saved_view("Ops").unwrap_err().0, StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+#[test]
+fn test_search() -> Result<(), AppError> {
+    let mut repo = Repo::new(Connection::open_in_memory().map_err(convert_db_error)?);
+    repo.init().map_err(convert_db_error)?;
+    let nat = repo.add_user("nat", "hash")?;
+    let due = (Local::now() + chrono::Duration::days(2)).format("%Y-%m-%d");
+    let report = repo.add(DEFAULT_LIST, "Write report", &format!("#ops due: {}", due), true, true, Some(nat))?;
+    let mut old = repo.add(DEFAULT_LIST, "Rotate keys", "#Ops", false, true, None)?;
+    old.created -= 10 * 24 * 60 * 60;
+    repo.put(&old)?;
+    repo.add(DEFAULT_LIST, "Buy milk", "2 litres", false, false, None)?;
+    repo.add("main", "Deleted ops", "#ops", false, true, None)
+        .and_then(|i| repo.delete(&i.id, None))?;
+    repo.add_list("home")?;
+    repo.add("home", "Fix gate", "#ops", false, true, None)?;
+
+    let titles = |repo: &Repo, query: &str| -> Result<Vec<String>, AppError> {
+        let mut titles: Vec<String> = repo
+            .search(DEFAULT_LIST, &query.parse()?)?
+            .into_iter()
+            .map(|i| i.title)
+            .collect();
+        titles.sort();
+        Ok(titles)
+    };
+    assert_eq!(titles(&repo, "urgent AND tag:ops")?, ["Rotate keys", "Write report"]);
+    assert_eq!(titles(&repo, "urgent AND tag:ops AND age>7d")?, ["Rotate keys"]);
+    assert_eq!(titles(&repo, "due<7d")?, ["Write report"]);
+    assert_eq!(titles(&repo, "due>7d OR LITRES")?, ["Buy milk"]);
+    assert_eq!(titles(&repo, "owner:nat")?, ["Write report"]);
+    assert_eq!(titles(&repo, "-#ops NOT important")?, ["Buy milk"]);
+    // Items without a due date or an owner aren't due soon or owned by nat
+    assert_eq!(titles(&repo, "NOT due<7d")?, ["Buy milk", "Rotate keys"]);
+    assert_eq!(titles(&repo, "-owner:nat")?, ["Buy milk", "Rotate keys"]);
+    assert_eq!(titles(&repo, "title:\"rotate KEYS\"")?, ["Rotate keys"]);
+    assert_eq!(report.id, repo.search(DEFAULT_LIST, &"important".parse()?)?[0].id);
+
+    // Counts of what views show, as in the header's saved views
+    for view in ["", "flag=urgent&tag=ops", "flag=unflagged", "age=%3E7d", "text=MILK&q=-%23ops"] {
+        let options = ViewOptions::from_query(view).unwrap();
+        assert_eq!(
+            repo.count(DEFAULT_LIST, options.filter_query()?.as_ref())?,
+            repo.view_page(DEFAULT_LIST, &options, None, None, 10)?.len(),
+            "{}",
+            view
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_pages() -> Result<(), AppError> {
+    let mut repo = Repo::new(Connection::open_in_memory().map_err(convert_db_error)?);
+    repo.init().map_err(convert_db_error)?;
+    for n in 1..=5 {
+        repo.add(DEFAULT_LIST, &format!("Item {}", n), "", n % 2 == 0, false, None)?;
+    }
+    let ids = |items: Vec<Item>| -> Vec<u32> { items.into_iter().map(|i| i.id).collect() };
+    assert_eq!(ids(repo.items_page(DEFAULT_LIST, None, 0, Some(2))?), [1, 2]);
+    assert_eq!(ids(repo.items_page(DEFAULT_LIST, None, 2, Some(2))?), [3, 4]);
+    assert_eq!(ids(repo.items_page(DEFAULT_LIST, None, 4, None)?), [5]);
+    let important = "important".parse()?;
+    assert_eq!(ids(repo.items_page(DEFAULT_LIST, Some(&important), 2, None)?), [4]);
+
+    // Deleted at the same time, so newest id first
+    for id in [1, 3, 4] {
+        repo.delete(&id, None)?;
+    }
+    let first = repo.deleted_page(DEFAULT_LIST, None, 2)?;
+    let last = first.last().map(|i| (i.deleted.unwrap_or_default(), i.id));
+    assert_eq!(ids(first), [4, 3]);
+    assert_eq!(ids(repo.deleted_page(DEFAULT_LIST, last, 2)?), [1]);
+    assert_eq!(ids(repo.items_page(DEFAULT_LIST, None, 0, None)?), [2, 5]);
+    Ok(())
+}
+
+#[test]
+fn test_feed_items() -> Result<(), AppError> {
+    let mut repo = Repo::new(Connection::open_in_memory().map_err(convert_db_error)?);
+    repo.init().map_err(convert_db_error)?;
+    repo.add(DEFAULT_LIST, "Renew passport", "due: 2022-11-03 #travel", true, false, None)?;
+    repo.add(DEFAULT_LIST, "Book hotel", "#travel", false, false, None)?;
+    repo.add(DEFAULT_LIST, "Water plants", "restore-on 2022-12-01", false, false, None)?;
+    repo.add(DEFAULT_LIST, "File taxes", "due: 2023-04-15", false, false, None)?;
+    repo.delete(&3, None)?;
+    repo.delete(&4, None)?;
+    let ids = |items: Vec<Item>| -> Vec<u32> { items.into_iter().map(|i| i.id).collect() };
+
+    // Deleted items are only shown for their snoozes, and active ones for
+    // their due dates
+    assert_eq!(ids(repo.calendar_items(DEFAULT_LIST, None)?), [1, 3]);
+    let travel = "#travel".parse()?;
+    assert_eq!(ids(repo.calendar_items(DEFAULT_LIST, Some(&travel))?), [1]);
 
+    // Most recent activity first, deleted or not
+    let mut item = repo.get(1)?;
+    item.modified = Some(Utc::now().timestamp() + 60);
+    repo.put(&item)?;
+    assert_eq!(ids(repo.recently_active(DEFAULT_LIST, None, 2)?), [1, 4]);
+    assert_eq!(ids(repo.recently_active(DEFAULT_LIST, Some(&travel), 5)?), [1, 2]);
     Ok(())
 }
@@ -2,6 +2,11 @@
 //! in a sub-process every five minutes. This can connect to the app's
 //! database, perform cleanup, insert recurring items, etc.
 //!
+//! Each list can have its own script too, in `WANNADO_SCRIPT_<LIST>` with the
+//! list's name in upper case and dashes as underscores (e.g.
+//! `WANNADO_SCRIPT_SIDE_PROJECTS` for `side-projects`). Scripts are run with
+//! the list's name in `WANNADO_LIST`; `WANNADO_SCRIPT` is the default list's.
+//!
 //! Note the program does no shell processing; the argument is passed
 //! straight to `std::process::Command`. If you need to run a shell command
 //! you can save it in an executable script.
@@ -9,19 +14,41 @@
 //! Interval can be customized by setting the environment variable
 //! `WANNADO_SCRIPT_INTERVAL_IN_SECONDS`.
 
+use crate::repo::DEFAULT_LIST;
 use std::{process::Command, thread::sleep, time::Duration};
 
+const PREFIX: &str = "WANNADO_SCRIPT";
+const INTERVAL: &str = "WANNADO_SCRIPT_INTERVAL_IN_SECONDS";
+
 pub fn start_recurring_script() {
-    if let Ok(cmd) = std::env::var("WANNADO_SCRIPT") {
-        println!("Starting recurring script");
-        std::thread::spawn(move || recurring_script(cmd));
-    } else {
+    let scripts = scripts(std::env::vars());
+    if scripts.is_empty() {
         println!("No recurring script");
     }
+    for (list, cmd) in scripts {
+        println!("Starting recurring script for list '{}'", list);
+        std::thread::spawn(move || recurring_script(list, cmd));
+    }
 }
 
-fn recurring_script(cmd: String) {
-    let timeout = if let Ok(src) = std::env::var("WANNADO_SCRIPT_INTERVAL_IN_SECONDS") {
+/// The lists with scripts, and their commands, from environment variables.
+fn scripts(vars: impl Iterator<Item = (String, String)>) -> Vec<(String, String)> {
+    let mut scripts: Vec<(String, String)> = vars
+        .filter(|(name, _)| name != INTERVAL)
+        .filter_map(|(name, cmd)| {
+            let list = match name.strip_prefix(PREFIX)? {
+                "" => String::from(DEFAULT_LIST),
+                rest => rest.strip_prefix('_')?.to_lowercase().replace('_', "-"),
+            };
+            Some((list, cmd))
+        })
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+fn recurring_script(list: String, cmd: String) {
+    let timeout = if let Ok(src) = std::env::var(INTERVAL) {
         let seconds = src.parse::<u64>().expect(
             "Invalid script timeout, expected WANNADO_SCRIPT_INTERVAL_IN_SECOND to be an integer",
         );
@@ -34,9 +61,29 @@ fn recurring_script(cmd: String) {
         t
     };
     loop {
-        if let Err(e) = Command::new(&cmd).spawn() {
-            eprintln!("Error in script execution: {:?}", e);
+        if let Err(e) = Command::new(&cmd).env("WANNADO_LIST", &list).spawn() {
+            eprintln!("Error in script execution for list '{}': {:?}", list, e);
         }
         sleep(timeout);
     }
 }
+
+#[test]
+fn test_scripts() {
+    let vars = [
+        ("WANNADO_SCRIPT", "./daily.sh"),
+        ("WANNADO_SCRIPT_INTERVAL_IN_SECONDS", "60"),
+        ("WANNADO_SCRIPT_SIDE_PROJECTS", "./side.sh"),
+        ("WANNADO_SCRIPTS", "ignored"),
+        ("PATH", "/bin"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_owned(), v.to_owned()));
+    assert_eq!(
+        scripts(vars),
+        [
+            (String::from("main"), String::from("./daily.sh")),
+            (String::from("side-projects"), String::from("./side.sh")),
+        ]
+    );
+}
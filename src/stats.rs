@@ -0,0 +1,321 @@
+//! Statistics about a list's items for the `/stats` page, worked out in SQL
+//! from when they were created, modified and deleted.
+//!
+//! Deleting an item is how it gets marked done, so "done" here means
+//! deleted. Items don't record when their flags changed, so the time an
+//! item has spent in its quadrant is the time since it was last modified (or
+//! created), which is the longest it's certainly been there.
+
+use crate::repo::{Quadrant, Repo};
+use crate::view_options::week_of;
+use crate::AppError;
+use chrono::{DateTime, Duration, Local};
+
+/// How many weeks of activity to show, including this one.
+const WEEKS: i64 = 12;
+/// How many of the oldest open items to show.
+const OLDEST: usize = 10;
+const DAY: f64 = 24.0 * 60.0 * 60.0;
+
+const QUADRANTS: [(Quadrant, &str, &str); 4] = [
+    (Quadrant::ImportantAndUrgent, "important-and-urgent", "Important and Urgent"),
+    (Quadrant::Important, "important", "Important"),
+    (Quadrant::Urgent, "urgent", "Urgent"),
+    (Quadrant::Other, "other", "Other"),
+];
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Stats {
+    /// How many items are open (not deleted) and done (deleted).
+    pub open: usize,
+    pub done: usize,
+    /// The last few weeks, oldest first.
+    pub weeks: Vec<Week>,
+    pub quadrants: Vec<QuadrantStats>,
+    /// The open items that were created longest ago, oldest first.
+    pub oldest: Vec<OldItem>,
+}
+
+/// How many items were created and done in the week starting on `start` (a
+/// Monday).
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Week {
+    pub start: String,
+    pub created: usize,
+    pub done: usize,
+}
+
+/// Averages over the open items in a quadrant, in days. They're zero if the
+/// quadrant's empty.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct QuadrantStats {
+    pub quadrant: &'static str,
+    pub name: &'static str,
+    pub open: usize,
+    pub average_age_days: f64,
+    pub average_days_in_quadrant: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct OldItem {
+    pub id: u32,
+    pub title: String,
+    pub quadrant: &'static str,
+    pub created: i64,
+    pub age_days: f64,
+}
+
+/// Seconds as days, to a tenth of a day.
+fn days(seconds: f64) -> f64 {
+    (seconds.max(0.0) / DAY * 10.0).round() / 10.0
+}
+
+fn quadrant_slug(quadrant: Quadrant) -> &'static str {
+    QUADRANTS
+        .iter()
+        .find(|(q, _, _)| *q == quadrant)
+        .map_or("other", |(_, slug, _)| slug)
+}
+
+pub fn stats(repo: &Repo, list: &str, now: DateTime<Local>) -> Result<Stats, AppError> {
+    let now_ts = now.timestamp();
+    let (open, done) = repo.open_and_done(list)?;
+
+    let activity = repo.weekly_activity(list)?;
+    let this_week = week_of(now_ts);
+    let weeks = (0..WEEKS)
+        .rev()
+        .map(|ago| {
+            let start = (this_week - Duration::weeks(ago)).format("%Y-%m-%d").to_string();
+            let (created, done) = activity.get(&start).copied().unwrap_or_default();
+            Week {
+                start,
+                created,
+                done,
+            }
+        })
+        .collect();
+
+    let ages = repo.quadrant_ages(list, now_ts)?;
+    let quadrants = QUADRANTS
+        .iter()
+        .map(|&(quadrant, slug, name)| {
+            let (open, age, in_quadrant) = ages
+                .iter()
+                .find(|(q, ..)| *q == quadrant)
+                .map_or((0, 0.0, 0.0), |&(_, open, age, in_quadrant)| (open, age, in_quadrant));
+            QuadrantStats {
+                quadrant: slug,
+                name,
+                open,
+                average_age_days: days(age),
+                average_days_in_quadrant: days(in_quadrant),
+            }
+        })
+        .collect();
+
+    let oldest = repo
+        .oldest_open(list, OLDEST)?
+        .into_iter()
+        .map(|i| OldItem {
+            id: i.id,
+            quadrant: quadrant_slug(i.quadrant()),
+            age_days: days((now_ts - i.created) as f64),
+            title: i.title,
+            created: i.created,
+        })
+        .collect();
+
+    Ok(Stats {
+        open,
+        done,
+        weeks,
+        quadrants,
+        oldest,
+    })
+}
+
+/// Simple bar charts, laid out here and drawn as SVG by the stats page.
+pub mod chart {
+    use super::{QuadrantStats, Week};
+    use chrono::NaiveDate;
+
+    pub const WIDTH: u32 = 600;
+    /// The height of the columns' area in the weekly chart, with room for
+    /// labels underneath.
+    pub const COLUMNS_HEIGHT: u32 = 160;
+    pub const ROW_HEIGHT: u32 = 28;
+    /// Room for the quadrants' names to the left of their bars.
+    pub const LABEL_WIDTH: u32 = 180;
+    /// Room for the values to the right of bars.
+    const VALUE_WIDTH: u32 = 70;
+
+    pub struct Rect {
+        pub x: u32,
+        pub y: u32,
+        pub width: u32,
+        pub height: u32,
+        /// A class for the CSS to colour it by.
+        pub class: &'static str,
+        pub title: String,
+    }
+
+    pub struct Label {
+        pub x: u32,
+        pub y: u32,
+        pub text: String,
+    }
+
+    fn scale(value: f64, max: f64, size: u32) -> u32 {
+        if max <= 0.0 {
+            0
+        } else {
+            (value / max * size as f64).round() as u32
+        }
+    }
+
+    /// A pair of columns for each week, for the items created and done.
+    pub fn week_columns(weeks: &[Week]) -> Vec<Rect> {
+        let max = weeks.iter().map(|w| w.created.max(w.done)).max().unwrap_or(0) as f64;
+        let slot = WIDTH / weeks.len().max(1) as u32;
+        let width = slot * 2 / 5;
+        let mut rects = Vec::new();
+        for (n, week) in weeks.iter().enumerate() {
+            let x = n as u32 * slot + slot / 10;
+            for (offset, class, count) in [(0, "created", week.created), (width, "done", week.done)] {
+                let height = scale(count as f64, max, COLUMNS_HEIGHT);
+                rects.push(Rect {
+                    x: x + offset,
+                    y: COLUMNS_HEIGHT - height,
+                    width,
+                    height,
+                    class,
+                    title: format!("Week of {}: {} {}", week.start, count, class),
+                });
+            }
+        }
+        rects
+    }
+
+    /// The weeks' dates under their columns, like "Oct 31".
+    pub fn week_labels(weeks: &[Week]) -> Vec<Label> {
+        let slot = WIDTH / weeks.len().max(1) as u32;
+        weeks
+            .iter()
+            .enumerate()
+            .map(|(n, week)| Label {
+                x: n as u32 * slot + slot / 2,
+                y: COLUMNS_HEIGHT + 16,
+                text: NaiveDate::parse_from_str(&week.start, "%Y-%m-%d")
+                    .map(|d| d.format("%b %-d").to_string())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// A bar for each quadrant, for the average age or (with `in_quadrant`)
+    /// the average time in the quadrant, with its value to the right.
+    pub fn quadrant_bars(quadrants: &[QuadrantStats], in_quadrant: bool) -> Vec<(Rect, Label)> {
+        let value = |q: &QuadrantStats| {
+            if in_quadrant {
+                q.average_days_in_quadrant
+            } else {
+                q.average_age_days
+            }
+        };
+        let max = quadrants.iter().map(value).fold(0.0, f64::max);
+        quadrants
+            .iter()
+            .enumerate()
+            .map(|(n, q)| {
+                let width = scale(value(q), max, WIDTH - LABEL_WIDTH - VALUE_WIDTH);
+                let y = n as u32 * ROW_HEIGHT;
+                let rect = Rect {
+                    x: LABEL_WIDTH,
+                    y: y + 4,
+                    width,
+                    height: ROW_HEIGHT - 8,
+                    class: q.quadrant,
+                    title: format!("{}: {} open", q.name, q.open),
+                };
+                let label = Label {
+                    x: LABEL_WIDTH + width + 6,
+                    y: y + ROW_HEIGHT / 2 + 5,
+                    text: format!("{} days", value(q)),
+                };
+                (rect, label)
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_stats() {
+    use crate::repo::{Item, DEFAULT_LIST};
+    use chrono::TimeZone;
+
+    let now = Local.ymd(2022, 11, 2).and_hms(12, 0, 0);
+    let day = 24 * 60 * 60;
+    let ts = now.timestamp();
+    let mut repo = Repo::new(rusqlite::Connection::open_in_memory().unwrap());
+    repo.init().unwrap();
+    let item = |id: u32, important: bool, created: i64, modified: Option<i64>, deleted: Option<i64>| Item {
+        id,
+        list: String::from(DEFAULT_LIST),
+        title: format!("Item {}", id),
+        body: String::new(),
+        important,
+        urgent: false,
+        created,
+        modified,
+        deleted,
+        restored: None,
+        owner: None,
+        assignees: Vec::new(),
+        created_by: None,
+        modified_by: None,
+        deleted_by: None,
+        restored_by: None,
+    };
+    for item in [
+        item(1, true, ts - 20 * day, Some(ts - 5 * day), None),
+        item(2, true, ts - 10 * day, None, None),
+        item(3, false, ts - 3 * day, None, Some(ts - day)),
+        item(4, false, ts - day, None, None),
+        item(5, false, ts - 100 * day, None, Some(ts - 90 * day)),
+    ] {
+        repo.put(&item).unwrap();
+    }
+    let stats = stats(&repo, DEFAULT_LIST, now).unwrap();
+    assert_eq!((stats.open, stats.done), (3, 2));
+
+    assert_eq!(stats.weeks.len(), 12);
+    let this_week = &stats.weeks[11];
+    assert_eq!(this_week.start, "2022-10-31");
+    // Item 4 was created (Nov 1) and item 3 done (Nov 1) this week; item 3
+    // was created the week before (Oct 30)
+    assert_eq!((this_week.created, this_week.done), (1, 1));
+    assert_eq!((stats.weeks[10].created, stats.weeks[10].done), (1, 0));
+    // Item 5 is too long ago
+    let created: usize = stats.weeks.iter().map(|w| w.created).sum();
+    assert_eq!(created, 4);
+
+    let important = &stats.quadrants[1];
+    assert_eq!((important.quadrant, important.open), ("important", 2));
+    assert_eq!(important.average_age_days, 15.0);
+    assert_eq!(important.average_days_in_quadrant, 7.5);
+    assert_eq!(stats.quadrants[0].open, 0);
+    assert_eq!(stats.quadrants[0].average_age_days, 0.0);
+
+    let oldest: Vec<u32> = stats.oldest.iter().map(|i| i.id).collect();
+    assert_eq!(oldest, [1, 2, 4]);
+    assert_eq!(stats.oldest[0].age_days, 20.0);
+
+    let columns = chart::week_columns(&stats.weeks);
+    assert_eq!(columns.len(), 24);
+    assert_eq!(columns[22].height, chart::COLUMNS_HEIGHT);
+    assert_eq!(columns[0].height, 0);
+    let bars = chart::quadrant_bars(&stats.quadrants, false);
+    assert_eq!(bars[0].0.width, 0);
+    assert_eq!(bars[1].1.text, "15 days");
+}
@@ -1,4 +1,4 @@
-use crate::repo;
+use crate::{backup, capture, feed, importers, lists, markdown, repo, stats, view_options};
 use crate::{AppError, StatusCode};
 use askama::Template;
 use chrono::{DateTime, TimeZone, Utc, Local};
@@ -12,12 +12,16 @@ struct ViewItem {
     pub created: DateTime<Local>,
     pub modified: Option<DateTime<Local>>,
     pub deleted: Option<DateTime<Local>>,
+    pub owner: Option<String>,
+    pub assignees: Vec<String>,
+    pub created_by: Option<String>,
+    pub modified_by: Option<String>,
+    pub deleted_by: Option<String>,
 }
 
-impl<'a> TryFrom<&'a repo::Item> for ViewItem {
-    type Error = AppError;
-
-    fn try_from(item: &'a repo::Item) -> Result<ViewItem, Self::Error> {
+impl ViewItem {
+    /// The item to show, with its users' names looked up in `users`.
+    fn new(item: &repo::Item, users: &[repo::User]) -> Result<ViewItem, AppError> {
         fn parse_ts(ts: i64) -> Result<DateTime<Local>, AppError> {
             Utc.timestamp_opt(ts, 0).single().ok_or((
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -27,6 +31,10 @@ impl<'a> TryFrom<&'a repo::Item> for ViewItem {
         let created = parse_ts(item.created)?;
         let modified = item.modified.map(parse_ts).transpose()?;
         let deleted = item.deleted.map(parse_ts).transpose()?;
+        let name = |id: &u32| match users.iter().find(|u| u.id == *id) {
+            Some(user) => user.name.clone(),
+            None => format!("user #{}", id),
+        };
         Ok(ViewItem {
             id: item.id,
             title: item.title.clone(),
@@ -36,6 +44,11 @@ impl<'a> TryFrom<&'a repo::Item> for ViewItem {
             created,
             modified,
             deleted,
+            owner: item.owner.as_ref().map(name),
+            assignees: item.assignees.iter().map(name).collect(),
+            created_by: item.created_by.as_ref().map(name),
+            modified_by: item.modified_by.as_ref().map(name),
+            deleted_by: item.deleted_by.as_ref().map(name),
         })
     }
 }
@@ -43,35 +56,140 @@ impl<'a> TryFrom<&'a repo::Item> for ViewItem {
 #[derive(Template)]
 #[template(path = "items-list.html")]
 pub struct ItemsList<'a> {
-    important_and_urgent: Vec<&'a repo::Item>,
-    important: Vec<&'a repo::Item>,
-    urgent: Vec<&'a repo::Item>,
-    other: Vec<&'a repo::Item>,
+    /// Headings and their items, in the order they're shown.
+    groups: Vec<(String, Vec<&'a repo::Item>)>,
+    views: Vec<ItemsView>,
+    options: view_options::ViewOptions,
+    /// The user whose items are shown, as in `?who=`.
+    who: Option<String>,
+    /// A link to the next page of items, if there are more.
+    next_page: Option<String>,
+    list: lists::List,
+    csrf: String,
+}
+
+/// A link to switch between everyone's items and one user's, with the hotkey
+/// that follows it.
+pub struct ItemsView {
+    pub label: String,
+    pub href: String,
+    pub hotkey: String,
+    pub active: bool,
 }
 
 impl<'a> ItemsList<'a> {
-    pub fn from_items(items: &'a Vec<repo::Item>) -> Result<ItemsList<'a>, AppError> {
-        let mut items_list = ItemsList {
-            important_and_urgent: Vec::new(),
-            important: Vec::new(),
-            urgent: Vec::new(),
-            other: Vec::new(),
-        };
-        if items.iter().any(|i| i.deleted.is_some()) {
+    /// Items grouped into the four quadrants.
+    pub fn from_items(items: &'a [repo::Item]) -> Result<ItemsList<'a>, AppError> {
+        let items: Vec<&repo::Item> = items.iter().collect();
+        ItemsList::from_groups(view_options::ViewOptions::default().group(&items))
+    }
+
+    pub fn from_groups(groups: Vec<(String, Vec<&'a repo::Item>)>) -> Result<ItemsList<'a>, AppError> {
+        if groups.iter().flat_map(|(_, items)| items).any(|i| i.deleted.is_some()) {
             return Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Deleted item ended up in list of items".to_owned(),
             ));
         }
-        for item in items {
-            match (item.important, item.urgent) {
-                (true, true) => items_list.important_and_urgent.push(item),
-                (true, false) => items_list.important.push(item),
-                (false, true) => items_list.urgent.push(item),
-                (false, false) => items_list.other.push(item),
-            }
+        Ok(ItemsList {
+            groups,
+            views: Vec::new(),
+            options: view_options::ViewOptions::default(),
+            who: None,
+            next_page: None,
+            list: lists::List::default(),
+            csrf: String::new(),
+        })
+    }
+
+    pub fn with_views(self, views: Vec<ItemsView>) -> Self {
+        ItemsList { views, ..self }
+    }
+
+    /// Show the form for changing the view options.
+    pub fn with_options(self, options: view_options::ViewOptions, who: Option<String>) -> Self {
+        ItemsList {
+            options,
+            who,
+            ..self
         }
-        Ok(items_list)
+    }
+
+    pub fn with_next_page(self, next_page: Option<String>) -> Self {
+        ItemsList { next_page, ..self }
+    }
+
+    pub fn with_list(self, list: lists::List, csrf: String) -> Self {
+        ItemsList { list, csrf, ..self }
+    }
+
+    /// The groups in the order they're shown, with their headings.
+    pub fn groups(&self) -> &[(String, Vec<&'a repo::Item>)] {
+        &self.groups
+    }
+
+    /// The value of a view option, for the form's fields.
+    fn option(&self, name: &str) -> String {
+        let params = self.options.to_params();
+        let value = match name {
+            "flag" => params.flag,
+            "tag" => params.tag,
+            "age" => params.age,
+            "text" => params.text,
+            "q" => params.q,
+            "sort" => Some(self.options.sort.to_string()),
+            "group" => Some(self.options.group.to_string()),
+            _ => None,
+        };
+        value.unwrap_or_default()
+    }
+
+    fn is_chosen(&self, name: &str, value: &str) -> bool {
+        self.option(name) == value
+    }
+
+    /// The values and labels of a view option's drop-down.
+    fn choices(&self, name: &str) -> &'static [(&'static str, &'static str)] {
+        match name {
+            "flag" => &[
+                ("", "Any flags"),
+                ("important", "Important"),
+                ("urgent", "Urgent"),
+                ("unflagged", "Unflagged"),
+            ],
+            "sort" => &[
+                ("modified", "Modified"),
+                ("created", "Created"),
+                ("title", "Title"),
+                ("due", "Due date"),
+                ("manual", "Manual"),
+            ],
+            "group" => &[
+                ("quadrant", "Quadrant"),
+                ("tag", "Tag"),
+                ("created-week", "Week created"),
+                ("none", "None"),
+            ],
+            _ => &[],
+        }
+    }
+
+    fn has_options(&self) -> bool {
+        self.options != view_options::ViewOptions::default()
+    }
+
+    fn is_manual(&self) -> bool {
+        self.options.sort == view_options::Sort::Manual
+    }
+
+    /// The saved view for the current options, if there is one.
+    fn saved_view(&self) -> Option<&repo::SavedView> {
+        let query = self.options.query();
+        self.list
+            .saved_views
+            .iter()
+            .map(|(view, _)| view)
+            .find(|view| view.query == query)
     }
 }
 
@@ -79,42 +197,68 @@ impl<'a> ItemsList<'a> {
 #[template(path = "deleted-items-list.html")]
 pub struct DeletedItems {
     items: Vec<repo::Item>,
+    /// A link to the next page of items, if there are more.
+    next_page: Option<String>,
+    list: lists::List,
 }
 
-impl TryFrom<Vec<repo::Item>> for DeletedItems {
-    type Error = AppError;
-
-    fn try_from(mut items: Vec<repo::Item>) -> Result<Self, Self::Error> {
+impl DeletedItems {
+    pub fn new(mut items: Vec<repo::Item>, list: lists::List) -> Result<Self, AppError> {
         if items.iter().any(|i| i.deleted.is_none()) {
             Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Active item ended up in deleted list".to_owned(),
             ))
         } else {
-            items.sort_by_key(|i| i.deleted);
-            items.reverse();
-            Ok(DeletedItems { items })
+            items.sort_by_key(|i| std::cmp::Reverse(i.deleted));
+            Ok(DeletedItems {
+                items,
+                next_page: None,
+                list,
+            })
         }
     }
+
+    pub fn with_next_page(self, next_page: Option<String>) -> Self {
+        DeletedItems { next_page, ..self }
+    }
 }
 
 #[derive(Template)]
 #[template(path = "item.html")]
 pub struct Item {
     item: ViewItem,
+    links: markdown::Links,
+    backlinks: Vec<repo::Item>,
+    attachments: Vec<repo::Attachment>,
+    list: lists::List,
+    csrf: String,
 }
 
-impl TryFrom<repo::Item> for Item {
-    type Error = AppError;
-
-    fn try_from(item: repo::Item) -> Result<Self, Self::Error> {
+impl Item {
+    pub fn new(
+        item: repo::Item,
+        links: markdown::Links,
+        backlinks: Vec<repo::Item>,
+        attachments: Vec<repo::Attachment>,
+        users: &[repo::User],
+        list: lists::List,
+        csrf: String,
+    ) -> Result<Self, AppError> {
         if item.deleted.is_some() {
             Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Tried to render a deleted item.".to_owned(),
             ))
         } else {
-            ViewItem::try_from(&item).map(|item| Item { item })
+            ViewItem::new(&item, users).map(|item| Item {
+                item,
+                links,
+                backlinks,
+                attachments,
+                list,
+                csrf,
+            })
         }
     }
 }
@@ -123,20 +267,59 @@ impl TryFrom<repo::Item> for Item {
 #[template(path = "edit-item.html")]
 pub struct EditItem {
     item: ViewItem,
+    attachments: Vec<repo::Attachment>,
+    users: Vec<repo::User>,
+    /// When the user's unsaved draft was saved, if they have one.
+    draft_saved: Option<String>,
+    list: lists::List,
+    csrf: String,
 }
 
-impl TryFrom<repo::Item> for EditItem {
-    type Error = AppError;
-
-    fn try_from(item: repo::Item) -> Result<Self, Self::Error> {
+impl EditItem {
+    pub fn new(
+        item: repo::Item,
+        attachments: Vec<repo::Attachment>,
+        users: Vec<repo::User>,
+        list: lists::List,
+        csrf: String,
+    ) -> Result<Self, AppError> {
         if item.deleted.is_some() {
             Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Tried to edit a deleted item".to_owned(),
             ))
         } else {
-            ViewItem::try_from(&item).map(|item| EditItem { item })
+            ViewItem::new(&item, &users).map(|item| EditItem {
+                item,
+                attachments,
+                users,
+                draft_saved: None,
+                list,
+                csrf,
+            })
+        }
+    }
+
+    /// Offer to restore an unsaved draft, or with `restore`, fill the form in
+    /// with it.
+    pub fn with_draft(mut self, draft: Option<repo::Draft>, restore: bool) -> Self {
+        match draft {
+            Some(draft) if restore => {
+                self.item.title = draft.title;
+                self.item.body = draft.body;
+            }
+            Some(draft) => self.draft_saved = Some(local_time(draft.saved)),
+            None => {}
         }
+        self
+    }
+
+    fn draft_url(&self) -> String {
+        format!("{}/item/{}/draft", self.list.base(), self.item.id)
+    }
+
+    fn owns(&self, user: &repo::User) -> bool {
+        self.item.owner.as_ref() == Some(&user.name)
     }
 }
 
@@ -147,43 +330,566 @@ pub struct NewItem<'a> {
     body: Option<&'a str>,
     important: Option<bool>,
     urgent: Option<bool>,
+    draft_saved: Option<String>,
+    /// Templates to pick from, and the one the form was filled in from.
+    templates: Vec<repo::ItemTemplate>,
+    template: Option<u32>,
+    list: lists::List,
+    csrf: String,
+}
+
+impl<'a> NewItem<'a> {
+    pub fn new(list: lists::List, csrf: String) -> Self {
+        NewItem {
+            list,
+            csrf,
+            ..Default::default()
+        }
+    }
+
+    /// Like `EditItem::with_draft`.
+    pub fn with_draft(mut self, draft: Option<&'a repo::Draft>, restore: bool) -> Self {
+        match draft {
+            Some(draft) if restore => {
+                self.title = Some(&draft.title);
+                self.body = Some(&draft.body);
+            }
+            Some(draft) => self.draft_saved = Some(local_time(draft.saved)),
+            None => {}
+        }
+        self
+    }
+
+    /// Fill the form in from a quick capture, for the user to confirm.
+    pub fn with_capture(mut self, capture: &'a capture::Capture) -> Self {
+        self.title = Some(&capture.title);
+        self.body = Some(&capture.body);
+        self
+    }
+
+    pub fn with_templates(mut self, templates: Vec<repo::ItemTemplate>) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    /// Fill the form in from a template, with `body` being its body with its
+    /// tags (see `item_templates::body_with_tags`).
+    pub fn with_template(mut self, template: &'a repo::ItemTemplate, body: &'a str) -> Self {
+        self.title = Some(&template.title);
+        self.body = Some(body);
+        self.important = Some(template.important);
+        self.urgent = Some(template.urgent);
+        self.template = Some(template.id);
+        self
+    }
+
+    fn draft_url(&self) -> String {
+        format!("{}/item/new/draft", self.list.base())
+    }
+
+    fn is_picked(&self, template: &repo::ItemTemplate) -> bool {
+        self.template == Some(template.id)
+    }
+}
+
+/// A rendered body on its own, rendered exactly as on its item's page.
+#[derive(Template)]
+#[template(path = "preview.html")]
+pub struct Preview {
+    body: String,
+    links: markdown::Links,
+}
+
+impl Preview {
+    pub fn new(body: String, links: markdown::Links) -> Self {
+        Preview { body, links }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "bookmarklet.html")]
+pub struct Bookmarklet {
+    link: String,
+    list: lists::List,
+}
+
+impl Bookmarklet {
+    pub fn new(link: String, list: lists::List) -> Self {
+        Bookmarklet { link, list }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "import.html")]
+pub struct Import {
+    list: lists::List,
+    csrf: String,
+}
+
+impl Import {
+    pub fn new(list: lists::List, csrf: String) -> Self {
+        Import { list, csrf }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "import-preview.html")]
+pub struct ImportPreview {
+    format: String,
+    source: String,
+    items: Vec<importers::ImportedItem>,
+    list: lists::List,
+    csrf: String,
+}
+
+impl ImportPreview {
+    pub fn new(
+        format: String,
+        source: String,
+        items: Vec<importers::ImportedItem>,
+        list: lists::List,
+        csrf: String,
+    ) -> Self {
+        ImportPreview {
+            format,
+            source,
+            items,
+            list,
+            csrf,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "backups.html")]
+pub struct Backups {
+    dir: String,
+    snapshots: Vec<backup::Snapshot>,
+    list: lists::List,
+    csrf: String,
+}
+
+impl Backups {
+    pub fn new(
+        dir: String,
+        snapshots: Vec<backup::Snapshot>,
+        list: lists::List,
+        csrf: String,
+    ) -> Self {
+        Backups {
+            dir,
+            snapshots,
+            list,
+            csrf,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "feed.xml")]
+pub struct Feed {
+    title: String,
+    /// Where the server can be reached, like `https://example.com`.
+    base_url: String,
+    /// The server's host name, for entries' ids, which don't change with
+    /// the scheme.
+    host: String,
+    /// Where the list is, like `https://example.com/l/work`.
+    list_url: String,
+    path: String,
+    entries: Vec<FeedEntry>,
+}
+
+pub struct FeedEntry {
+    event: feed::Event,
+    links: markdown::Links,
+}
+
+impl Feed {
+    pub fn new(title: String, base_url: String, list_base: String, path: String) -> Self {
+        let host = base_url.split("://").last().unwrap_or_default();
+        let host = host.split('/').next().unwrap_or_default().to_owned();
+        Feed {
+            title,
+            list_url: format!("{}{}", base_url, list_base),
+            base_url,
+            host,
+            path,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, event: feed::Event, links: markdown::Links) {
+        self.entries.push(FeedEntry { event, links });
+    }
+
+    fn updated(&self) -> String {
+        let latest = self.entries.iter().map(|e| e.event.at).max().unwrap_or(0);
+        rfc3339(latest)
+    }
+}
+
+impl FeedEntry {
+    fn at(&self) -> String {
+        rfc3339(self.event.at)
+    }
+}
+
+fn local_time(ts: i64) -> String {
+    Local
+        .timestamp_opt(ts, 0)
+        .single()
+        .map(|dt| dt.to_rfc2822())
+        .unwrap_or_default()
+}
+
+fn rfc3339(ts: i64) -> String {
+    Utc.timestamp_opt(ts, 0)
+        .single()
+        .map(|dt| dt.to_rfc3339())
+        .unwrap_or_default()
 }
 
 #[derive(Template)]
 #[template(path = "deleted-item.html")]
 pub struct DeletedItem {
     item: ViewItem,
+    links: markdown::Links,
+    attachments: Vec<repo::Attachment>,
+    list: lists::List,
+    csrf: String,
 }
 
-impl TryFrom<repo::Item> for DeletedItem {
-    type Error = AppError;
-
-    fn try_from(item: repo::Item) -> Result<Self, Self::Error> {
+impl DeletedItem {
+    pub fn new(
+        item: repo::Item,
+        links: markdown::Links,
+        attachments: Vec<repo::Attachment>,
+        users: &[repo::User],
+        list: lists::List,
+        csrf: String,
+    ) -> Result<Self, AppError> {
         if item.deleted.is_none() {
             Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Tried to edit a deleted item".to_owned(),
             ))
         } else {
-            ViewItem::try_from(&item).map(|item| DeletedItem { item })
+            ViewItem::new(&item, users).map(|item| DeletedItem {
+                item,
+                links,
+                attachments,
+                list,
+                csrf,
+            })
         }
     }
 }
 
+#[derive(Template)]
+#[template(path = "api-tokens.html")]
+pub struct ApiTokens {
+    tokens: Vec<repo::ApiToken>,
+    created: Option<String>,
+    list: lists::List,
+    csrf: String,
+}
+
+impl ApiTokens {
+    pub fn new(
+        tokens: Vec<repo::ApiToken>,
+        created: Option<String>,
+        list: lists::List,
+        csrf: String,
+    ) -> Self {
+        ApiTokens {
+            tokens,
+            created,
+            list,
+            csrf,
+        }
+    }
+
+    fn local_time(&self, ts: &i64) -> String {
+        local_time(*ts)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+pub struct Login {
+    next: String,
+    error: Option<String>,
+    list: lists::List,
+}
+
+impl Login {
+    pub fn new(next: String, error: Option<String>) -> Self {
+        Login {
+            next,
+            error,
+            list: lists::List::default(),
+        }
+    }
+}
+
+/// The page for creating and deleting lists.
+#[derive(Template)]
+#[template(path = "lists.html")]
+pub struct Lists {
+    counts: Vec<(String, usize)>,
+    list: lists::List,
+    csrf: String,
+}
+
+impl Lists {
+    pub fn new(counts: Vec<(String, usize)>, list: lists::List, csrf: String) -> Self {
+        Lists { counts, list, csrf }
+    }
+
+    /// Only empty lists can be deleted, and never the default list.
+    fn can_delete(&self, name: &str, count: &usize) -> bool {
+        *count == 0 && name != repo::DEFAULT_LIST
+    }
+}
+
+/// The page for managing item templates.
+#[derive(Template)]
+#[template(path = "item-templates.html")]
+pub struct ItemTemplates {
+    templates: Vec<repo::ItemTemplate>,
+    list: lists::List,
+    csrf: String,
+}
+
+impl ItemTemplates {
+    pub fn new(templates: Vec<repo::ItemTemplate>, list: lists::List, csrf: String) -> Self {
+        ItemTemplates {
+            templates,
+            list,
+            csrf,
+        }
+    }
+}
+
+/// The form for a new template (with an id of 0) or an existing one.
+#[derive(Template)]
+#[template(path = "edit-item-template.html")]
+pub struct EditItemTemplate {
+    template: repo::ItemTemplate,
+    list: lists::List,
+    csrf: String,
+}
+
+impl EditItemTemplate {
+    pub fn new(template: repo::ItemTemplate, list: lists::List, csrf: String) -> Self {
+        EditItemTemplate {
+            template,
+            list,
+            csrf,
+        }
+    }
+
+    fn action(&self) -> String {
+        match self.template.id {
+            0 => String::from("/templates"),
+            id => format!("/templates/{}", id),
+        }
+    }
+
+    fn tags(&self) -> String {
+        self.template.tags.join(" ")
+    }
+}
+
+/// The statistics page, with charts drawn as SVG.
+#[derive(Template)]
+#[template(path = "stats.html")]
+pub struct Stats {
+    stats: stats::Stats,
+    list: lists::List,
+}
+
+impl Stats {
+    pub fn new(stats: stats::Stats, list: lists::List) -> Self {
+        Stats { stats, list }
+    }
+
+    fn chart_width(&self) -> u32 {
+        stats::chart::WIDTH
+    }
+
+    fn week_chart_height(&self) -> u32 {
+        stats::chart::COLUMNS_HEIGHT + 24
+    }
+
+    fn quadrant_chart_height(&self) -> u32 {
+        stats::chart::ROW_HEIGHT * self.stats.quadrants.len() as u32
+    }
+
+    fn week_columns(&self) -> Vec<stats::chart::Rect> {
+        stats::chart::week_columns(&self.stats.weeks)
+    }
+
+    fn week_labels(&self) -> Vec<stats::chart::Label> {
+        stats::chart::week_labels(&self.stats.weeks)
+    }
+
+    fn quadrant_bars(&self, in_quadrant: bool) -> Vec<(stats::chart::Rect, stats::chart::Label)> {
+        stats::chart::quadrant_bars(&self.stats.quadrants, in_quadrant)
+    }
+
+    /// Where the quadrants' names go, to the left of their bars.
+    fn quadrant_labels(&self) -> Vec<stats::chart::Label> {
+        self.stats
+            .quadrants
+            .iter()
+            .enumerate()
+            .map(|(n, q)| stats::chart::Label {
+                x: stats::chart::LABEL_WIDTH - 8,
+                y: n as u32 * stats::chart::ROW_HEIGHT + stats::chart::ROW_HEIGHT / 2 + 5,
+                text: q.name.to_owned(),
+            })
+            .collect()
+    }
+}
+
 mod filters {
     //! Additional Askama filters.
+    //!
+    //! Rendered Markdown is sanitized (see `render::sanitize`), so it's safe
+    //! to output with `|safe`.
+
+    use crate::{markdown, render};
 
-    pub fn md(src: &str) -> askama::Result<String> {
-        use pulldown_cmark::{html, Options, Parser};
+    pub fn md(src: &str, links: &markdown::Links) -> askama::Result<String> {
+        Ok(render::to_html(src, links, false))
+    }
+
+    /// Like `md`, but task-list checkboxes are enabled and carry the source
+    /// offset of their marker so they can be toggled.
+    pub fn md_tasks(src: &str, links: &markdown::Links) -> askama::Result<String> {
+        Ok(render::to_html(src, links, true))
+    }
+
+    /// Human-readable file size, e.g. "12.3 KB".
+    pub fn filesize(bytes: &i64) -> askama::Result<String> {
+        let bytes = *bytes as f64;
+        Ok(match bytes {
+            b if b < 1024.0 => format!("{} B", b),
+            b if b < 1024.0 * 1024.0 => format!("{:.1} KB", b / 1024.0),
+            b => format!("{:.1} MB", b / (1024.0 * 1024.0)),
+        })
+    }
 
-        let mut options = Options::empty();
-        options.insert(Options::ENABLE_TABLES);
-        options.insert(Options::ENABLE_TASKLISTS);
-        options.insert(Options::ENABLE_SMART_PUNCTUATION);
+    /// Summary of a body's task list, e.g. "2/5", or nothing if it has none.
+    pub fn tasks(src: &str) -> askama::Result<String> {
+        match markdown::task_progress(src) {
+            (_, 0) => Ok(String::new()),
+            (done, total) => Ok(format!("{}/{}", done, total)),
+        }
+    }
+}
 
-        let parser = Parser::new_ext(src, options);
-        let mut output = String::new();
-        html::push_html(&mut output, parser);
-        Ok(output)
+#[test]
+fn test_xss_payloads() {
+    let payloads = [
+        "<script>alert('xss')</script>",
+        "<img src=x onerror=\"alert('xss')\">",
+        "[click](javascript:alert('xss'))",
+        "<a href=\"javascript:alert('xss')\">click</a>",
+        "<iframe src=\"javascript:alert('xss')\"></iframe>",
+        "<svg><script>alert('xss')</script></svg>",
+        "<div onmouseover=\"alert('xss')\">hover</div>",
+        "<object data=\"data:text/html,<script>alert('xss')</script>\"></object>",
+        "<form action=\"https://evil.example\"><button>Go</button></form>",
+        "- [ ] <input type=\"text\" autofocus onfocus=\"alert('xss')\">",
+    ];
+    let item = |body: &str, deleted: Option<i64>| repo::Item {
+        id: 1,
+        list: String::from(repo::DEFAULT_LIST),
+        title: String::from("<script>alert('title')</script>"),
+        body: body.to_owned(),
+        important: false,
+        urgent: false,
+        created: 0,
+        modified: None,
+        deleted,
+        restored: None,
+        owner: None,
+        assignees: Vec::new(),
+        created_by: None,
+        modified_by: None,
+        deleted_by: None,
+        restored_by: None,
+    };
+    let check = |html: String| {
+        // Pages have their own scripts, so look in the item's body and title
+        let start = html.find("<h1").unwrap();
+        let end = html.rfind("<script src=").unwrap();
+        let shown = html[start..end].to_lowercase();
+        for bad in ["<script", "javascript:", "onerror", "onmouseover", "onfocus", "<iframe", "<object", "<form action=\"https"] {
+            assert!(!shown.contains(bad), "found {} in {}", bad, shown);
+        }
+    };
+    for body in payloads {
+        let page = Item::new(
+            item(body, None),
+            markdown::Links::default(),
+            Vec::new(),
+            Vec::new(),
+            &[],
+            lists::List::default(),
+            String::new(),
+        )
+        .unwrap();
+        check(page.to_string());
+        let page = DeletedItem::new(
+            item(body, Some(0)),
+            markdown::Links::default(),
+            Vec::new(),
+            &[],
+            lists::List::default(),
+            String::new(),
+        )
+        .unwrap();
+        check(page.to_string());
     }
 }
+
+#[test]
+fn test_preview_matches_item() {
+    let body = "# Plan\n\n- [ ] Book *flights*\n- [x] Ask #12\n\n| a | b |\n|---|---|\n| 1 | 2 |\n";
+    let item = repo::Item {
+        id: 1,
+        list: String::from(repo::DEFAULT_LIST),
+        title: String::from("Trip"),
+        body: body.to_owned(),
+        important: false,
+        urgent: false,
+        created: 0,
+        modified: None,
+        deleted: None,
+        restored: None,
+        owner: None,
+        assignees: Vec::new(),
+        created_by: None,
+        modified_by: None,
+        deleted_by: None,
+        restored_by: None,
+    };
+    let page = Item::new(
+        item,
+        markdown::Links::default(),
+        Vec::new(),
+        Vec::new(),
+        &[],
+        lists::List::default(),
+        String::new(),
+    )
+    .unwrap()
+    .to_string();
+    let preview = Preview::new(body.to_owned(), markdown::Links::default()).to_string();
+    assert!(preview.contains("data-offset"));
+    assert!(page.contains(preview.trim_end()));
+}
@@ -0,0 +1,484 @@
+//! Import and export of items as JSON, CSV, or Markdown files with a
+//! front-matter header.
+//!
+//! JSON and CSV exports hold every item in one document. Markdown exports are
+//! one file per item; over HTTP these are bundled into a tar archive.
+
+use crate::repo::{Item, Repo, DEFAULT_LIST};
+use crate::{AppError, StatusCode};
+use chrono::{DateTime, TimeZone, Utc};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+    Markdown,
+}
+
+impl std::str::FromStr for Format {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "markdown" | "md" => Ok(Format::Markdown),
+            _ => Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown format '{}', expected json, csv or markdown", s),
+            )),
+        }
+    }
+}
+
+/// How imported items are added to the repo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Replace items with the same id, or add them with that id.
+    Merge,
+    /// Add every item with a new id.
+    Append,
+}
+
+pub fn to_json(items: &[Item]) -> Result<String, AppError> {
+    serde_json::to_string_pretty(items).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("JSON export failed: {:?}", e),
+        )
+    })
+}
+
+pub fn from_json(src: &str) -> Result<Vec<Item>, AppError> {
+    serde_json::from_str(src).map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)))
+}
+
+/// An item as a CSV row. CSV can't hold lists, so the assignees' ids are
+/// separated by spaces in one column.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct CsvItem {
+    id: u32,
+    #[serde(default)]
+    list: String,
+    title: String,
+    body: String,
+    important: bool,
+    urgent: bool,
+    created: i64,
+    modified: Option<i64>,
+    deleted: Option<i64>,
+    #[serde(default)]
+    restored: Option<i64>,
+    #[serde(default)]
+    owner: Option<u32>,
+    #[serde(default)]
+    assignees: String,
+    #[serde(default)]
+    created_by: Option<u32>,
+    #[serde(default)]
+    modified_by: Option<u32>,
+    #[serde(default)]
+    deleted_by: Option<u32>,
+    #[serde(default)]
+    restored_by: Option<u32>,
+}
+
+impl From<&Item> for CsvItem {
+    fn from(item: &Item) -> Self {
+        let assignees: Vec<String> = item.assignees.iter().map(u32::to_string).collect();
+        CsvItem {
+            id: item.id,
+            list: item.list.clone(),
+            title: item.title.clone(),
+            body: item.body.clone(),
+            important: item.important,
+            urgent: item.urgent,
+            created: item.created,
+            modified: item.modified,
+            deleted: item.deleted,
+            restored: item.restored,
+            owner: item.owner,
+            assignees: assignees.join(" "),
+            created_by: item.created_by,
+            modified_by: item.modified_by,
+            deleted_by: item.deleted_by,
+            restored_by: item.restored_by,
+        }
+    }
+}
+
+impl TryFrom<CsvItem> for Item {
+    type Error = AppError;
+
+    fn try_from(row: CsvItem) -> Result<Self, Self::Error> {
+        let assignees = row
+            .assignees
+            .split_whitespace()
+            .map(|id| {
+                id.parse().map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        format!("CSV error: bad assignee '{}' for item {}", id, row.id),
+                    )
+                })
+            })
+            .collect::<Result<_, AppError>>()?;
+        Ok(Item {
+            id: row.id,
+            list: match row.list.as_str() {
+                "" => String::from(DEFAULT_LIST),
+                _ => row.list,
+            },
+            title: row.title,
+            body: row.body,
+            important: row.important,
+            urgent: row.urgent,
+            created: row.created,
+            modified: row.modified,
+            deleted: row.deleted,
+            restored: row.restored,
+            owner: row.owner,
+            assignees,
+            created_by: row.created_by,
+            modified_by: row.modified_by,
+            deleted_by: row.deleted_by,
+            restored_by: row.restored_by,
+        })
+    }
+}
+
+pub fn to_csv(items: &[Item]) -> Result<String, AppError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for item in items {
+        writer.serialize(CsvItem::from(item)).map_err(csv_error)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("CSV export failed: {:?}", e),
+        )
+    })?;
+    String::from_utf8(bytes).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("CSV export failed: {:?}", e),
+        )
+    })
+}
+
+pub fn from_csv(src: &str) -> Result<Vec<Item>, AppError> {
+    csv::Reader::from_reader(src.as_bytes())
+        .deserialize::<CsvItem>()
+        .map(|row| row.map_err(csv_error).and_then(Item::try_from))
+        .collect()
+}
+
+/// A file name for the item's Markdown export, e.g. `42-buy-milk.md`.
+pub fn markdown_file_name(item: &Item) -> String {
+    let slug: String = item
+        .title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    let slug: String = slug.chars().take(50).collect();
+    format!("{}-{}.md", item.id, slug.trim_end_matches('-'))
+}
+
+pub fn to_markdown(item: &Item) -> String {
+    fn timestamp(ts: i64) -> String {
+        Utc.timestamp_opt(ts, 0)
+            .single()
+            .map(|dt| dt.to_rfc3339())
+            .unwrap_or_else(|| ts.to_string())
+    }
+    let mut doc = String::from("---\n");
+    doc.push_str(&format!("id: {}\n", item.id));
+    doc.push_str(&format!("list: {}\n", item.list));
+    // A JSON string is also a valid YAML string
+    doc.push_str(&format!(
+        "title: {}\n",
+        serde_json::Value::from(item.title.as_str())
+    ));
+    doc.push_str(&format!("important: {}\n", item.important));
+    doc.push_str(&format!("urgent: {}\n", item.urgent));
+    doc.push_str(&format!("created: {}\n", timestamp(item.created)));
+    if let Some(modified) = item.modified {
+        doc.push_str(&format!("modified: {}\n", timestamp(modified)));
+    }
+    if let Some(deleted) = item.deleted {
+        doc.push_str(&format!("deleted: {}\n", timestamp(deleted)));
+    }
+    if let Some(restored) = item.restored {
+        doc.push_str(&format!("restored: {}\n", timestamp(restored)));
+    }
+    // Users are referred to by id
+    if let Some(owner) = item.owner {
+        doc.push_str(&format!("owner: {}\n", owner));
+    }
+    if !item.assignees.is_empty() {
+        let ids: Vec<String> = item.assignees.iter().map(u32::to_string).collect();
+        doc.push_str(&format!("assignees: [{}]\n", ids.join(", ")));
+    }
+    let attribution = [
+        ("created_by", item.created_by),
+        ("modified_by", item.modified_by),
+        ("deleted_by", item.deleted_by),
+        ("restored_by", item.restored_by),
+    ];
+    for (key, user) in attribution {
+        if let Some(user) = user {
+            doc.push_str(&format!("{}: {}\n", key, user));
+        }
+    }
+    doc.push_str("---\n\n");
+    doc.push_str(&item.body);
+    doc
+}
+
+pub fn from_markdown(src: &str) -> Result<Item, AppError> {
+    fn invalid(msg: String) -> AppError {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid Markdown item: {}", msg),
+        )
+    }
+    fn timestamp(value: &str) -> Result<i64, AppError> {
+        value
+            .parse::<i64>()
+            .or_else(|_| DateTime::parse_from_rfc3339(value).map(|dt| dt.timestamp()))
+            .map_err(|_| invalid(format!("bad timestamp '{}'", value)))
+    }
+    fn flag(value: &str) -> Result<bool, AppError> {
+        value
+            .parse()
+            .map_err(|_| invalid(format!("expected true or false, got '{}'", value)))
+    }
+    fn user(value: &str) -> Result<u32, AppError> {
+        value
+            .parse()
+            .map_err(|_| invalid(format!("bad user id '{}'", value)))
+    }
+
+    let src = src.replace("\r\n", "\n");
+    let rest = src
+        .strip_prefix("---\n")
+        .ok_or_else(|| invalid("missing front-matter".to_owned()))?;
+    let (header, body) = rest
+        .split_once("\n---\n")
+        .ok_or_else(|| invalid("unterminated front-matter".to_owned()))?;
+    let mut item = Item {
+        id: 0,
+        list: String::from(DEFAULT_LIST),
+        title: String::new(),
+        body: body.strip_prefix('\n').unwrap_or(body).to_owned(),
+        important: false,
+        urgent: false,
+        created: Utc::now().timestamp(),
+        modified: None,
+        deleted: None,
+        restored: None,
+        owner: None,
+        assignees: Vec::new(),
+        created_by: None,
+        modified_by: None,
+        deleted_by: None,
+        restored_by: None,
+    };
+    for line in header.lines().filter(|l| !l.trim().is_empty()) {
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid(format!("expected 'key: value', got '{}'", line)))?;
+        let value = value.trim();
+        match key.trim() {
+            "id" => {
+                item.id = value
+                    .parse()
+                    .map_err(|_| invalid(format!("bad id '{}'", value)))?
+            }
+            "list" => item.list = value.to_owned(),
+            "title" if value.starts_with('"') => {
+                item.title = serde_json::from_str(value)
+                    .map_err(|_| invalid(format!("bad title {}", value)))?
+            }
+            "title" => item.title = value.to_owned(),
+            "important" => item.important = flag(value)?,
+            "urgent" => item.urgent = flag(value)?,
+            "created" => item.created = timestamp(value)?,
+            "modified" => item.modified = Some(timestamp(value)?),
+            "deleted" => item.deleted = Some(timestamp(value)?),
+            "restored" => item.restored = Some(timestamp(value)?),
+            "owner" => item.owner = Some(user(value)?),
+            "assignees" => {
+                item.assignees = value
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(user)
+                    .collect::<Result<_, _>>()?
+            }
+            "created_by" => item.created_by = Some(user(value)?),
+            "modified_by" => item.modified_by = Some(user(value)?),
+            "deleted_by" => item.deleted_by = Some(user(value)?),
+            "restored_by" => item.restored_by = Some(user(value)?),
+            _ => {} // Ignore fields we don't know about
+        }
+    }
+    Ok(item)
+}
+
+/// A tar archive of every item as a Markdown file.
+pub fn to_markdown_archive(items: &[Item]) -> Result<Vec<u8>, AppError> {
+    let mut archive = tar::Builder::new(Vec::new());
+    for item in items {
+        let contents = to_markdown(item);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(item.modified.unwrap_or(item.created).max(0) as u64);
+        header.set_cksum();
+        archive
+            .append_data(
+                &mut header,
+                format!("wannado/{}", markdown_file_name(item)),
+                contents.as_bytes(),
+            )
+            .map_err(io_error)?;
+    }
+    archive.into_inner().map_err(io_error)
+}
+
+pub fn write_markdown_dir(items: &[Item], dir: &Path) -> Result<(), AppError> {
+    std::fs::create_dir_all(dir).map_err(io_error)?;
+    for item in items {
+        std::fs::write(dir.join(markdown_file_name(item)), to_markdown(item)).map_err(io_error)?;
+    }
+    Ok(())
+}
+
+pub fn read_markdown_dir(dir: &Path) -> Result<Vec<Item>, AppError> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)
+        .map_err(io_error)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()
+        .map_err(io_error)?;
+    paths.retain(|p| p.extension().is_some_and(|ext| ext == "md"));
+    paths.sort();
+    paths
+        .iter()
+        .map(|p| {
+            let src = std::fs::read_to_string(p).map_err(io_error)?;
+            from_markdown(&src)
+                .map_err(|(status, msg)| (status, format!("{}: {}", p.display(), msg)))
+        })
+        .collect()
+}
+
+/// Add imported items to the repo, creating any lists they're in that don't
+/// exist yet. Returns the number of items imported.
+pub fn import(repo: &mut Repo, items: &[Item], mode: Mode) -> Result<usize, AppError> {
+    // All or nothing, so a bad item doesn't leave half an import behind
+    repo.in_transaction(|repo| {
+        for item in items {
+            if !repo.has_list(&item.list)? {
+                repo.add_list(&item.list)?;
+            }
+            match mode {
+                Mode::Merge if item.id != 0 => repo.put(item)?,
+                _ => {
+                    repo.append(item)?;
+                }
+            }
+        }
+        Ok(items.len())
+    })
+}
+
+fn csv_error(err: csv::Error) -> AppError {
+    (StatusCode::BAD_REQUEST, format!("CSV error: {}", err))
+}
+
+fn io_error(err: std::io::Error) -> AppError {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("File error: {:?}", err),
+    )
+}
+
+#[test]
+fn test_round_trips() -> Result<(), AppError> {
+    let items = vec![
+        Item {
+            id: 3,
+            list: String::from(DEFAULT_LIST),
+            title: String::from("Plan \"the\" trip: part 1"),
+            body: String::from("- [ ] book, flights\n- [ ] hotel\n\n---\n\nnotes"),
+            important: true,
+            urgent: false,
+            created: 1_666_000_000,
+            modified: Some(1_666_100_000),
+            deleted: None,
+            restored: None,
+            owner: None,
+            assignees: Vec::new(),
+            created_by: None,
+            modified_by: None,
+            deleted_by: None,
+            restored_by: None,
+        },
+        Item {
+            id: 7,
+            list: String::from("home"),
+            title: String::from("Gone"),
+            body: String::new(),
+            important: false,
+            urgent: true,
+            created: 1_666_000_000,
+            modified: None,
+            deleted: Some(1_666_200_000),
+            restored: Some(1_666_150_000),
+            owner: Some(1),
+            assignees: vec![2, 3],
+            created_by: Some(1),
+            modified_by: None,
+            deleted_by: None,
+            restored_by: None,
+        },
+    ];
+    fn same(a: &[Item], b: &[Item]) -> bool {
+        serde_json::to_value(a).unwrap() == serde_json::to_value(b).unwrap()
+    }
+
+    assert!(same(&items, &from_json(&to_json(&items)?)?));
+    assert!(same(&items, &from_csv(&to_csv(&items)?)?));
+    let from_md: Vec<Item> = items
+        .iter()
+        .map(|i| from_markdown(&to_markdown(i)))
+        .collect::<Result<_, _>>()?;
+    assert!(same(&items, &from_md));
+    assert_eq!(markdown_file_name(&items[0]), "3-plan-the-trip-part-1.md");
+
+    let cxn = rusqlite::Connection::open_in_memory().unwrap();
+    let mut repo = Repo::new(cxn);
+    repo.init().unwrap();
+    repo.add(DEFAULT_LIST, "Existing", "", false, false, None)?;
+    import(&mut repo, &items, Mode::Merge)?;
+    assert_eq!(repo.get(3)?.title, items[0].title);
+    assert!(repo.get_deleted(7).is_ok());
+    assert_eq!(repo.lists()?, vec!["home", "main"]);
+    import(&mut repo, &items, Mode::Append)?;
+    assert_eq!(repo.active_and_deleted()?.len(), 5);
+    assert_eq!(repo.get(1)?.title, "Existing");
+
+    // A bad item leaves nothing of the import behind
+    let mut bad = items.clone();
+    bad[1].list = String::from("Not a list");
+    assert!(import(&mut repo, &bad, Mode::Append).is_err());
+    assert_eq!(repo.active_and_deleted()?.len(), 5);
+
+    Ok(())
+}
@@ -0,0 +1,632 @@
+//! A terminal UI for using Wannado over SSH or anywhere else without a
+//! browser, started with `wannado tui`.
+//!
+//! It has the same pages and hotkeys as the web app: the four quadrants on the
+//! home page, item pages with their bodies rendered as styled Markdown, and
+//! deleted items. Items are edited as Markdown files with a front-matter
+//! header (like `wannado export markdown`) in `$VISUAL` or `$EDITOR`. Changes
+//! are written straight to the database through `Repo`. It shows one list at
+//! a time: the default list, or the one given with `--list`.
+
+use crate::repo::{Item, Repo};
+use crate::{cli, markdown, template, transfer};
+use crate::{AppError, StatusCode};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use pulldown_cmark::{Event as MdEvent, Parser, Tag};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{Frame, Terminal};
+use std::io::Stdout;
+
+type Term = Terminal<CrosstermBackend<Stdout>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Items,
+    Item(u32),
+    Deleted,
+    DeletedItem(u32),
+}
+
+struct App {
+    repo: Repo,
+    /// The list whose items are shown, and where new items go.
+    list: String,
+    view: View,
+    /// Index of the selected item in the list views.
+    selected: usize,
+    /// How far an item's page is scrolled.
+    scroll: u16,
+    message: Option<String>,
+}
+
+pub fn run(list: &str) -> Result<(), AppError> {
+    let repo = crate::open_repo();
+    if !repo.has_list(list)? {
+        return Err((StatusCode::NOT_FOUND, format!("No list called '{}'", list)));
+    }
+    let mut app = App {
+        repo,
+        list: list.to_owned(),
+        view: View::Items,
+        selected: 0,
+        scroll: 0,
+        message: None,
+    };
+    let mut terminal = start().map_err(io_error)?;
+    let result = app.run(&mut terminal);
+    stop(&mut terminal).map_err(io_error)?;
+    result
+}
+
+fn start() -> std::io::Result<Term> {
+    enable_raw_mode()?;
+    crossterm::execute!(std::io::stdout(), EnterAlternateScreen)?;
+    Terminal::new(CrosstermBackend::new(std::io::stdout()))
+}
+
+fn stop(terminal: &mut Term) -> std::io::Result<()> {
+    disable_raw_mode()?;
+    crossterm::execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut Term) -> Result<(), AppError> {
+        loop {
+            let items = self.list()?;
+            let item = match self.view {
+                View::Item(id) => self.repo.get(id).ok(),
+                View::DeletedItem(id) => self.repo.get_deleted(id).ok(),
+                _ => None,
+            };
+            self.selected = self.selected.min(items.len().saturating_sub(1));
+            terminal
+                .draw(|f| self.draw(f, &items, item.as_ref()))
+                .map_err(io_error)?;
+
+            let key = match event::read().map_err(io_error)? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            self.message = None;
+            let result = match (key.code, self.view) {
+                (KeyCode::Char('q'), _) => return Ok(()),
+                (KeyCode::Char('c'), _) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+                (KeyCode::Char('h'), _) | (KeyCode::Esc, View::Item(_)) => {
+                    self.go(View::Items);
+                    Ok(())
+                }
+                (KeyCode::Char('d'), _) | (KeyCode::Esc, View::DeletedItem(_)) => {
+                    self.go(View::Deleted);
+                    Ok(())
+                }
+                (KeyCode::Char('n'), _) => self.new_item(terminal),
+                (KeyCode::Char('j') | KeyCode::Down, View::Items | View::Deleted) => {
+                    self.selected = (self.selected + 1).min(items.len().saturating_sub(1));
+                    Ok(())
+                }
+                (KeyCode::Char('k') | KeyCode::Up, View::Items | View::Deleted) => {
+                    self.selected = self.selected.saturating_sub(1);
+                    Ok(())
+                }
+                (KeyCode::Enter, View::Items) => {
+                    if let Some(item) = items.get(self.selected) {
+                        self.go(View::Item(item.id));
+                    }
+                    Ok(())
+                }
+                (KeyCode::Enter, View::Deleted) => {
+                    if let Some(item) = items.get(self.selected) {
+                        self.go(View::DeletedItem(item.id));
+                    }
+                    Ok(())
+                }
+                (KeyCode::Char('e'), View::Items) => match items.get(self.selected) {
+                    Some(item) => self.edit(terminal, item.clone()),
+                    None => Ok(()),
+                },
+                (KeyCode::Char('j') | KeyCode::Down, View::Item(_) | View::DeletedItem(_)) => {
+                    self.scroll = self.scroll.saturating_add(1);
+                    Ok(())
+                }
+                (KeyCode::Char('k') | KeyCode::Up, View::Item(_) | View::DeletedItem(_)) => {
+                    self.scroll = self.scroll.saturating_sub(1);
+                    Ok(())
+                }
+                (KeyCode::Char('e'), View::Item(_)) => match item {
+                    Some(item) => self.edit(terminal, item),
+                    None => Ok(()),
+                },
+                (KeyCode::Char('x'), View::Item(id)) => self.repo.delete(&id, None).map(|_| {
+                    self.message = Some(format!("Deleted #{}", id));
+                    self.go(View::Items);
+                }),
+                (KeyCode::Char('r'), View::DeletedItem(id)) => self.repo.restore(&id, None).map(|_| {
+                    self.message = Some(format!("Restored #{}", id));
+                    self.go(View::Item(id));
+                }),
+                _ => Ok(()),
+            };
+            if let Err((_, msg)) = result {
+                self.message = Some(msg);
+            }
+        }
+    }
+
+    fn go(&mut self, view: View) {
+        self.view = view;
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    /// The items in the current list view, in the order they're shown.
+    fn list(&mut self) -> Result<Vec<Item>, AppError> {
+        match self.view {
+            View::Items | View::Item(_) => {
+                let mut items = self.repo.all_in(&self.list)?;
+                items.sort_by_key(|i| (i.modified, i.created));
+                let list = template::ItemsList::from_items(&items)?;
+                Ok(list
+                    .groups()
+                    .iter()
+                    .flat_map(|(_, items)| items.iter().map(|i| (*i).clone()))
+                    .collect())
+            }
+            View::Deleted | View::DeletedItem(_) => {
+                let mut items = self.repo.deleted_in(&self.list)?;
+                items.sort_by_key(|i| std::cmp::Reverse(i.deleted));
+                Ok(items)
+            }
+        }
+    }
+
+    fn edit(&mut self, terminal: &mut Term, mut item: Item) -> Result<(), AppError> {
+        let edited = edit_item(terminal, &item)?;
+        if (&edited.title, &edited.body, edited.important, edited.urgent)
+            == (&item.title, &item.body, item.important, item.urgent)
+        {
+            return Ok(());
+        }
+        item.title = edited.title;
+        item.body = edited.body;
+        item.important = edited.important;
+        item.urgent = edited.urgent;
+        self.repo.update(&mut item, None)?;
+        self.message = Some(format!("Saved #{}", item.id));
+        Ok(())
+    }
+
+    fn new_item(&mut self, terminal: &mut Term) -> Result<(), AppError> {
+        let blank = Item {
+            id: 0,
+            list: self.list.clone(),
+            title: String::new(),
+            body: String::new(),
+            important: false,
+            urgent: false,
+            created: chrono::Utc::now().timestamp(),
+            modified: None,
+            deleted: None,
+            restored: None,
+            owner: None,
+            assignees: Vec::new(),
+            created_by: None,
+            modified_by: None,
+            deleted_by: None,
+            restored_by: None,
+        };
+        let edited = edit_item(terminal, &blank)?;
+        if edited.title.trim().is_empty() {
+            self.message = Some(String::from("No title, so no item was created"));
+            return Ok(());
+        }
+        let item = self.repo.add(
+            &self.list,
+            &edited.title,
+            &edited.body,
+            edited.important,
+            edited.urgent,
+            None,
+        )?;
+        self.go(View::Item(item.id));
+        Ok(())
+    }
+
+    fn draw(&self, f: &mut Frame, items: &[Item], item: Option<&Item>) {
+        let [main, footer] = split(f.size(), Direction::Vertical, Constraint::Length(1));
+        match (self.view, item) {
+            (View::Items, _) => self.draw_quadrants(f, main, items),
+            (View::Deleted, _) => {
+                let list = List::new(items.iter().map(|i| ListItem::new(i.title.clone())))
+                    .block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title("Deleted Items"),
+                    )
+                    .highlight_style(highlight());
+                let mut state = ListState::default().with_selected(Some(self.selected));
+                f.render_stateful_widget(list, main, &mut state);
+            }
+            (_, Some(item)) => self.draw_item(f, main, item),
+            (_, None) => {}
+        }
+
+        let help = match self.view {
+            View::Items => "j/k select · Enter open · e edit · n new · d deleted · q quit",
+            View::Item(_) => "e edit · x delete · j/k scroll · h home · n new · q quit",
+            View::Deleted => "j/k select · Enter open · h home · n new · q quit",
+            View::DeletedItem(_) => "r restore · j/k scroll · d deleted · h home · q quit",
+        };
+        let footer_text = match &self.message {
+            Some(msg) => Line::from(Span::styled(
+                msg.clone(),
+                Style::default().fg(Color::Yellow),
+            )),
+            None => Line::from(Span::styled(help, Style::default().fg(Color::DarkGray))),
+        };
+        f.render_widget(Paragraph::new(footer_text), footer);
+    }
+
+    fn draw_quadrants(&self, f: &mut Frame, area: Rect, items: &[Item]) {
+        let halves = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(area);
+        let panes: Vec<Rect> = halves
+            .iter()
+            .flat_map(|half| {
+                Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                    .split(*half)
+                    .to_vec()
+            })
+            .collect();
+
+        let items = items.to_vec();
+        let list = match template::ItemsList::from_items(&items) {
+            Ok(list) => list,
+            Err(_) => return,
+        };
+        let mut offset = 0;
+        for ((heading, quadrant), pane) in list.groups().iter().zip(panes) {
+            let selected = (offset..offset + quadrant.len())
+                .contains(&self.selected)
+                .then(|| self.selected - offset);
+            offset += quadrant.len();
+            let style = match heading.as_str() {
+                "Important and Urgent" => Style::default().fg(Color::Red),
+                "Important" => Style::default().fg(Color::Magenta),
+                "Urgent" => Style::default().fg(Color::Yellow),
+                _ => Style::default(),
+            };
+            let entries = quadrant.iter().map(|i| {
+                let progress = match markdown::task_progress(&i.body) {
+                    (_, 0) => String::new(),
+                    (done, total) => format!(" [{}/{}]", done, total),
+                };
+                ListItem::new(format!("{}{}", i.title, progress))
+            });
+            let list = List::new(entries)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(Span::styled(heading.as_str(), style.add_modifier(Modifier::BOLD))),
+                )
+                .highlight_style(highlight());
+            let mut state = ListState::default().with_selected(selected);
+            f.render_stateful_widget(list, pane, &mut state);
+        }
+    }
+
+    fn draw_item(&self, f: &mut Frame, area: Rect, item: &Item) {
+        let mut flags = Vec::new();
+        if item.important {
+            flags.push(Span::styled(
+                "Important ",
+                Style::default().fg(Color::Magenta),
+            ));
+        }
+        if item.urgent {
+            flags.push(Span::styled("Urgent ", Style::default().fg(Color::Yellow)));
+        }
+        if item.deleted.is_some() {
+            flags.push(Span::styled(
+                "Deleted",
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        let mut text = Text::from(vec![Line::from(flags), Line::default()]);
+        text.extend(render_markdown(&item.body));
+        if let Ok(attachments) = self.repo.attachments(item.id) {
+            if !attachments.is_empty() {
+                text.extend([Line::default(), Line::from("Attachments:")]);
+                text.extend(
+                    attachments
+                        .iter()
+                        .map(|a| Line::from(format!("  {}", a.name))),
+                );
+            }
+        }
+        let page = Paragraph::new(text)
+            .block(Block::default().borders(Borders::ALL).title(Span::styled(
+                format!("#{} {}", item.id, item.title),
+                Style::default().add_modifier(Modifier::BOLD),
+            )))
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll, 0));
+        f.render_widget(page, area);
+    }
+}
+
+/// Split `area` in two, with `last` sizing the second part.
+fn split(area: Rect, direction: Direction, last: Constraint) -> [Rect; 2] {
+    let parts = Layout::default()
+        .direction(direction)
+        .constraints([Constraint::Min(0), last])
+        .split(area);
+    [parts[0], parts[1]]
+}
+
+fn highlight() -> Style {
+    Style::default().add_modifier(Modifier::REVERSED)
+}
+
+/// Edit an item as a Markdown file, leaving the terminal UI while the editor
+/// runs.
+fn edit_item(terminal: &mut Term, item: &Item) -> Result<Item, AppError> {
+    stop(terminal).map_err(io_error)?;
+    let edited = cli::edit_in_editor(&transfer::to_markdown(item), item.id);
+    enable_raw_mode().map_err(io_error)?;
+    crossterm::execute!(terminal.backend_mut(), EnterAlternateScreen).map_err(io_error)?;
+    terminal.clear().map_err(io_error)?;
+    transfer::from_markdown(&edited?)
+}
+
+/// Render Markdown as styled text for the terminal.
+pub fn render_markdown(src: &str) -> Text<'static> {
+    let mut renderer = Renderer::default();
+    for event in Parser::new_ext(src, markdown::options()) {
+        renderer.event(event);
+    }
+    renderer.flush();
+    while renderer.lines.last().is_some_and(|l| l.spans.is_empty()) {
+        renderer.lines.pop();
+    }
+    Text::from(renderer.lines)
+}
+
+#[derive(Default)]
+struct Renderer {
+    lines: Vec<Line<'static>>,
+    spans: Vec<Span<'static>>,
+    styles: Vec<Style>,
+    /// The next number of each (nested) list, or `None` for bullet lists
+    lists: Vec<Option<u64>>,
+    quotes: usize,
+    in_code_block: bool,
+}
+
+impl Renderer {
+    fn event(&mut self, event: MdEvent) {
+        match event {
+            MdEvent::Start(tag) => self.start(tag),
+            MdEvent::End(tag) => self.end(tag),
+            MdEvent::Text(text) if self.in_code_block => {
+                for line in text.lines() {
+                    self.text(line);
+                    self.flush();
+                }
+            }
+            MdEvent::Text(text) => self.text(&text),
+            MdEvent::Code(code) => {
+                self.styled(&code, self.style().fg(Color::Yellow));
+            }
+            MdEvent::Html(html) => self.styled(html.trim_end(), self.style().fg(Color::DarkGray)),
+            MdEvent::FootnoteReference(name) => self.text(&format!("[^{}]", name)),
+            MdEvent::SoftBreak => self.text(" "),
+            MdEvent::HardBreak => self.flush(),
+            MdEvent::Rule => {
+                self.flush();
+                self.lines.push(Line::from("─".repeat(20)));
+                self.blank();
+            }
+            MdEvent::TaskListMarker(done) => {
+                self.text(if done { "[x] " } else { "[ ] " });
+            }
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Heading(level, _, _) => {
+                self.flush();
+                let mut style = self.style().fg(Color::Cyan).add_modifier(Modifier::BOLD);
+                if level == pulldown_cmark::HeadingLevel::H1 {
+                    style = style.add_modifier(Modifier::UNDERLINED);
+                }
+                self.styles.push(style);
+            }
+            Tag::BlockQuote => {
+                self.flush();
+                self.quotes += 1;
+                self.styles
+                    .push(self.style().add_modifier(Modifier::ITALIC));
+            }
+            Tag::CodeBlock(_) => {
+                self.flush();
+                self.in_code_block = true;
+                self.styles.push(self.style().fg(Color::Yellow));
+            }
+            Tag::List(start) => {
+                self.flush();
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.flush();
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+                    _ => String::from("• "),
+                };
+                let depth = self.lists.len().saturating_sub(1);
+                self.spans.push(Span::raw(format!(
+                    "{}{}{}",
+                    "│ ".repeat(self.quotes),
+                    "  ".repeat(depth),
+                    bullet
+                )));
+            }
+            Tag::Emphasis => self
+                .styles
+                .push(self.style().add_modifier(Modifier::ITALIC)),
+            Tag::Strong => self.styles.push(self.style().add_modifier(Modifier::BOLD)),
+            Tag::Strikethrough => self
+                .styles
+                .push(self.style().add_modifier(Modifier::CROSSED_OUT)),
+            Tag::Link(..) => self.styles.push(
+                self.style()
+                    .fg(Color::Blue)
+                    .add_modifier(Modifier::UNDERLINED),
+            ),
+            Tag::Image(..) => {
+                self.text("[image: ");
+                self.styles
+                    .push(self.style().add_modifier(Modifier::ITALIC));
+            }
+            Tag::Paragraph
+            | Tag::FootnoteDefinition(_)
+            | Tag::Table(_)
+            | Tag::TableHead
+            | Tag::TableRow
+            | Tag::TableCell => {}
+        }
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::Heading(..) | Tag::CodeBlock(_) => {
+                self.styles.pop();
+                self.in_code_block = false;
+                self.blank();
+            }
+            Tag::BlockQuote => {
+                self.flush();
+                self.styles.pop();
+                self.quotes -= 1;
+                if self.quotes == 0 {
+                    self.blank();
+                }
+            }
+            Tag::Paragraph if self.lists.is_empty() => self.blank(),
+            Tag::Paragraph | Tag::Item | Tag::TableHead | Tag::TableRow => self.flush(),
+            Tag::List(_) => {
+                self.flush();
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.blank();
+                }
+            }
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) => {
+                self.styles.pop();
+            }
+            Tag::Image(..) => {
+                self.styles.pop();
+                self.text("]");
+            }
+            Tag::TableCell => self.text(" │ "),
+            Tag::Table(_) => self.blank(),
+            Tag::FootnoteDefinition(_) => {}
+        }
+    }
+
+    fn style(&self) -> Style {
+        self.styles.last().copied().unwrap_or_default()
+    }
+
+    fn text(&mut self, text: &str) {
+        self.styled(text, self.style());
+    }
+
+    fn styled(&mut self, text: &str, style: Style) {
+        if self.spans.is_empty() {
+            let indent = format!(
+                "{}{}",
+                "│ ".repeat(self.quotes),
+                "  ".repeat(self.lists.len())
+            );
+            if !indent.is_empty() {
+                self.spans.push(Span::raw(indent));
+            }
+        }
+        self.spans.push(Span::styled(text.to_owned(), style));
+    }
+
+    /// Finish the current line.
+    fn flush(&mut self) {
+        if !self.spans.is_empty() {
+            self.lines.push(Line::from(std::mem::take(&mut self.spans)));
+        }
+    }
+
+    /// Finish the current line and leave a blank line after it.
+    fn blank(&mut self) {
+        self.flush();
+        if self.lines.last().is_some_and(|l| !l.spans.is_empty()) {
+            self.lines.push(Line::default());
+        }
+    }
+}
+
+fn io_error(err: std::io::Error) -> AppError {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Terminal error: {:?}", err),
+    )
+}
+
+#[test]
+fn test_render_markdown() {
+    let text = render_markdown(
+        "# Trip\n\nPack *light*.\n\n- [x] Tickets\n- [ ] Passport\n  1. Photo\n\n> Bon voyage\n\n```\nls -l\n```\n",
+    );
+    let lines: Vec<String> = text
+        .lines
+        .iter()
+        .map(|l| l.spans.iter().map(|s| s.content.as_ref()).collect())
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "Trip",
+            "",
+            "Pack light.",
+            "",
+            "• [x] Tickets",
+            "• [ ] Passport",
+            "  1. Photo",
+            "",
+            "│ Bon voyage",
+            "",
+            "ls -l",
+        ]
+    );
+    let light = &text.lines[2].spans[1];
+    assert_eq!(light.content, "light");
+    assert!(light.style.add_modifier.contains(Modifier::ITALIC));
+    assert!(text.lines[0].spans[0]
+        .style
+        .add_modifier
+        .contains(Modifier::BOLD));
+}
@@ -0,0 +1,600 @@
+//! Options for how the home page shows items: which ones (filters), in what
+//! order, and grouped how. They're given as query parameters, e.g.
+//! `/?tag=ops&age=>7d&sort=due&group=tag`:
+//!
+//! * `flag`: `important`, `urgent` or `unflagged`
+//! * `tag`: a hashtag, with or without its `#`
+//! * `age`: `>7d` for items created more than a week ago, `<2w` for ones
+//!   created in the last two weeks (`d` for days, `w` for weeks)
+//! * `text`: words in the title or body, ignoring case
+//! * `sort`: `modified` (the default), `created`, `title`, `due` or `manual`
+//! * `group`: `quadrant` (the default), `tag`, `created-week` or `none`
+//! * `q`: a query, like `urgent AND tag:ops` (see the `query` module)
+//!
+//! The options last chosen are kept in a cookie, so the home page looks the
+//! same next time, and can be saved as named views (see `Repo::saved_views`).
+
+use crate::query::{Query, Term};
+use crate::repo::{Item, Quadrant};
+use crate::{markdown, AppError, StatusCode};
+use chrono::{Datelike, Duration, Local, NaiveDate, TimeZone};
+use rusqlite::types::Value;
+use std::str::FromStr;
+
+pub const VIEW_COOKIE: &str = "wannado_view";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Important,
+    Urgent,
+    Unflagged,
+}
+
+/// Items created more (or less) than `days` ago.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Age {
+    pub older: bool,
+    pub days: i64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Sort {
+    #[default]
+    Modified,
+    Created,
+    Title,
+    Due,
+    Manual,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Group {
+    #[default]
+    Quadrant,
+    Tag,
+    CreatedWeek,
+    None,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ViewOptions {
+    pub flag: Option<Flag>,
+    pub tag: Option<String>,
+    pub age: Option<Age>,
+    pub text: Option<String>,
+    pub sort: Sort,
+    pub group: Group,
+    /// A query, checked to be valid.
+    pub q: Option<String>,
+}
+
+/// View options as they appear in query strings. Empty values (from blank
+/// form fields) are ignored.
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+pub struct ViewParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+}
+
+impl ViewParams {
+    /// Whether any options were given at all.
+    pub fn is_empty(&self) -> bool {
+        [
+            &self.flag,
+            &self.tag,
+            &self.age,
+            &self.text,
+            &self.sort,
+            &self.group,
+            &self.q,
+        ]
+        .iter()
+        .all(|p| p.is_none())
+    }
+}
+
+impl ViewOptions {
+    pub fn from_params(params: &ViewParams) -> Result<Self, AppError> {
+        fn given(param: &Option<String>) -> Option<&str> {
+            param.as_deref().map(str::trim).filter(|p| !p.is_empty())
+        }
+        Ok(ViewOptions {
+            flag: given(&params.flag).map(str::parse).transpose()?,
+            tag: given(&params.tag).map(|t| t.trim_start_matches('#').to_lowercase()),
+            age: given(&params.age).map(str::parse).transpose()?,
+            text: given(&params.text).map(str::to_owned),
+            sort: given(&params.sort)
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or_default(),
+            group: given(&params.group)
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or_default(),
+            q: given(&params.q)
+                .map(|q| q.parse::<Query>().map(|_| q.to_owned()))
+                .transpose()?,
+        })
+    }
+
+    /// The options as query parameters, leaving out the defaults.
+    pub fn to_params(&self) -> ViewParams {
+        ViewParams {
+            flag: self.flag.map(|f| f.to_string()),
+            tag: self.tag.clone(),
+            age: self.age.map(|a| a.to_string()),
+            text: self.text.clone(),
+            sort: (self.sort != Sort::default()).then(|| self.sort.to_string()),
+            group: (self.group != Group::default()).then(|| self.group.to_string()),
+            q: self.q.clone(),
+        }
+    }
+
+    /// The options as a query string (without the `?`), which is also what's
+    /// kept in the cookie and saved views.
+    pub fn query(&self) -> String {
+        serde_urlencoded::to_string(self.to_params()).unwrap_or_default()
+    }
+
+    /// Read the options kept in the cookie, ignoring them if they're invalid.
+    pub fn from_query(query: &str) -> Option<Self> {
+        serde_urlencoded::from_str(query)
+            .ok()
+            .and_then(|params| ViewOptions::from_params(&params).ok())
+    }
+
+    /// The filters and the query combined into one query, so that the items
+    /// they show can be counted in SQL. It's `None` if there aren't any.
+    pub fn filter_query(&self) -> Result<Option<Query>, AppError> {
+        let not = |term| Query::Not(Box::new(Query::Term(term)));
+        let mut parts = Vec::new();
+        match self.flag {
+            Some(Flag::Important) => parts.push(Query::Term(Term::Important)),
+            Some(Flag::Urgent) => parts.push(Query::Term(Term::Urgent)),
+            Some(Flag::Unflagged) => parts.extend([not(Term::Important), not(Term::Urgent)]),
+            None => {}
+        }
+        if let Some(tag) = &self.tag {
+            parts.push(Query::Term(Term::Tag(tag.clone())));
+        }
+        if let Some(age) = self.age {
+            parts.push(Query::Term(Term::Age(age)));
+        }
+        if let Some(text) = &self.text {
+            parts.push(Query::Term(Term::Text(text.clone())));
+        }
+        if let Some(q) = &self.q {
+            parts.push(q.parse()?);
+        }
+        Ok(parts
+            .into_iter()
+            .reduce(|a, b| Query::And(Box::new(a), Box::new(b))))
+    }
+
+    /// SQL for the order items are shown in, for `Repo::view_page`:
+    /// expressions over the items table to sort by, none of them NULL so that
+    /// they can be compared with the keys of the last item on a page, and
+    /// whether to join each item's tags (as `tag`) to group them by.
+    pub fn order_sql(&self) -> (Vec<&'static str>, bool) {
+        const CREATED: &str = "json_extract(item, '$.created')";
+        let mut keys = match self.group {
+            Group::Quadrant => vec![
+                "2 * NOT coalesce(json_extract(item, '$.important'), 0) \
+                 + NOT coalesce(json_extract(item, '$.urgent'), 0)",
+            ],
+            // Untagged items go last
+            Group::Tag => vec!["tag.value IS NULL", "coalesce(tag.value, '')"],
+            // Newest first, by the Monday of the week (see `week_of`)
+            Group::CreatedWeek => vec![
+                "-strftime('%s', json_extract(item, '$.created'), 'unixepoch', 'localtime', \
+                 'start of day', 'weekday 0', '-6 days')",
+            ],
+            Group::None => Vec::new(),
+        };
+        match self.sort {
+            // Items that have never been modified go first
+            Sort::Modified => keys.extend([
+                "json_extract(item, '$.modified') IS NOT NULL",
+                "coalesce(json_extract(item, '$.modified'), 0)",
+                CREATED,
+            ]),
+            Sort::Created => keys.push(CREATED),
+            Sort::Title => keys.push("wannado_lower(json_extract(item, '$.title'))"),
+            // Items without a due date go last
+            Sort::Due => keys.extend([
+                "wannado_due(json_extract(item, '$.body')) IS NULL",
+                "coalesce(wannado_due(json_extract(item, '$.body')), '')",
+                CREATED,
+            ]),
+            // Items without a position go after the ones with, oldest first
+            Sort::Manual => keys.extend([
+                "(SELECT position FROM item_positions WHERE item = items.rowid) IS NULL",
+                "coalesce((SELECT position FROM item_positions WHERE item = items.rowid), 0)",
+                CREATED,
+            ]),
+        }
+        (keys, self.group == Group::Tag)
+    }
+
+    /// Split sorted items into groups with headings, keeping their order.
+    /// Grouping by quadrant always gives all four groups, even empty ones;
+    /// other groupings only give non-empty ones. Items with several tags are
+    /// in the group for each of them.
+    pub fn group<'a>(&self, items: &[&'a Item]) -> Vec<(String, Vec<&'a Item>)> {
+        match self.group {
+            Group::Quadrant => [
+                ("Important and Urgent", Quadrant::ImportantAndUrgent),
+                ("Important", Quadrant::Important),
+                ("Urgent", Quadrant::Urgent),
+                ("Other", Quadrant::Other),
+            ]
+            .into_iter()
+            .map(|(heading, quadrant)| {
+                let items = items
+                    .iter()
+                    .filter(|i| i.quadrant() == quadrant)
+                    .copied()
+                    .collect();
+                (heading.to_owned(), items)
+            })
+            .collect(),
+            Group::Tag => {
+                let mut groups: Vec<(String, Vec<&Item>)> = Vec::new();
+                let mut untagged = Vec::new();
+                for &item in items {
+                    let tags = markdown::tags(&item.body);
+                    if tags.is_empty() {
+                        untagged.push(item);
+                    }
+                    for tag in tags {
+                        let heading = format!("#{}", tag);
+                        match groups.iter_mut().find(|(h, _)| *h == heading) {
+                            Some((_, group)) => group.push(item),
+                            None => groups.push((heading, vec![item])),
+                        }
+                    }
+                }
+                groups.sort_by(|(a, _), (b, _)| a.cmp(b));
+                if !untagged.is_empty() {
+                    groups.push((String::from("Untagged"), untagged));
+                }
+                groups
+            }
+            Group::CreatedWeek => {
+                let mut weeks: Vec<(NaiveDate, Vec<&Item>)> = Vec::new();
+                for &item in items {
+                    let week = week_of(item.created);
+                    match weeks.iter_mut().find(|(w, _)| *w == week) {
+                        Some((_, group)) => group.push(item),
+                        None => weeks.push((week, vec![item])),
+                    }
+                }
+                // Newest first
+                weeks.sort_by(|(a, _), (b, _)| b.cmp(a));
+                weeks
+                    .into_iter()
+                    .map(|(week, items)| (format!("Week of {}", week.format("%Y-%m-%d")), items))
+                    .collect()
+            }
+            Group::None => vec![(String::new(), items.to_vec())],
+        }
+    }
+
+    /// Group a page of items from `Repo::view_page`, like `group`. When
+    /// grouping by tag, items with several tags are on the page once for each,
+    /// with the tag in their keys.
+    pub fn group_page<'a>(&self, page: &'a [(Vec<Value>, Item)]) -> Vec<(String, Vec<&'a Item>)> {
+        if self.group != Group::Tag {
+            let items: Vec<&Item> = page.iter().map(|(_, item)| item).collect();
+            return self.group(&items);
+        }
+        let mut groups: Vec<(String, Vec<&Item>)> = Vec::new();
+        for (keys, item) in page {
+            let heading = match keys.get(1) {
+                Some(Value::Text(tag)) if !tag.is_empty() => format!("#{}", tag),
+                _ => String::from("Untagged"),
+            };
+            match groups.last_mut() {
+                Some((last, items)) if *last == heading => items.push(item),
+                _ => groups.push((heading, vec![item])),
+            }
+        }
+        groups
+    }
+}
+
+/// The keys of the last item on a page (from `Repo::view_page`), for the
+/// link to the next page.
+pub fn cursor(keys: &[Value]) -> String {
+    let keys: Vec<serde_json::Value> = keys
+        .iter()
+        .map(|key| match key {
+            Value::Integer(n) => (*n).into(),
+            Value::Real(n) => (*n).into(),
+            Value::Text(s) => s.as_str().into(),
+            Value::Null | Value::Blob(_) => serde_json::Value::Null,
+        })
+        .collect();
+    serde_json::Value::from(keys).to_string()
+}
+
+pub fn parse_cursor(src: &str) -> Result<Vec<Value>, AppError> {
+    let error = || invalid("page", src, "a link from the previous page");
+    let keys: Vec<serde_json::Value> = serde_json::from_str(src).map_err(|_| error())?;
+    keys.into_iter()
+        .map(|key| match key {
+            serde_json::Value::Number(n) => n
+                .as_i64()
+                .map(Value::Integer)
+                .or_else(|| n.as_f64().map(Value::Real))
+                .ok_or_else(error),
+            serde_json::Value::String(s) => Ok(Value::Text(s)),
+            _ => Err(error()),
+        })
+        .collect()
+}
+
+/// The Monday of the week (in local time) that `ts` is in.
+pub fn week_of(ts: i64) -> NaiveDate {
+    let date = match Local.timestamp_opt(ts, 0).single() {
+        Some(time) => time.naive_local().date(),
+        None => NaiveDate::from_ymd(1970, 1, 1),
+    };
+    date - Duration::days(date.weekday().num_days_from_monday().into())
+}
+
+fn invalid(what: &str, value: &str, expected: &str) -> AppError {
+    (
+        StatusCode::BAD_REQUEST,
+        format!("Unknown {} '{}', expected {}", what, value, expected),
+    )
+}
+
+impl FromStr for Flag {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "important" => Ok(Flag::Important),
+            "urgent" => Ok(Flag::Urgent),
+            "unflagged" => Ok(Flag::Unflagged),
+            _ => Err(invalid("flag", s, "important, urgent or unflagged")),
+        }
+    }
+}
+
+impl std::fmt::Display for Flag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Flag::Important => "important",
+            Flag::Urgent => "urgent",
+            Flag::Unflagged => "unflagged",
+        })
+    }
+}
+
+impl FromStr for Age {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || invalid("age", s, "e.g. >7d or <2w");
+        let (older, rest) = if let Some(rest) = s.strip_prefix('>') {
+            (true, rest)
+        } else if let Some(rest) = s.strip_prefix('<') {
+            (false, rest)
+        } else {
+            return Err(error());
+        };
+        let (number, days_per_unit) = match rest.trim() {
+            r if r.ends_with('d') => (&r[..r.len() - 1], 1),
+            r if r.ends_with('w') => (&r[..r.len() - 1], 7),
+            _ => return Err(error()),
+        };
+        let number: i64 = number.parse().map_err(|_| error())?;
+        if !(0..=100_000).contains(&number) {
+            return Err(error());
+        }
+        Ok(Age {
+            older,
+            days: number * days_per_unit,
+        })
+    }
+}
+
+impl std::fmt::Display for Age {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.older { '>' } else { '<' };
+        if self.days % 7 == 0 && self.days > 0 {
+            write!(f, "{}{}w", sign, self.days / 7)
+        } else {
+            write!(f, "{}{}d", sign, self.days)
+        }
+    }
+}
+
+impl FromStr for Sort {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "modified" => Ok(Sort::Modified),
+            "created" => Ok(Sort::Created),
+            "title" => Ok(Sort::Title),
+            "due" => Ok(Sort::Due),
+            "manual" => Ok(Sort::Manual),
+            _ => Err(invalid(
+                "sort",
+                s,
+                "modified, created, title, due or manual",
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Sort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Sort::Modified => "modified",
+            Sort::Created => "created",
+            Sort::Title => "title",
+            Sort::Due => "due",
+            Sort::Manual => "manual",
+        })
+    }
+}
+
+impl FromStr for Group {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quadrant" => Ok(Group::Quadrant),
+            "tag" => Ok(Group::Tag),
+            "created-week" => Ok(Group::CreatedWeek),
+            "none" => Ok(Group::None),
+            _ => Err(invalid(
+                "grouping",
+                s,
+                "quadrant, tag, created-week or none",
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Group {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Group::Quadrant => "quadrant",
+            Group::Tag => "tag",
+            Group::CreatedWeek => "created-week",
+            Group::None => "none",
+        })
+    }
+}
+
+#[test]
+fn test_view_options() {
+    let params: ViewParams =
+        serde_urlencoded::from_str("flag=important&tag=%23Ops&age=%3E7d&text=&sort=due&group=none")
+            .unwrap();
+    let options = ViewOptions::from_params(&params).unwrap();
+    assert_eq!(options.tag.as_deref(), Some("ops"));
+    assert_eq!(
+        options.age,
+        Some(Age {
+            older: true,
+            days: 7
+        })
+    );
+    assert_eq!(options.text, None);
+    assert_eq!(
+        options.query(),
+        "flag=important&tag=ops&age=%3E1w&sort=due&group=none"
+    );
+    assert_eq!(ViewOptions::from_query(&options.query()), Some(options));
+    assert_eq!(ViewOptions::default().query(), "");
+    assert_eq!(ViewOptions::from_query("sort=sideways"), None);
+    assert_eq!(ViewOptions::from_query("q=urgent+AND+%28"), None);
+    let options = ViewOptions::from_query("q=urgent+AND+%23ops").unwrap();
+    assert_eq!(options.q.as_deref(), Some("urgent AND #ops"));
+    assert_eq!(options.query(), "q=urgent+AND+%23ops");
+    for bad in ["7d", ">d", ">7y", "<-1d", "", "é7d", ">7é"] {
+        assert!(bad.parse::<Age>().is_err(), "{}", bad);
+    }
+
+    use crate::repo::{Repo, DEFAULT_LIST};
+    let mut repo = Repo::new(rusqlite::Connection::open_in_memory().unwrap());
+    repo.init().unwrap();
+    let day = 24 * 60 * 60;
+    let now = Local::now().timestamp();
+    for (title, body, important, age) in [
+        ("Write report", "#work due: 2022-11-03", true, 10),
+        ("buy milk", "#home #errands", false, 1),
+        ("Call plumber", "#home due: 2022-11-01", true, 2),
+    ] {
+        let mut item = repo
+            .add(DEFAULT_LIST, title, body, important, false, None)
+            .unwrap();
+        item.created = now - age * day;
+        repo.put(&item).unwrap();
+    }
+    let page = |repo: &Repo, query: &str, after: Option<&[Value]>, limit| {
+        let options = ViewOptions::from_query(query).unwrap();
+        repo.view_page(DEFAULT_LIST, &options, None, after, limit)
+            .unwrap()
+    };
+    let order = |repo: &Repo, query: &str| -> Vec<u32> {
+        page(repo, query, None, 10)
+            .iter()
+            .map(|(_, i)| i.id)
+            .collect()
+    };
+
+    let only = |query: &str| -> Vec<u32> {
+        let mut ids = order(&repo, query);
+        ids.sort_unstable();
+        ids
+    };
+    assert_eq!(only("flag=important"), [1, 3]);
+    assert_eq!(only("flag=unflagged"), [2]);
+    assert_eq!(only("tag=home"), [2, 3]);
+    assert_eq!(only("age=%3E1w"), [1]);
+    assert_eq!(only("age=%3C3d"), [2, 3]);
+    assert_eq!(only("text=MILK"), [2]);
+    assert_eq!(only("tag=home&flag=important"), [3]);
+
+    assert_eq!(order(&repo, "sort=created&group=none"), [1, 3, 2]);
+    assert_eq!(order(&repo, "sort=title&group=none"), [2, 3, 1]);
+    assert_eq!(order(&repo, "sort=due&group=none"), [3, 1, 2]);
+    repo.reorder(&[2, 1]).unwrap();
+    assert_eq!(order(&repo, "sort=manual&group=none"), [2, 1, 3]);
+
+    let headings = |query: &str| -> Vec<(String, Vec<u32>)> {
+        let options = ViewOptions::from_query(query).unwrap();
+        options
+            .group_page(&page(&repo, query, None, 10))
+            .into_iter()
+            .map(|(heading, items)| (heading, items.iter().map(|i| i.id).collect()))
+            .collect()
+    };
+    let quadrants = headings("");
+    assert_eq!(quadrants.len(), 4);
+    assert_eq!(quadrants[1], (String::from("Important"), vec![1, 3]));
+    let tags = headings("group=tag");
+    let tag_names: Vec<&str> = tags.iter().map(|(h, _)| h.as_str()).collect();
+    assert_eq!(tag_names, ["#errands", "#home", "#work"]);
+    assert_eq!(tags[1].1, [3, 2]);
+    assert_eq!(headings("group=none"), [(String::new(), vec![1, 3, 2])]);
+    assert!(headings("group=created-week")
+        .iter()
+        .all(|(h, _)| h.starts_with("Week of ")));
+
+    // Following the links from page to page, an item at a time
+    for (query, expected) in [("", vec![1, 3, 2]), ("group=tag", vec![2, 3, 2, 1])] {
+        let mut ids = Vec::new();
+        let mut after = None;
+        while let Some((keys, item)) = page(&repo, query, after.as_deref(), 1).pop() {
+            ids.push(item.id);
+            after = Some(parse_cursor(&cursor(&keys)).unwrap());
+        }
+        assert_eq!(ids, expected, "{}", query);
+    }
+    let options = ViewOptions::default();
+    let wrong = [Value::Integer(1)];
+    assert!(repo
+        .view_page(DEFAULT_LIST, &options, None, Some(&wrong), 1)
+        .is_err());
+    assert!(parse_cursor("[1, [2]]").is_err());
+}
@@ -56,3 +56,11 @@ function deletedItems() {
 hotkey('h', goHome);
 hotkey('n', newItem);
 hotkey('d', deletedItems);
+
+// Buttons with a `data-confirm` message ask before submitting their form.
+document.body.addEventListener('click', (evt) => {
+    const button = evt.target.closest('[data-confirm]');
+    if (button && !confirm(button.dataset.confirm)) {
+        evt.preventDefault();
+    }
+});
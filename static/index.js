@@ -6,3 +6,40 @@ function editFocusedItem() {
 }
 
 hotkey('e', editFocusedItem)
+
+// Switch between everyone's items and one person's
+document.querySelectorAll('nav.views a[data-hotkey]').forEach(link => {
+    hotkey(link.dataset.hotkey, () => goto(link.href));
+});
+
+// When sorted manually, J and K move the focused item down and up within its
+// group, and the new order is saved
+const reorderable = document.querySelector('.item-list[data-reorder]');
+
+async function moveFocusedItem(down) {
+    const focused = reorderable?.querySelector('.focused-item');
+    const li = focused?.closest('li');
+    const sibling = down ? li?.nextElementSibling : li?.previousElementSibling;
+    if (sibling == null) {
+        return;
+    }
+    if (down) {
+        sibling.after(li);
+    } else {
+        sibling.before(li);
+    }
+    ensureVisible(focused);
+    const ids = Array.from(li.parentElement.querySelectorAll('.item-control'), item => item.dataset.id);
+    const response = await fetch(reorderable.dataset.reorder, {
+        method: 'POST',
+        body: new URLSearchParams({ csrf: reorderable.dataset.csrf, ids: ids.join(',') }),
+    });
+    if (!response.ok) {
+        alert(`Couldn't save the new order: ${await response.text()}`);
+    }
+}
+
+if (reorderable != null) {
+    hotkey('J', () => moveFocusedItem(true));
+    hotkey('K', () => moveFocusedItem(false));
+}
@@ -12,14 +12,18 @@ function editFocusedItem() {
     }
 }
 
-function focusNextItem() {
-    const items = Array.from(document.getElementsByClassName('item-control'));
+async function focusNextItem() {
+    let items = Array.from(document.getElementsByClassName('item-control'));
     const focused = document.querySelector('.focused-item');
     if (focused == undefined) {
         document.querySelector('.item-control').classList.add('focused-item');
         return
     }
-    const focusedItemIndex = items.indexOf(focused);
+    let focusedItemIndex = items.indexOf(focused);
+    if (focusedItemIndex == items.length - 1 && await loadNextPage()) {
+        items = Array.from(document.getElementsByClassName('item-control'));
+        focusedItemIndex = items.indexOf(focused);
+    }
     const next = items[focusedItemIndex + 1];
     if (next != undefined) {
         setFocusedItem(next);
@@ -32,6 +36,9 @@ function setFocusedItem(item) {
     })
     item.classList.add('focused-item')
     ensureVisible(item)
+    if (item.dataset.id) {
+        sessionStorage.setItem(focusKey, item.dataset.id);
+    }
 }
 
 function ensureVisible(item) {
@@ -65,12 +72,90 @@ hotkey('Enter', gotoFocusedItem);
 
 // TODO: Edit focused item?
 
-document.querySelector('.item-control')?.classList.add('focused-item');
+// Long lists come a page at a time, and the next page is loaded when its
+// "More items" link scrolls into view (or J goes past the last item). Items
+// are added to the group with the same heading as on the page so far.
+let loading = null;
+
+function loadNextPage() {
+    const link = document.querySelector('.item-list a.next-page');
+    if (link == null) {
+        return Promise.resolve(false);
+    }
+    loading ??= fetchNextPage(link).finally(() => loading = null);
+    return loading;
+}
 
-document.querySelectorAll('.item-control')?.forEach(item => {
-    item.addEventListener("click", (evt) => {
-        if (evt.target?.classList?.contains("item-control")) {
-            setFocusedItem(evt.target);
+async function fetchNextPage(link) {
+    const response = await fetch(link.href);
+    if (!response.ok) {
+        console.warn("Couldn't load more items", await response.text());
+        return false;
+    }
+    const page = new DOMParser().parseFromString(await response.text(), 'text/html');
+    const list = link.closest('.item-list');
+    for (const ul of page.querySelectorAll('.item-list ul')) {
+        const heading = ul.previousElementSibling?.matches('h1') ? ul.previousElementSibling : null;
+        const existing = Array.from(list.querySelectorAll('h1'))
+            .find(h1 => h1.textContent == heading?.textContent);
+        const lists = list.querySelectorAll('ul');
+        if (existing != null && existing.nextElementSibling?.matches('ul')) {
+            existing.nextElementSibling.append(...ul.children);
+        } else if (heading == null && lists.length > 0) {
+            lists[lists.length - 1].append(...ul.children);
+        } else {
+            if (heading != null) {
+                link.before(heading);
+            }
+            link.before(ul);
         }
-    });
-})
\ No newline at end of file
+    }
+    const next = page.querySelector('.item-list a.next-page');
+    if (next != null) {
+        link.href = next.href;
+        // Keep checking in case the new items didn't fill the screen
+        observer.unobserve(link);
+        observer.observe(link);
+    } else {
+        link.remove();
+    }
+    return true;
+}
+
+const observer = new IntersectionObserver(entries => {
+    if (entries.some(entry => entry.isIntersecting)) {
+        loadNextPage();
+    }
+});
+document.querySelectorAll('.item-list a.next-page').forEach(link => observer.observe(link));
+
+// The focused item is remembered for this page, so that coming back to it
+// (or reloading it) keeps the selection
+const focusKey = `focused-item:${location.pathname}`;
+
+async function restoreFocusedItem() {
+    document.querySelector('.item-control')?.classList.add('focused-item');
+    const id = sessionStorage.getItem(focusKey);
+    if (id == null) {
+        return;
+    }
+    const selector = `.item-control[data-id="${CSS.escape(id)}"]`;
+    // It might be a few pages in
+    for (let pages = 0; document.querySelector(selector) == null && pages < 10; pages++) {
+        if (!await loadNextPage()) {
+            break;
+        }
+    }
+    const item = document.querySelector(selector);
+    if (item != null) {
+        setFocusedItem(item);
+    }
+}
+
+restoreFocusedItem();
+
+document.querySelector('.item-list')?.addEventListener("click", (evt) => {
+    if (evt.target?.classList?.contains("item-control")) {
+        setFocusedItem(evt.target);
+    }
+});
\ No newline at end of file
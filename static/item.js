@@ -13,4 +13,24 @@ function deleteItem() {
 }
 
 hotkey('e', editItem);
-hotkey('x', deleteItem);
\ No newline at end of file
+hotkey('x', deleteItem);
+
+function toggleTask(evt) {
+    const checkbox = evt.target;
+    const itemId = checkbox.closest('.item-body').dataset.item;
+    const body = new URLSearchParams({ offset: checkbox.dataset.offset });
+    const csrf = document.querySelector('input[name=csrf]')?.value ?? '';
+    const headers = { 'X-CSRF-Token': csrf };
+    fetch(`/item/${itemId}/task`, { method: 'POST', body, headers }).then(response => {
+        if (!response.ok) {
+            throw new Error(`${response.status} ${response.statusText}`);
+        }
+    }).catch(err => {
+        checkbox.checked = !checkbox.checked;
+        window.alert(`Couldn't update the task: ${err.message}`);
+    });
+}
+
+document.querySelectorAll('.item-body input.task').forEach(checkbox => {
+    checkbox.addEventListener('change', toggleTask);
+});